@@ -1,31 +1,316 @@
+use crate::data::{Phase, Transform};
 use crate::nodes;
-use crate::nodes::{NodeConfig, NodeVec};
+use crate::nodes::jq::Jq;
+use crate::nodes::{Node, NodeConfig, NodeVec};
+use crate::payload::{Compress, Payload};
 use crate::DependencyGraph;
 use derivative::Derivative;
 use serde::de::{Error, MapAccess, Visitor};
 use serde::{Deserialize, Deserializer};
 use serde_json::Value;
-use serde_json_wasm::de;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
+#[cfg(test)]
+use std::collections::BTreeSet;
 use std::fmt::{self, Formatter};
-
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Node runs allowed per request unless `max_node_runs` is given. Each node
+/// runs at most once per phase, so regular configurations stay well below it.
+pub const DEFAULT_MAX_NODE_RUNS: usize = 10_000;
+
+/// A node provided by the host rather than by the configuration, through
+/// which data enters and leaves the graph. The host decides the set of
+/// implicit nodes, such as the request and response of an HTTP filter.
+///
+/// The data of an implicit node is set and read by port position, so a port
+/// that is both an input and an output must be at the same position in both.
 pub struct ImplicitNode {
     name: String,
     inputs: Vec<String>,
     outputs: Vec<String>,
+    /// The outputs that the host sets and the inputs that it reads back
+    /// when processing a phase.
+    phase_ports: Vec<(Phase, Vec<String>, Vec<String>)>,
 }
 
 impl ImplicitNode {
     pub fn new(name: &str, inputs: Vec<String>, outputs: Vec<String>) -> ImplicitNode {
+        debug_assert!(
+            inputs
+                .iter()
+                .enumerate()
+                .all(|(i, port)| !outputs.contains(port) || outputs.get(i) == Some(port)),
+            "implicit node {name}: shared ports must be at the same position"
+        );
         ImplicitNode {
             name: name.into(),
             inputs,
             outputs,
+            phase_ports: vec![],
+        }
+    }
+
+    /// Declare the ports that the host processes in a phase: the outputs
+    /// that it sets, for nodes to read, and the inputs that it reads back,
+    /// as written by nodes. `Config::phase_has_work` relies on these to
+    /// tell whether a phase other than those of the request headers, the
+    /// call responses and the ticks involves any work.
+    pub fn in_phase(mut self, phase: Phase, outputs: &[&str], inputs: &[&str]) -> ImplicitNode {
+        debug_assert!(
+            outputs
+                .iter()
+                .all(|port| self.outputs.iter().any(|p| p == port))
+                && inputs
+                    .iter()
+                    .all(|port| self.inputs.iter().any(|p| p == port)),
+            "implicit node {}: unknown port in phase {}",
+            self.name,
+            phase.name()
+        );
+        let names =
+            |ports: &[&str]| -> Vec<String> { ports.iter().map(|p| p.to_string()).collect() };
+        self.phase_ports
+            .push((phase, names(outputs), names(inputs)));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The position of a port, whether it is an input, an output or both.
+    pub fn port(&self, port: &str) -> Option<usize> {
+        let find = |ports: &[String]| ports.iter().position(|p| p == port);
+        find(&self.outputs).or_else(|| find(&self.inputs))
+    }
+}
+
+/// The positions of an implicit node and of one of its ports, by name.
+pub fn find_implicit_port(
+    implicits: &[ImplicitNode],
+    node: &str,
+    port: &str,
+) -> Option<(usize, usize)> {
+    let n = implicits.iter().position(|inode| inode.name == node)?;
+    Some((n, implicits[n].port(port)?))
+}
+
+/// An error found when parsing or checking a configuration.
+#[derive(PartialEq, Debug)]
+pub enum ConfigError {
+    /// The configuration is not valid JSON, or does not have the expected shape.
+    ParseError(String),
+    /// An error in the definition of a specific node.
+    InNode {
+        name: String,
+        node_type: String,
+        error: Box<ConfigError>,
+    },
+    /// The node configuration was rejected by its node type.
+    NodeConfig(String),
+    ReservedName,
+    UnknownNodeType,
+    DuplicateNode(String),
+    UnknownNode(String),
+    UnknownLinkEndpoints {
+        from: String,
+        to: String,
+    },
+    BadLink(String),
+    SelfLoop,
+    InvalidPortName {
+        kind: &'static str,
+        node: String,
+        port: String,
+    },
+    InvalidPortIndex {
+        kind: &'static str,
+        node: String,
+        port: String,
+        n_ports: usize,
+    },
+    NoOutputPorts,
+    DuplicateInputPort(String),
+    TooManyInputs(usize),
+    UnresolvedPortName,
+    AlreadyConnected {
+        node: String,
+        port: String,
+        other_node: String,
+        other_port: String,
+    },
+    ContendingInputs {
+        node: String,
+        port: String,
+        first: String,
+        second: String,
+    },
+    InvalidTickPeriod,
+    InvalidWhen(String),
+    InvalidPhases(String),
+    InvalidTransforms(String),
+    TickWithInputs(String),
+    EmptyExit(String),
+    SinkToImplicit {
+        node: String,
+        target: String,
+    },
+    InvalidDeadline,
+    InvalidMaxBodyBytes,
+    InvalidMaxNodeRuns,
+    InvalidCompress(String),
+    InvalidJqPrelude(String),
+    ReservedAlias(String),
+    InvalidAlias {
+        alias: String,
+        target: String,
+    },
+    UnknownDebugNode(String),
+    /// Only reported by `validate`.
+    #[cfg(test)]
+    Cycle(Vec<String>),
+}
+
+impl ConfigError {
+    fn in_node(desc: &UserNodeDesc, error: ConfigError) -> ConfigError {
+        ConfigError::InNode {
+            name: desc.name.clone(),
+            node_type: desc.node_type.clone(),
+            error: Box::new(error),
+        }
+    }
+
+    /// The message without the stage at which the error was found.
+    pub fn detail(&self) -> impl fmt::Display + '_ {
+        struct Detail<'a>(&'a ConfigError);
+
+        impl fmt::Display for Detail<'_> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                self.0.fmt_detail(f)
+            }
+        }
+
+        Detail(self)
+    }
+
+    fn fmt_detail(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use ConfigError::*;
+        match self {
+            ParseError(e) | NodeConfig(e) => f.write_str(e),
+            InNode {
+                name,
+                node_type,
+                error,
+            } => {
+                write!(f, "in node `{name}` of type `{node_type}`: ")?;
+                error.fmt_detail(f)
+            }
+            ReservedName => f.write_str("cannot use reserved node name"),
+            UnknownNodeType => f.write_str("unknown node type"),
+            DuplicateNode(name) => write!(f, "multiple definitions of node `{name}`"),
+            UnknownNode(np) => write!(f, "unknown node in link: {np}"),
+            UnknownLinkEndpoints { from, to } => {
+                write!(f, "link {from} -> {to} does not refer to any given node")
+            }
+            BadLink(name) => write!(f, "bad link definition in node {name}"),
+            SelfLoop => f.write_str("node cannot connect to itself"),
+            InvalidPortName { kind, node, port } => {
+                write!(f, "invalid {kind} port name {node}.{port}")
+            }
+            InvalidPortIndex {
+                kind,
+                node,
+                port,
+                n_ports,
+            } => write!(
+                f,
+                "invalid {kind} port index {node}.{port} (node has {n_ports} {kind} ports)"
+            ),
+            NoOutputPorts => f.write_str("node in link has no output ports"),
+            DuplicateInputPort(port) => write!(f, "duplicated input port {port}"),
+            TooManyInputs(n) => write!(
+                f,
+                "too many inputs declared (node type supports {n} inputs)"
+            ),
+            UnresolvedPortName => f.write_str("could not resolve a name"),
+            AlreadyConnected {
+                node,
+                port,
+                other_node,
+                other_port,
+            } => write!(
+                f,
+                "{node}.{port} is already connected to {other_node}.{other_port}"
+            ),
+            ContendingInputs {
+                node,
+                port,
+                first,
+                second,
+            } => write!(
+                f,
+                "nodes `{first}` and `{second}` are both connected to \
+                 `{node}.{port}`, which can only take one input"
+            ),
+            InvalidTickPeriod => f.write_str("`tick_period_ms` must be a positive integer"),
+            InvalidWhen(e) => write!(f, "invalid `when`: {e}"),
+            InvalidPhases(e) => write!(f, "invalid `phases`: {e}"),
+            InvalidTransforms(e) => write!(f, "invalid `transforms`: {e}"),
+            TickWithInputs(name) => write!(
+                f,
+                "node `{name}` with `tick_period_ms` cannot have connected inputs"
+            ),
+            EmptyExit(name) => write!(
+                f,
+                "node `{name}` of type `exit` has no connected inputs \
+                 and would produce an empty response"
+            ),
+            SinkToImplicit { node, target } => write!(
+                f,
+                "node `{node}` only performs a side effect and produces no data, \
+                 but is connected to `{target}`"
+            ),
+            InvalidDeadline => f.write_str("`deadline_ms` must be a positive integer"),
+            InvalidMaxBodyBytes => f.write_str("`max_body_bytes` must be a positive integer"),
+            InvalidMaxNodeRuns => f.write_str("`max_node_runs` must be a positive integer"),
+            InvalidCompress(value) => write!(
+                f,
+                "invalid `compress` value `{value}`, expected auto, gzip or none"
+            ),
+            InvalidJqPrelude(e) => f.write_str(e),
+            ReservedAlias(alias) => write!(f, "alias `{alias}` is the name of an implicit node"),
+            InvalidAlias { alias, target } => write!(
+                f,
+                "alias `{alias}` refers to `{target}`, which is not an implicit node"
+            ),
+            UnknownDebugNode(name) => write!(f, "debug filter refers to unknown node `{name}`"),
+            #[cfg(test)]
+            Cycle(names) => {
+                let names: Vec<String> = names.iter().map(|name| format!("`{name}`")).collect();
+                write!(
+                    f,
+                    "nodes {} are in a cycle or depend on one, so they can never run",
+                    names.join(", ")
+                )
+            }
         }
     }
 }
 
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::ParseError(_) => f.write_str("failed parsing configuration: ")?,
+            _ => f.write_str("failed checking configuration: ")?,
+        }
+        self.fmt_detail(f)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 #[derive(PartialEq, Debug)]
 struct UserNodePort {
     node: Option<String>,
@@ -114,11 +399,39 @@ impl UserLink {
         }
     }
 
+    /// Resolve a port given by its position (e.g. the `1` in `mycall.1`)
+    /// into the name of the port at that index. Names take precedence,
+    /// so a port which is literally named with digits is kept as is.
+    fn resolve_port_index(
+        port: &mut String,
+        ports: &[String],
+        node: &str,
+        kind: &'static str,
+    ) -> Result<(), ConfigError> {
+        if ports.contains(port) || port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(());
+        }
+
+        let index: usize = port.parse().unwrap_or(usize::MAX);
+        match ports.get(index) {
+            Some(name) => {
+                *port = name.clone();
+                Ok(())
+            }
+            None => Err(ConfigError::InvalidPortIndex {
+                kind,
+                node: node.into(),
+                port: port.clone(),
+                n_ports: ports.len(),
+            }),
+        }
+    }
+
     fn get_or_create_output(
         np: &UserNodePort,
         outs: &mut Vec<String>,
         user: bool,
-    ) -> Result<String, String> {
+    ) -> Result<String, ConfigError> {
         // If out ports list has a first port declared
         // (either explicitly or implicitly), use it
         if let Some(&port) = outs.first().as_ref() {
@@ -132,7 +445,7 @@ impl UserLink {
             outs.push(new_port.clone());
             Ok(new_port)
         } else {
-            Err("node in link has no output ports".into())
+            Err(ConfigError::NoOutputPorts)
         }
     }
 
@@ -141,21 +454,18 @@ impl UserLink {
         ins: &mut Vec<String>,
         user: bool,
         n: usize,
-    ) -> Result<String, String> {
+    ) -> Result<String, ConfigError> {
         if user {
             let new_port = make_port_name(np)?;
             if ins.contains(&new_port) {
-                return Err(format!("duplicated input port {new_port}"));
+                return Err(ConfigError::DuplicateInputPort(new_port));
             }
             ins.push(new_port.clone());
             Ok(new_port.clone())
         } else if let Some(&port) = ins.get(n - 1).as_ref() {
             Ok(port.into())
         } else {
-            Err(format!(
-                "too many inputs declared (node type supports {} inputs)",
-                ins.len()
-            ))
+            Err(ConfigError::TooManyInputs(ins.len()))
         }
     }
 
@@ -164,7 +474,7 @@ impl UserLink {
         src: &mut PortInfo,
         dst: &mut PortInfo,
         n_ins: usize,
-    ) -> Result<(), String> {
+    ) -> Result<(), ConfigError> {
         let mut from_port = None;
         let mut to_port = None;
 
@@ -173,11 +483,21 @@ impl UserLink {
         let ins = &mut dst.ins;
         let user_ins = dst.user_ins;
 
+        if let (Some(node), Some(port)) = (&self.from.node, &mut self.from.port) {
+            Self::resolve_port_index(port, outs, node, "output")?;
+        }
+        if let (Some(node), Some(port)) = (&self.to.node, &mut self.to.port) {
+            Self::resolve_port_index(port, ins, node, "input")?;
+        }
+
         match &self.from.port {
             Some(port) => {
                 if !Self::accept_port_name(port, outs, user_outs) {
-                    let node = self.from.node.as_ref().unwrap();
-                    return Err(format!("invalid output port name {node}.{port}"));
+                    return Err(ConfigError::InvalidPortName {
+                        kind: "output",
+                        node: self.from.node.clone().unwrap(),
+                        port: port.clone(),
+                    });
                 }
             }
             None => {
@@ -188,8 +508,11 @@ impl UserLink {
         match &self.to.port {
             Some(port) => {
                 if !Self::accept_port_name(port, ins, user_ins) {
-                    let node = self.to.node.as_ref().unwrap();
-                    return Err(format!("invalid input port name {node}.{port}"));
+                    return Err(ConfigError::InvalidPortName {
+                        kind: "input",
+                        node: self.to.node.clone().unwrap(),
+                        port: port.clone(),
+                    });
                 }
             }
             None => {
@@ -271,7 +594,7 @@ impl<'a> Deserialize<'a> for UserNodeConfig {
                         "inputs" => {
                             if let Ok(v) = map.next_value::<serde_json::Value>() {
                                 read_links(&mut links, v, &mut named_ins, &UserLink::new)
-                                    .map_err(Error::custom::<&str>)?;
+                                    .map_err(|e| Error::custom(format!("{e} in `inputs`")))?;
                             }
                         }
                         "output" => {
@@ -283,7 +606,7 @@ impl<'a> Deserialize<'a> for UserNodeConfig {
                         "outputs" => {
                             if let Ok(v) = map.next_value::<serde_json::Value>() {
                                 read_links(&mut links, v, &mut named_outs, &UserLink::new_reverse)
-                                    .map_err(Error::custom::<&str>)?;
+                                    .map_err(|e| Error::custom(format!("{e} in `outputs`")))?;
                             }
                         }
                         _ => {
@@ -378,6 +701,28 @@ pub struct UserConfig {
     nodes: Vec<UserNodeConfig>,
     #[serde(default)]
     debug: bool,
+    #[serde(default)]
+    debug_nodes: Option<Vec<String>>,
+    #[serde(default)]
+    debug_exclude: Vec<String>,
+    #[serde(default)]
+    strict: bool,
+    #[serde(default)]
+    deadline_ms: Option<u64>,
+    #[serde(default)]
+    max_body_bytes: Option<u64>,
+    #[serde(default)]
+    max_node_runs: Option<u64>,
+    #[serde(default)]
+    dedupe_calls: bool,
+    #[serde(default)]
+    compress: Option<String>,
+    #[serde(default)]
+    sniff_content_type: bool,
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    jq_prelude: Option<String>,
 }
 
 #[derive(Derivative)]
@@ -388,15 +733,40 @@ struct NodeInfo {
     #[derivative(PartialEq = "ignore")]
     #[derivative(Debug = "ignore")]
     node_config: Box<dyn NodeConfig>,
+    tick_period: Option<Duration>,
+    #[derivative(PartialEq = "ignore")]
+    #[derivative(Debug = "ignore")]
+    when: Option<Rc<Jq>>,
+    /// The names of the phases the node may run in, if restricted.
+    phases: Option<Vec<&'static str>>,
+    /// The programs applied to the values read by the input ports,
+    /// by port position.
+    #[derivative(PartialEq = "ignore")]
+    #[derivative(Debug = "ignore")]
+    transforms: Vec<Option<Transform>>,
 }
 
 #[derive(PartialEq, Debug)]
 pub struct Config {
     n_nodes: usize,
     n_implicits: usize,
+    /// The output ports of the implicit nodes that the host sets in a
+    /// phase, and their input ports that it reads back, by position.
+    phase_outputs: Vec<(Phase, usize, usize)>,
+    phase_inputs: Vec<(Phase, usize, usize)>,
     node_list: Vec<NodeInfo>,
     graph: DependencyGraph,
+    run_order: Vec<usize>,
     debug: bool,
+    debug_nodes: Option<Vec<String>>,
+    debug_exclude: Vec<String>,
+    deadline: Option<Duration>,
+    max_body_bytes: Option<usize>,
+    max_node_runs: usize,
+    dedupe_calls: bool,
+    compress: Compress,
+    sniff_content_type: bool,
+    warnings: Vec<String>,
 }
 
 struct PortInfo {
@@ -447,23 +817,27 @@ fn add_default_links(
     }
 }
 
-fn make_port_name(np: &UserNodePort) -> Result<String, String> {
+fn make_port_name(np: &UserNodePort) -> Result<String, ConfigError> {
     Ok(match (&np.node, &np.port) {
         (Some(n), Some(p)) => format!("{n}.{p}"),
         (Some(n), None) => n.into(),
-        (None, _) => return Err("could not resolve a name".into()),
+        (None, _) => return Err(ConfigError::UnresolvedPortName),
     })
 }
 
-fn err_at_node(desc: &UserNodeDesc, e: &str) -> String {
-    let name = &desc.name;
-    let nt = &desc.node_type;
-    format!("in node `{name}` of type `{nt}`: {e}")
+/// Parse the user configuration, reporting the path of the offending
+/// value (e.g. `nodes[2].inputs`) and its position in case of errors.
+fn parse_user_config(bytes: &[u8]) -> Result<UserConfig, ConfigError> {
+    let parse_error = |e: &dyn fmt::Display| ConfigError::ParseError(e.to_string());
+    let mut de = serde_json::Deserializer::from_slice(bytes);
+    let user_config = serde_path_to_error::deserialize(&mut de).map_err(|e| parse_error(&e))?;
+    de.end().map_err(|e| parse_error(&e))?;
+    Ok(user_config)
 }
 
-fn get_link_str(o: &Option<String>, _name: &str) -> Result<String, String> {
+fn get_link_str(o: &Option<String>, name: &str) -> Result<String, ConfigError> {
     o.as_ref()
-        .ok_or_else(|| "bad link definition in node {_name}".into())
+        .ok_or_else(|| ConfigError::BadLink(name.into()))
         .cloned()
 }
 
@@ -480,18 +854,18 @@ impl PortInfo {
     }
 }
 
-fn node_position(node_names: &[String], np: &UserNodePort) -> Result<usize, String> {
+fn node_position(node_names: &[String], np: &UserNodePort) -> Result<usize, ConfigError> {
     node_names
         .iter()
         .position(|name: &String| Some(name) == np.node.as_ref())
-        .ok_or_else(|| format!("unknown node in link: {}", np))
+        .ok_or_else(|| ConfigError::UnknownNode(np.to_string()))
 }
 
 fn get_source_dest_ports(
     port_list: &mut [PortInfo],
     s: usize,
     d: usize,
-) -> Result<(&mut PortInfo, &mut PortInfo), &'static str> {
+) -> Result<(&mut PortInfo, &mut PortInfo), ConfigError> {
     match s.cmp(&d) {
         Ordering::Less => {
             let (ss, ds) = port_list.split_at_mut(s + 1);
@@ -501,7 +875,7 @@ fn get_source_dest_ports(
             let (ds, ss) = port_list.split_at_mut(d + 1);
             Ok((&mut ss[s - (d + 1)], &mut ds[d]))
         }
-        Ordering::Equal => Err("node cannot connect to itself"),
+        Ordering::Equal => Err(ConfigError::SelfLoop),
     }
 }
 
@@ -510,24 +884,68 @@ fn fixup_missing_port_names(
     node_names: &[String],
     port_list: &mut [PortInfo],
     linked_inputs: &mut [usize],
-) -> Result<(), String> {
+) -> Result<(), ConfigError> {
+    // A node's own inputs are resolved positionally, in the order
+    // they were declared, regardless of any links that other nodes
+    // declare into it via their outputs.
+    let mut own_inputs = 0;
     for link in &mut unc.links {
         let s = node_position(node_names, &link.from)?;
         let d = node_position(node_names, &link.to)?;
         let (src, dst) = get_source_dest_ports(port_list, s, d)?;
 
-        linked_inputs[d] += 1;
+        let n = if link.to.node.as_ref() == Some(&unc.desc.name) {
+            own_inputs += 1;
+            own_inputs
+        } else {
+            linked_inputs[d] += 1;
+            linked_inputs[d]
+        };
 
-        link.resolve_port_names(src, dst, linked_inputs[d])?;
+        link.resolve_port_names(src, dst, n)?;
     }
     Ok(())
 }
 
-fn make_node_info(unc: &mut UserNodeConfig, port_info: &PortInfo) -> Result<NodeInfo, String> {
+fn make_node_info(unc: &mut UserNodeConfig, port_info: &PortInfo) -> Result<NodeInfo, ConfigError> {
     let name = &unc.desc.name;
     let node_type = &unc.desc.node_type;
 
-    let nc = nodes::new_config(node_type, name, &port_info.ins, &port_info.outs, &unc.bt)?;
+    let nc = nodes::new_config(node_type, name, &port_info.ins, &port_info.outs, &unc.bt)
+        .map_err(ConfigError::NodeConfig)?;
+
+    let tick_period = match unc.bt.get("tick_period_ms") {
+        Some(v) => match v.as_u64() {
+            Some(ms) if ms > 0 => Some(Duration::from_millis(ms)),
+            _ => return Err(ConfigError::InvalidTickPeriod),
+        },
+        None => None,
+    };
+
+    let when = match unc.bt.get("when") {
+        Some(Value::String(when)) => {
+            let prelude = get_config_value::<String>(&unc.bt, "jq_prelude").unwrap_or_default();
+            let jq =
+                Jq::predicate(when, &prelude, &port_info.ins).map_err(ConfigError::InvalidWhen)?;
+            Some(Rc::new(jq))
+        }
+        Some(_) => return Err(ConfigError::InvalidWhen("must be a string".into())),
+        None => None,
+    };
+
+    let phases = match unc.bt.get("phases") {
+        Some(value) => Some(parse_phases(value)?),
+        None => None,
+    };
+
+    let transforms = match unc.bt.get("transforms") {
+        Some(Value::Object(map)) => {
+            let prelude = get_config_value::<String>(&unc.bt, "jq_prelude").unwrap_or_default();
+            parse_transforms(map, &port_info.ins, &prelude)?
+        }
+        Some(_) => return Err(ConfigError::InvalidTransforms("must be an object".into())),
+        None => vec![],
+    };
 
     add_default_links(name, unc.n_inputs, unc.n_outputs, &mut unc.links, &*nc);
 
@@ -535,9 +953,72 @@ fn make_node_info(unc: &mut UserNodeConfig, port_info: &PortInfo) -> Result<Node
         name: name.to_string(),
         node_type: node_type.to_string(),
         node_config: nc,
+        tick_period,
+        when,
+        phases,
+        transforms,
     })
 }
 
+/// Compile the `transforms` of a node, each a jq program run
+/// with the value read by an input port as `.`, producing its
+/// first result.
+fn parse_transforms(
+    map: &serde_json::Map<String, Value>,
+    inputs: &[String],
+    prelude: &str,
+) -> Result<Vec<Option<Transform>>, ConfigError> {
+    let err = ConfigError::InvalidTransforms;
+    let mut transforms = vec![None; inputs.len()];
+
+    for (port, program) in map {
+        let Some(i) = inputs.iter().position(|input| input == port) else {
+            return Err(err(format!("`{port}` is not an input port of the node")));
+        };
+        let Some(program) = program.as_str() else {
+            return Err(err(format!("the transform of `{port}` must be a string")));
+        };
+        let program = format!("$value | ({program})");
+        let jq = Jq::with_prelude(&program, prelude, vec!["value".into()], BTreeMap::new())
+            .map_err(|e| err(format!("`{port}`: {e}")))?;
+        transforms[i] = Some(Rc::new(move |payload: &Payload| {
+            let results = jq.eval(&[Some(payload)], None)?;
+            Ok(results.into_iter().next().unwrap_or(Value::Null))
+        }));
+    }
+
+    Ok(transforms)
+}
+
+/// The phases in which nodes run on behalf of a request.
+const PHASE_NAMES: &[&str] = &[
+    "request_headers",
+    "request_body",
+    "response_headers",
+    "response_body",
+    "call_response",
+    "grpc_call_response",
+];
+
+fn parse_phases(value: &Value) -> Result<Vec<&'static str>, ConfigError> {
+    let err = |e: &str| ConfigError::InvalidPhases(e.into());
+    let Some(names) = value.as_array().filter(|names| !names.is_empty()) else {
+        return Err(err("must be a non-empty array of phase names"));
+    };
+
+    names
+        .iter()
+        .map(|name| {
+            let name = name.as_str().unwrap_or_default();
+            PHASE_NAMES
+                .iter()
+                .find(|phase| **phase == name)
+                .copied()
+                .ok_or_else(|| err(&format!("unknown phase {name:?}")))
+        })
+        .collect()
+}
+
 fn into_name_lists(ports: Vec<PortInfo>) -> (Vec<Vec<String>>, Vec<Vec<String>>) {
     let n = ports.len();
     let mut input_names = Vec::with_capacity(n);
@@ -550,7 +1031,101 @@ fn into_name_lists(ports: Vec<PortInfo>) -> (Vec<Vec<String>>, Vec<Vec<String>>)
 }
 
 impl UserConfig {
-    fn into_config(mut self, implicits: &[ImplicitNode]) -> Result<Config, String> {
+    /// Replace the aliases of implicit nodes in links by their canonical
+    /// names, so that the rest of the configuration only deals with those.
+    fn resolve_aliases(&mut self, implicits: &[ImplicitNode]) -> Result<(), ConfigError> {
+        let is_implicit = |name: &str| implicits.iter().any(|inode| inode.name == name);
+
+        for (alias, target) in &self.aliases {
+            if is_implicit(alias) {
+                return Err(ConfigError::ReservedAlias(alias.clone()));
+            }
+            if !is_implicit(target) {
+                return Err(ConfigError::InvalidAlias {
+                    alias: alias.clone(),
+                    target: target.clone(),
+                });
+            }
+        }
+
+        for unc in self.nodes.iter_mut() {
+            if self.aliases.contains_key(&unc.desc.name) {
+                return Err(ConfigError::in_node(&unc.desc, ConfigError::ReservedName));
+            }
+
+            for link in unc.links.iter_mut() {
+                for np in [&mut link.from, &mut link.to] {
+                    if let Some(target) = np.node.as_ref().and_then(|n| self.aliases.get(n)) {
+                        np.node = Some(target.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the node names, types and link endpoints of all nodes,
+    /// reporting every problem found instead of stopping at the first.
+    #[cfg(test)]
+    fn check_nodes(&self, implicits: &[ImplicitNode]) -> Vec<ConfigError> {
+        let mut errors = vec![];
+        let mut names: Vec<&str> = implicits.iter().map(|inode| inode.name.as_str()).collect();
+
+        for unc in &self.nodes {
+            let desc = &unc.desc;
+            let name = desc.name.as_str();
+
+            if implicits.iter().any(|inode| inode.name == name) {
+                errors.push(ConfigError::in_node(desc, ConfigError::ReservedName));
+            } else if names.contains(&name) {
+                errors.push(ConfigError::DuplicateNode(name.into()));
+            } else {
+                names.push(name);
+            }
+
+            if !nodes::is_valid_type(&desc.node_type) {
+                errors.push(ConfigError::in_node(desc, ConfigError::UnknownNodeType));
+            }
+        }
+
+        for unc in &self.nodes {
+            for link in &unc.links {
+                for np in [&link.from, &link.to] {
+                    if !np.node.as_deref().is_some_and(|node| names.contains(&node)) {
+                        let e = ConfigError::UnknownNode(np.to_string());
+                        errors.push(ConfigError::in_node(&unc.desc, e));
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// Check the `jq_prelude` once, and hand it to every node using jq.
+    fn share_jq_prelude(&mut self) -> Result<(), ConfigError> {
+        let Some(prelude) = &self.jq_prelude else {
+            return Ok(());
+        };
+        nodes::jq::check_prelude(prelude).map_err(ConfigError::InvalidJqPrelude)?;
+
+        for unc in self.nodes.iter_mut() {
+            if ["jq", "assert"].contains(&unc.desc.node_type.as_str())
+                || unc.bt.contains_key("when")
+                || unc.bt.contains_key("transforms")
+            {
+                unc.bt.insert("jq_prelude".into(), prelude.clone().into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn into_config(mut self, implicits: &[ImplicitNode]) -> Result<Config, ConfigError> {
+        self.resolve_aliases(implicits)?;
+        self.share_jq_prelude()?;
+
         let p = implicits.len();
         let n = self.nodes.len() + p;
 
@@ -568,6 +1143,10 @@ impl UserConfig {
                 name: inode.name.clone(),
                 node_type: "implicit".into(),
                 node_config: Box::new(nodes::implicit::ImplicitConfig {}),
+                tick_period: None,
+                when: None,
+                phases: None,
+                transforms: vec![],
             });
             ports.push(PortInfo::new("implicit", &inode.inputs, &inode.outputs));
         }
@@ -579,11 +1158,11 @@ impl UserConfig {
 
             // at this point, node_names contains only the implicit entries
             if node_names.iter().any(|n| n == name) {
-                return Err(err_at_node(desc, "cannot use reserved node name"));
+                return Err(ConfigError::in_node(desc, ConfigError::ReservedName));
             }
 
             if !nodes::is_valid_type(node_type) {
-                return Err(err_at_node(desc, "unknown node type"));
+                return Err(ConfigError::in_node(desc, ConfigError::UnknownNodeType));
             }
 
             ports.push(PortInfo::new(node_type, &unc.named_ins, &unc.named_outs));
@@ -593,7 +1172,7 @@ impl UserConfig {
             let name = &unc.desc.name;
 
             if node_names.contains(name) {
-                return Err(format!("multiple definitions of node `{name}`"));
+                return Err(ConfigError::DuplicateNode(name.into()));
             }
 
             node_names.push(name.into());
@@ -602,55 +1181,424 @@ impl UserConfig {
         let mut linked_inputs = vec![0; node_names.len()];
         for unc in self.nodes.iter_mut() {
             fixup_missing_port_names(unc, &node_names, &mut ports, &mut linked_inputs)
-                .map_err(|e| err_at_node(&unc.desc, &e))?;
+                .map_err(|e| ConfigError::in_node(&unc.desc, e))?;
         }
 
         // Now that all user-given links are resolved,
         // we can create the user-given nodes
         // (which may add default links of their own into implicit nodes)
         for (u, unc) in self.nodes.iter_mut().enumerate() {
-            nodes.push(make_node_info(unc, &ports[u + p]).map_err(|e| err_at_node(&unc.desc, &e))?);
+            let info = make_node_info(unc, &ports[u + p])
+                .map_err(|e| ConfigError::in_node(&unc.desc, e))?;
+            nodes.push(info);
         }
 
         let (input_names, output_names) = into_name_lists(ports);
         let mut graph = DependencyGraph::new(node_names, input_names, output_names);
 
+        // which user node provides each connected input of the implicit nodes,
+        // to report contending providers (such as two nodes producing
+        // `response.body`) more clearly than the graph would
+        let mut implicit_providers: BTreeMap<(String, String), String> = BTreeMap::new();
+
+        // problems rejected only in strict mode, reported to the caller
+        let mut warnings = vec![];
+
         for unc in &self.nodes {
             let name = &unc.desc.name;
             for link in &unc.links {
-                graph.add(
-                    &get_link_str(&link.from.node, name)?,
-                    &get_link_str(&link.from.port, name)?,
-                    &get_link_str(&link.to.node, name)?,
-                    &get_link_str(&link.to.port, name)?,
-                )?;
+                let src_node = get_link_str(&link.from.node, name)?;
+                let src_port = get_link_str(&link.from.port, name)?;
+                let dst_node = get_link_str(&link.to.node, name)?;
+                let dst_port = get_link_str(&link.to.port, name)?;
+
+                if implicits.iter().any(|inode| inode.name == dst_node) {
+                    let is_sink =
+                        |info: &NodeInfo| info.name == src_node && info.node_config.sink_outputs();
+                    if nodes.iter().any(is_sink) {
+                        let e = ConfigError::SinkToImplicit {
+                            node: src_node.clone(),
+                            target: format!("{dst_node}.{dst_port}"),
+                        };
+                        if self.strict {
+                            return Err(e);
+                        }
+                        warnings.push(e.detail().to_string());
+                    }
+
+                    let key = (dst_node.clone(), dst_port.clone());
+                    if let Some(other) = implicit_providers.insert(key, src_node.clone()) {
+                        if other != src_node {
+                            return Err(ConfigError::ContendingInputs {
+                                node: dst_node,
+                                port: dst_port,
+                                first: other,
+                                second: src_node,
+                            });
+                        }
+                    }
+                }
+
+                graph.add(&src_node, &src_port, &dst_node, &dst_port)?;
+            }
+        }
+
+        for (i, info) in nodes.iter().enumerate() {
+            let name = &info.name;
+            if info.tick_period.is_some() && graph.has_providers(i) {
+                return Err(ConfigError::TickWithInputs(name.clone()));
+            }
+
+            if info.node_type == "exit" && !graph.has_providers(i) {
+                let e = ConfigError::EmptyExit(name.clone());
+                if self.strict {
+                    return Err(e);
+                }
+                warnings.push(e.detail().to_string());
+            }
+        }
+
+        let deadline = match self.deadline_ms {
+            Some(0) => return Err(ConfigError::InvalidDeadline),
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => None,
+        };
+
+        let max_body_bytes = match self.max_body_bytes {
+            Some(0) => return Err(ConfigError::InvalidMaxBodyBytes),
+            Some(n) => Some(usize::try_from(n).unwrap_or(usize::MAX)),
+            None => None,
+        };
+
+        let max_node_runs = match self.max_node_runs {
+            Some(0) => return Err(ConfigError::InvalidMaxNodeRuns),
+            Some(n) => usize::try_from(n).unwrap_or(usize::MAX),
+            None => DEFAULT_MAX_NODE_RUNS,
+        };
+
+        let compress = match self.compress.as_deref() {
+            Some(value) => {
+                Compress::parse(value).ok_or_else(|| ConfigError::InvalidCompress(value.into()))?
+            }
+            None => Compress::default(),
+        };
+
+        let debug_names = self.debug_nodes.iter().flatten().chain(&self.debug_exclude);
+        for name in debug_names {
+            if !nodes.iter().any(|info| &info.name == name) {
+                return Err(ConfigError::UnknownDebugNode(name.clone()));
+            }
+        }
+
+        let run_order = graph.stable_topological_order(p);
+
+        let mut phase_outputs = vec![];
+        let mut phase_inputs = vec![];
+        for (i, inode) in implicits.iter().enumerate() {
+            for (phase, outputs, inputs) in &inode.phase_ports {
+                let positions = |names: &[String], ports: &[String]| {
+                    ports
+                        .iter()
+                        .filter_map(|port| names.iter().position(|name| name == port))
+                        .map(|port| (*phase, i, port))
+                        .collect::<Vec<_>>()
+                };
+                phase_outputs.extend(positions(&inode.outputs, outputs));
+                phase_inputs.extend(positions(&inode.inputs, inputs));
             }
         }
 
         Ok(Config {
             n_nodes: n,
             n_implicits: p,
+            phase_outputs,
+            phase_inputs,
             node_list: nodes,
             graph,
+            run_order,
+            debug: self.debug,
+            debug_nodes: self.debug_nodes,
+            debug_exclude: self.debug_exclude,
+            deadline,
+            max_body_bytes,
+            max_node_runs,
+            dedupe_calls: self.dedupe_calls,
+            compress,
+            sniff_content_type: self.sniff_content_type,
+            warnings,
+        })
+    }
+}
+
+/// Programmatic construction of a `Config`, without going through JSON.
+/// The resulting configuration goes through the same checks as `Config::new`.
+/// The filter itself reads JSON, so this is only built for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct ConfigBuilder {
+    nodes: Vec<UserNodeConfig>,
+    links: Vec<(String, String)>,
+    debug: bool,
+    debug_nodes: Option<Vec<String>>,
+    debug_exclude: Vec<String>,
+    strict: bool,
+    deadline_ms: Option<u64>,
+    max_body_bytes: Option<u64>,
+    max_node_runs: Option<u64>,
+    dedupe_calls: bool,
+    compress: Option<String>,
+    sniff_content_type: bool,
+    aliases: BTreeMap<String, String>,
+    jq_prelude: Option<String>,
+}
+
+#[cfg(test)]
+impl ConfigBuilder {
+    pub fn add_node(mut self, node_type: &str, name: &str, bt: BTreeMap<String, Value>) -> Self {
+        self.nodes.push(UserNodeConfig {
+            desc: UserNodeDesc {
+                node_type: node_type.into(),
+                name: name.into(),
+            },
+            bt,
+            links: vec![],
+            n_inputs: 0,
+            n_outputs: 0,
+            named_ins: vec![],
+            named_outs: vec![],
+        });
+        self
+    }
+
+    /// Link two ports, given as `node.port` (or just `node`),
+    /// as in the `input` and `output` attributes of a node.
+    pub fn link(mut self, from: &str, to: &str) -> Self {
+        self.links.push((from.into(), to.into()));
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Only trace the given nodes.
+    pub fn debug_nodes(mut self, names: &[&str]) -> Self {
+        self.debug_nodes = Some(names.iter().map(|name| name.to_string()).collect());
+        self
+    }
+
+    /// Do not trace the given nodes.
+    pub fn debug_exclude(mut self, names: &[&str]) -> Self {
+        self.debug_exclude = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn deadline_ms(mut self, deadline_ms: u64) -> Self {
+        self.deadline_ms = Some(deadline_ms);
+        self
+    }
+
+    pub fn max_body_bytes(mut self, max_body_bytes: u64) -> Self {
+        self.max_body_bytes = Some(max_body_bytes);
+        self
+    }
+
+    pub fn max_node_runs(mut self, max_node_runs: u64) -> Self {
+        self.max_node_runs = Some(max_node_runs);
+        self
+    }
+
+    pub fn dedupe_calls(mut self, dedupe_calls: bool) -> Self {
+        self.dedupe_calls = dedupe_calls;
+        self
+    }
+
+    pub fn compress(mut self, compress: &str) -> Self {
+        self.compress = Some(compress.into());
+        self
+    }
+
+    pub fn sniff_content_type(mut self, sniff_content_type: bool) -> Self {
+        self.sniff_content_type = sniff_content_type;
+        self
+    }
+
+    /// Allow referring to the implicit node `target` as `alias` in links.
+    pub fn alias(mut self, alias: &str, target: &str) -> Self {
+        self.aliases.insert(alias.into(), target.into());
+        self
+    }
+
+    pub fn jq_prelude(mut self, prelude: &str) -> Self {
+        self.jq_prelude = Some(prelude.into());
+        self
+    }
+
+    fn find_node(&mut self, name: &Option<String>) -> Option<&mut UserNodeConfig> {
+        self.nodes
+            .iter_mut()
+            .find(|unc| Some(&unc.desc.name) == name.as_ref())
+    }
+
+    fn into_user_config(mut self) -> Result<UserConfig, ConfigError> {
+        for (from, to) in std::mem::take(&mut self.links) {
+            let (from_node, from_port) = parse_node_port(from.clone());
+            let (to_node, to_port) = parse_node_port(to.clone());
+
+            // Links are attached to their destination node as inputs,
+            // or to their source node as outputs if the destination
+            // is not a user-given node (e.g. an implicit node).
+            if let Some(unc) = self.find_node(&to_node) {
+                if let Some(port) = &to_port {
+                    unc.named_ins.push(port.clone());
+                }
+                unc.n_inputs += 1;
+                unc.links
+                    .push(UserLink::new(from_node, from_port, to_node, to_port));
+            } else if let Some(unc) = self.find_node(&from_node) {
+                if let Some(port) = &from_port {
+                    unc.named_outs.push(port.clone());
+                }
+                unc.n_outputs += 1;
+                unc.links
+                    .push(UserLink::new(from_node, from_port, to_node, to_port));
+            } else {
+                return Err(ConfigError::UnknownLinkEndpoints { from, to });
+            }
+        }
+
+        Ok(UserConfig {
+            nodes: self.nodes,
             debug: self.debug,
+            debug_nodes: self.debug_nodes,
+            debug_exclude: self.debug_exclude,
+            strict: self.strict,
+            deadline_ms: self.deadline_ms,
+            max_body_bytes: self.max_body_bytes,
+            max_node_runs: self.max_node_runs,
+            dedupe_calls: self.dedupe_calls,
+            compress: self.compress,
+            sniff_content_type: self.sniff_content_type,
+            aliases: self.aliases,
+            jq_prelude: self.jq_prelude,
         })
     }
+
+    pub fn build(self, implicits: &[ImplicitNode]) -> Result<Config, ConfigError> {
+        self.into_user_config()
+            .and_then(|user_config| user_config.into_config(implicits))
+    }
 }
 
 impl Config {
-    pub fn new(config_bytes: Vec<u8>, implicits: &[ImplicitNode]) -> Result<Config, String> {
-        match de::from_slice::<UserConfig>(&config_bytes) {
-            Ok(user_config) => user_config
-                .into_config(implicits)
-                .map_err(|err| format!("failed checking configuration: {err}")),
-            Err(err) => Err(format!("failed parsing configuration: {err}")),
+    /// Check a configuration without keeping the result. Unlike `new`, this
+    /// reports all the problems with node names, types and link endpoints,
+    /// and also rejects nodes that can never run because they are in a
+    /// cycle. Only those are collected: the other problems, such as invalid
+    /// attributes or ports, are found while building the configuration as
+    /// `new` does, so only the first of them is reported, and only once the
+    /// nodes passed the checks above. As for `new`, node types must have
+    /// been registered. The filter only reads configurations through `new`,
+    /// so this is only built for tests.
+    #[cfg(test)]
+    pub fn validate(
+        config_bytes: &[u8],
+        implicits: &[ImplicitNode],
+    ) -> Result<(), Vec<ConfigError>> {
+        let mut user_config = parse_user_config(config_bytes).map_err(|err| vec![err])?;
+        user_config
+            .resolve_aliases(implicits)
+            .map_err(|err| vec![err])?;
+
+        let errors = user_config.check_nodes(implicits);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let config = user_config
+            .into_config(implicits)
+            .map_err(|err| vec![err])?;
+
+        let first = config.n_implicits;
+        let cyclic = config.graph.cyclic_nodes(first);
+        if !cyclic.is_empty() {
+            let names = cyclic
+                .iter()
+                .map(|&i| config.get_node_name(i).to_string())
+                .collect();
+            return Err(vec![ConfigError::Cycle(names)]);
         }
+
+        Ok(())
+    }
+
+    pub fn new(config_bytes: Vec<u8>, implicits: &[ImplicitNode]) -> Result<Config, ConfigError> {
+        parse_user_config(&config_bytes)?.into_config(implicits)
+    }
+
+    #[cfg(test)]
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
     }
 
     pub fn debug(&self) -> bool {
         self.debug
     }
 
+    /// The nodes to trace, if limited to some of them.
+    pub fn debug_nodes(&self) -> Option<&[String]> {
+        self.debug_nodes.as_deref()
+    }
+
+    /// The nodes never to trace.
+    pub fn debug_exclude(&self) -> &[String] {
+        &self.debug_exclude
+    }
+
+    /// The maximum time for processing a request, counted from its start.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    /// The largest request or response body that is buffered for processing.
+    pub fn max_body_bytes(&self) -> Option<usize> {
+        self.max_body_bytes
+    }
+
+    /// The most node runs allowed while processing a single request.
+    pub fn max_node_runs(&self) -> usize {
+        self.max_node_runs
+    }
+
+    /// Whether identical HTTP calls in flight for the same request
+    /// share a single dispatch.
+    pub fn dedupe_calls(&self) -> bool {
+        self.dedupe_calls
+    }
+
+    /// When to compress the response body written through `response.body`.
+    pub fn compress(&self) -> Compress {
+        self.compress
+    }
+
+    /// Whether a body without a `Content-Type` is parsed as JSON
+    /// when it looks like JSON.
+    pub fn sniff_content_type(&self) -> bool {
+        self.sniff_content_type
+    }
+
+    /// Problems found while checking the configuration which did not
+    /// reject it, since `strict` is not set.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
     pub fn node_count(&self) -> usize {
         self.n_nodes
     }
@@ -659,6 +1607,13 @@ impl Config {
         self.n_implicits
     }
 
+    /// The user-given nodes in the order in which they are run:
+    /// the order of declaration, except that a node always comes
+    /// after the nodes connected to its inputs.
+    pub fn run_order(&self) -> &[usize] {
+        &self.run_order
+    }
+
     pub fn get_node_name(&self, i: usize) -> &str {
         &self.node_list.get(i).expect("valid index").name
     }
@@ -673,21 +1628,152 @@ impl Config {
             .map(|info| (info.name.as_ref(), info.node_type.as_ref()))
     }
 
+    pub fn has_optional_inputs(&self, i: usize) -> bool {
+        self.node_list
+            .get(i)
+            .expect("valid index")
+            .node_config
+            .optional_inputs()
+    }
+
+    pub fn has_sink_outputs(&self, i: usize) -> bool {
+        self.node_list
+            .get(i)
+            .expect("valid index")
+            .node_config
+            .sink_outputs()
+    }
+
+    /// The period of a node that runs in the background on a timer,
+    /// rather than as part of request processing.
+    pub fn get_tick_period(&self, i: usize) -> Option<Duration> {
+        self.node_list.get(i).expect("valid index").tick_period
+    }
+
+    /// The condition under which a node runs, if it has one; when it does
+    /// not hold, the node is skipped.
+    pub fn get_when(&self, i: usize) -> Option<&Jq> {
+        self.node_list.get(i).expect("valid index").when.as_deref()
+    }
+
+    /// The programs applied to the values read by the input ports
+    /// of the node, by port position.
+    pub fn get_transforms(&self, i: usize) -> &[Option<Transform>] {
+        &self.node_list.get(i).expect("valid index").transforms
+    }
+
+    /// Whether the node may run in the given phase, as restricted
+    /// by its `phases` attribute.
+    pub fn runs_in_phase(&self, i: usize, phase: Phase) -> bool {
+        match &self.node_list.get(i).expect("valid index").phases {
+            Some(phases) => phases.contains(&phase.name()),
+            None => true,
+        }
+    }
+
+    /// The period of the timer running the background nodes, if any: the
+    /// greatest common divisor of their periods, so that each node runs on
+    /// a tick falling exactly on its own period.
+    pub fn tick_period(&self) -> Option<Duration> {
+        fn gcd(a: u128, b: u128) -> u128 {
+            if b == 0 {
+                a
+            } else {
+                gcd(b, a % b)
+            }
+        }
+
+        self.node_list
+            .iter()
+            .filter_map(|info| info.tick_period)
+            .map(|period| period.as_millis())
+            .reduce(gcd)
+            .map(|ms| Duration::from_millis(ms as u64))
+    }
+
+    /// Whether processing a phase involves any work: reading or writing the
+    /// data of the implicit nodes that the host processes in that phase, as
+    /// declared with `ImplicitNode::in_phase`, or, for call responses and
+    /// ticks, having nodes that make calls or run in the background. All
+    /// nodes are first considered for running with the request headers.
+    /// Hosts can skip the phases without work, such as buffering bodies
+    /// that no node reads.
+    pub fn phase_has_work(&self, phase: Phase) -> bool {
+        match phase {
+            Phase::HttpRequestHeaders => self.node_count() > self.n_implicits,
+            Phase::HttpCallResponse => self.has_node_type("call"),
+            Phase::GrpcCallResponse(_) => self.has_node_type("grpc_call"),
+            Phase::Tick => self.tick_period().is_some(),
+            _ => {
+                let graph = &self.graph;
+                self.phase_outputs
+                    .iter()
+                    .any(|&(p, n, port)| p == phase && graph.has_dependents(n, port))
+                    || self
+                        .phase_inputs
+                        .iter()
+                        .any(|&(p, n, port)| p == phase && graph.has_provider(n, port))
+                    || self.node_list.iter().any(|info| {
+                        // a node held back until this phase
+                        info.phases
+                            .as_ref()
+                            .is_some_and(|phases| phases.contains(&phase.name()))
+                    })
+            }
+        }
+    }
+
+    /// Whether any of the user-given nodes is of the given type.
+    pub fn has_node_type(&self, node_type: &str) -> bool {
+        self.node_list[self.n_implicits..]
+            .iter()
+            .any(|info| info.node_type == node_type)
+    }
+
+    /// The set of types used by the user-given nodes.
+    #[cfg(test)]
+    pub fn distinct_node_types(&self) -> BTreeSet<&str> {
+        self.node_list[self.n_implicits..]
+            .iter()
+            .map(|info| info.node_type.as_str())
+            .collect()
+    }
+
+    /// The resolved input port names of the node at the given index.
+    #[cfg(test)]
+    pub fn node_input_ports(&self, i: usize) -> &[String] {
+        self.graph.input_names(i)
+    }
+
+    /// The resolved output port names of the node at the given index.
+    #[cfg(test)]
+    pub fn node_output_ports(&self, i: usize) -> &[String] {
+        self.graph.output_names(i)
+    }
+
     pub fn get_graph(&self) -> &DependencyGraph {
         &self.graph
     }
 
     pub fn build_nodes(&self) -> NodeVec {
-        let mut nodes = NodeVec::with_capacity(self.node_list.len());
+        (0..self.node_list.len())
+            .map(|i| self.build_node(i))
+            .collect()
+    }
 
-        for info in &self.node_list {
-            match nodes::new_node(&info.node_type, &*info.node_config) {
-                Ok(node) => nodes.push(node),
-                Err(err) => log::error!("{err}"),
+    pub fn build_node(&self, i: usize) -> Box<dyn Node> {
+        let info = &self.node_list[i];
+        match nodes::new_node(&info.node_type, &*info.node_config) {
+            Ok(node) => node,
+            Err(err) => {
+                // keep the vector aligned with the node indices of the
+                // graph: the failed node becomes a placeholder that fails
+                let err = format!("failed building node {}: {err}", info.name);
+                log::error!("{err}");
+                let n_outputs = self.graph.number_of_outputs(i);
+                Box::new(nodes::Placeholder::new(n_outputs, err))
             }
         }
-
-        nodes
     }
 }
 
@@ -699,15 +1785,32 @@ pub fn get_config_value<T: for<'de> serde::Deserialize<'de>>(
         .and_then(|v| serde_json::from_value(v.clone()).ok())
 }
 
+/// Kong resolves the vault references of referenceable fields before
+/// handing over the configuration, so one that is still there, as in
+/// `{vault://env/token}`, could not be resolved.
+pub fn check_resolved(node_type: &str, key: &str, value: &str) -> Result<(), String> {
+    if value.starts_with("{vault://") && value.ends_with('}') {
+        Err(format!(
+            "{node_type}: '{key}' holds the unresolved vault reference {value}"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::data::{Input, InputPort, Phase, State};
     use crate::nodes::PortConfig;
+    use mock_proxy_wasm::*;
+    use proxy_wasm::traits::*;
+    use proxy_wasm::types::Bytes;
     use serde_json::json;
     use std::any::Any;
 
     fn deserialize_user_config(cfg: &str) -> UserConfig {
-        de::from_slice::<UserConfig>(cfg.as_bytes()).unwrap()
+        parse_user_config(cfg.as_bytes()).unwrap()
     }
 
     fn declare_implicits() -> Vec<ImplicitNode> {
@@ -721,6 +1824,67 @@ mod test {
         ]
     }
 
+    #[test]
+    fn implicit_ports() {
+        let implicits = declare_implicits();
+        assert_eq!(
+            Some((0, 2)),
+            find_implicit_port(&implicits, "request", "query")
+        );
+        assert_eq!(
+            Some((1, 2)),
+            find_implicit_port(&implicits, "service_request", "query")
+        );
+        assert_eq!(
+            Some((3, 1)),
+            find_implicit_port(&implicits, "response", "headers")
+        );
+        assert_eq!(None, find_implicit_port(&implicits, "response", "query"));
+        assert_eq!(None, find_implicit_port(&implicits, "message", "body"));
+    }
+
+    #[test]
+    fn config_custom_implicits() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        // a message queue consumer rather than an HTTP filter
+        let implicits = vec![
+            ImplicitNode::new(
+                "message",
+                vec![],
+                PortConfig::names(&["payload", "attributes"]),
+            ),
+            ImplicitNode::new("publish", PortConfig::names(&["payload"]), vec![]),
+        ];
+        let config = Config::new(
+            br#"{
+                "nodes": [
+                    {
+                        "name": "JQ",
+                        "type": "jq",
+                        "inputs": { "p": "message.payload", "a": "message.attributes" },
+                        "output": "publish.payload"
+                    }
+                ]
+            }"#
+            .to_vec(),
+            &implicits,
+        )
+        .unwrap();
+
+        let graph = config.get_graph();
+        assert_eq!(2, config.number_of_implicits());
+        assert!(graph.has_dependents(0, 1));
+        assert!(graph.has_provider(1, 0));
+
+        let err = Config::new(
+            br#"{ "nodes": [ { "type": "jq", "input": "request.body" } ] }"#.to_vec(),
+            &implicits,
+        );
+        assert!(err.is_err());
+    }
+
     #[test]
     fn deserialize_empty_nodes() {
         let uc = deserialize_user_config(
@@ -733,6 +1897,17 @@ mod test {
             UserConfig {
                 nodes: vec![],
                 debug: false,
+                debug_nodes: None,
+                debug_exclude: vec![],
+                strict: false,
+                deadline_ms: None,
+                max_body_bytes: None,
+                max_node_runs: None,
+                dedupe_calls: false,
+                compress: None,
+                sniff_content_type: false,
+                aliases: BTreeMap::new(),
+                jq_prelude: None,
             }
         );
     }
@@ -852,7 +2027,18 @@ mod test {
                         named_outs: vec![]
                     }
                 ],
-                debug: false
+                debug: false,
+                debug_nodes: None,
+                debug_exclude: vec![],
+                strict: false,
+                deadline_ms: None,
+                max_body_bytes: None,
+                max_node_runs: None,
+                dedupe_calls: false,
+                compress: None,
+                sniff_content_type: false,
+                aliases: BTreeMap::new(),
+                jq_prelude: None,
             }
         );
     }
@@ -905,14 +2091,14 @@ mod test {
         let result = Config::new(cfg.as_bytes().to_vec(), &implicits);
 
         let err = result.unwrap_err();
-        assert_eq!(err, message);
+        assert_eq!(err.to_string(), message);
     }
 
     #[test]
     fn config_no_json() {
         reject_config_with(
             "",
-            "failed parsing configuration: EOF while parsing a JSON value.",
+            "failed parsing configuration: EOF while parsing a value at line 1 column 0",
         )
     }
 
@@ -920,13 +2106,16 @@ mod test {
     fn config_bad_json() {
         reject_config_with(
             "{",
-            "failed parsing configuration: EOF while parsing an object.",
+            "failed parsing configuration: EOF while parsing an object at line 1 column 1",
         )
     }
 
     #[test]
     fn config_empty_json() {
-        reject_config_with("{}", "failed parsing configuration: missing field `nodes`")
+        reject_config_with(
+            "{}",
+            "failed parsing configuration: missing field `nodes` at line 1 column 2",
+        )
     }
 
     #[test]
@@ -948,7 +2137,27 @@ mod test {
                     }
                 ]
             }"#,
-            "failed parsing configuration: missing field `type`",
+            "failed parsing configuration: nodes[0]: missing field `type` at line 5 column 21",
+        )
+    }
+
+    #[test]
+    fn config_invalid_inputs() {
+        reject_config_with(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "A",
+                        "type": "implicit"
+                    },
+                    {
+                        "name": "B",
+                        "type": "implicit",
+                        "inputs": 1
+                    }
+                ]
+            }"#,
+            "failed parsing configuration: nodes[1]: invalid object in `inputs` at line 11 column 21",
         )
     }
 
@@ -1002,16 +2211,72 @@ mod test {
         )
     }
 
-    struct IgnoreConfig {}
-    impl NodeConfig for IgnoreConfig {
-        fn as_any(&self) -> &dyn Any {
-            self
-        }
+    #[test]
+    fn builder_matches_json() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let implicits = declare_implicits();
+
+        let from_json = Config::new(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "jq1",
+                        "type": "jq",
+                        "input": "request.headers",
+                        "jq": "."
+                    },
+                    {
+                        "name": "mycall",
+                        "type": "call",
+                        "input": "jq1",
+                        "url": "http://example.com"
+                    },
+                    {
+                        "name": "jq2",
+                        "type": "jq",
+                        "inputs": {
+                            "$mycall": "mycall",
+                            "$request": "request.body"
+                        },
+                        "output": "response.body"
+                    }
+                ]
+            }"#
+            .as_bytes()
+            .to_vec(),
+            &implicits,
+        )
+        .unwrap();
+
+        let from_builder = Config::builder()
+            .add_node("jq", "jq1", BTreeMap::from([("jq".into(), json!("."))]))
+            .add_node(
+                "call",
+                "mycall",
+                BTreeMap::from([("url".into(), json!("http://example.com"))]),
+            )
+            .add_node("jq", "jq2", BTreeMap::new())
+            .link("request.headers", "jq1")
+            .link("jq1", "mycall")
+            .link("mycall", "jq2.$mycall")
+            .link("request.body", "jq2.$request")
+            .link("jq2", "response.body")
+            .build(&implicits)
+            .unwrap();
+
+        assert_eq!(from_json, from_builder);
     }
 
     #[test]
-    fn convert_complete_example() {
-        let uc = deserialize_user_config(
+    fn graph_to_dot() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let config = Config::new(
             r#"{
                 "nodes": [
                     {
@@ -1036,87 +2301,1100 @@ mod test {
                         "jq": "{ \"bee\": $mycall.bee, \"boo\": $request.boo }"
                     }
                 ]
+            }"#
+            .as_bytes()
+            .to_vec(),
+            &declare_implicits(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            r#"digraph datakit {
+    "request" [shape=box];
+    "service_request" [shape=box];
+    "service_response" [shape=box];
+    "response" [shape=box];
+    "jq1" [label="jq1 (jq)"];
+    "mycall" [label="mycall (call)"];
+    "jq2" [label="jq2 (jq)"];
+    "request" -> "jq2" [label="body -> $request"];
+    "request" -> "jq1" [label="headers -> request.headers"];
+    "jq1" -> "mycall" [label="mycall -> body"];
+    "mycall" -> "jq2" [label="body -> $mycall"];
+}
+"#,
+            config.get_graph().to_dot(&config)
+        );
+    }
+
+    #[test]
+    fn builder_rejects_invalid() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let implicits = declare_implicits();
+
+        let err = Config::builder()
+            .add_node("jq", "response", BTreeMap::new())
+            .build(&implicits)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "failed checking configuration: in node `response` of type `jq`: cannot use reserved node name");
+
+        let err = Config::builder()
+            .add_node("jq", "MY_NODE", BTreeMap::new())
+            .link("MY_NODE", "MY_NODE.input")
+            .build(&implicits)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "failed checking configuration: in node `MY_NODE` of type `jq`: node cannot connect to itself");
+        assert!(
+            matches!(err, ConfigError::InNode { error, .. } if *error == ConfigError::SelfLoop)
+        );
+
+        let err = Config::builder()
+            .link("request.body", "response.body")
+            .build(&implicits)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "failed checking configuration: link request.body -> response.body does not refer to any given node");
+    }
+
+    fn call_inputs(cfg: &str) -> Vec<Option<(usize, usize)>> {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let config = Config::new(cfg.as_bytes().to_vec(), &declare_implicits()).unwrap();
+        let call = config
+            .node_types()
+            .position(|(_, node_type)| node_type == "call")
+            .unwrap();
+
+        config.graph.each_input(call).cloned().collect()
+    }
+
+    #[test]
+    fn inputs_map_binds_by_port_name() {
+        let inputs = call_inputs(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "CALL",
+                        "type": "call",
+                        "url": "http://example.com",
+                        "inputs": {
+                            "query": "request.query",
+                            "headers": "request.headers",
+                            "body": "request.body"
+                        }
+                    }
+                ]
+            }"#,
+        );
+        assert_eq!(inputs, vec![Some((0, 0)), Some((0, 1)), Some((0, 2))]);
+
+        let inputs = call_inputs(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "CALL",
+                        "type": "call",
+                        "url": "http://example.com",
+                        "inputs": {
+                            "headers": "request.headers",
+                            "body": "request.query"
+                        }
+                    }
+                ]
+            }"#,
+        );
+        assert_eq!(inputs, vec![Some((0, 2)), Some((0, 1)), None, None]);
+    }
+
+    #[test]
+    fn inputs_list_binds_by_position() {
+        let inputs = call_inputs(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "CALL",
+                        "type": "call",
+                        "url": "http://example.com",
+                        "inputs": ["request.query", "request.headers"]
+                    }
+                ]
+            }"#,
+        );
+        assert_eq!(inputs, vec![Some((0, 2)), Some((0, 1)), None, None]);
+    }
+
+    #[test]
+    fn inputs_list_ignores_links_from_other_nodes() {
+        let inputs = call_inputs(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "JQ",
+                        "type": "jq",
+                        "input": "request.body",
+                        "outputs": {
+                            "q": "CALL.query"
+                        }
+                    },
+                    {
+                        "name": "CALL",
+                        "type": "call",
+                        "url": "http://example.com",
+                        "inputs": ["request.body", "request.headers"]
+                    }
+                ]
             }"#,
         );
+        assert_eq!(inputs, vec![Some((0, 0)), Some((0, 1)), Some((4, 0)), None]);
+    }
 
+    #[test]
+    fn config_node_types() {
         nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
         nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
         nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
 
-        let implicits = declare_implicits();
+        let config = Config::new(
+            r#"{
+                "nodes": [
+                    { "name": "A", "type": "jq" },
+                    { "name": "B", "type": "call", "url": "http://example.com" },
+                    { "name": "C", "type": "jq" }
+                ]
+            }"#
+            .as_bytes()
+            .to_vec(),
+            &declare_implicits(),
+        )
+        .unwrap();
 
-        let config = uc.into_config(&implicits).unwrap();
-        assert!(!config.debug);
-        assert_eq!(config.n_nodes, 7);
-        assert_eq!(config.n_implicits, 4);
-        assert_eq!(
-            config.node_list,
-            vec![
-                NodeInfo {
-                    name: "request".into(),
-                    node_type: "implicit".into(),
-                    node_config: Box::new(IgnoreConfig {}),
-                },
-                NodeInfo {
-                    name: "service_request".into(),
-                    node_type: "implicit".into(),
-                    node_config: Box::new(IgnoreConfig {}),
-                },
-                NodeInfo {
-                    name: "service_response".into(),
-                    node_type: "implicit".into(),
-                    node_config: Box::new(IgnoreConfig {}),
-                },
-                NodeInfo {
-                    name: "response".into(),
-                    node_type: "implicit".into(),
-                    node_config: Box::new(IgnoreConfig {}),
-                },
-                NodeInfo {
-                    name: "jq1".into(),
-                    node_type: "jq".into(),
-                    node_config: Box::new(IgnoreConfig {}),
-                },
-                NodeInfo {
-                    name: "mycall".into(),
-                    node_type: "call".into(),
-                    node_config: Box::new(IgnoreConfig {}),
-                },
-                NodeInfo {
-                    name: "jq2".into(),
-                    node_type: "jq".into(),
-                    node_config: Box::new(IgnoreConfig {}),
-                },
-            ]
+        assert!(config.has_node_type("jq"));
+        assert!(config.has_node_type("call"));
+        assert!(!config.has_node_type("exit"));
+        assert!(!config.has_node_type("implicit"));
+        assert_eq!(config.distinct_node_types(), BTreeSet::from(["call", "jq"]));
+    }
+
+    #[test]
+    fn config_tick_period() {
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let config = accept_config(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "TOKEN",
+                        "type": "call",
+                        "url": "http://example.com/token",
+                        "tick_period_ms": 30000
+                    },
+                    {
+                        "name": "JQ",
+                        "type": "jq",
+                        "input": "TOKEN.body"
+                    }
+                ]
+            }"#,
         );
-        let input_lists: &[&[Option<(usize, usize)>]] = &[
-            &[],
-            &[None, None, None],
-            &[],
-            &[None, None],
-            &[Some((0, 1))],
-            &[Some((4, 0)), None, None],
-            &[Some((5, 0)), Some((0, 0))],
-        ];
-        for (i, &input_list) in input_lists.iter().enumerate() {
-            let given: Vec<_> = input_list.iter().collect();
-            let computed: Vec<_> = config.graph.each_input(i).collect();
-            assert_eq!(given, computed);
-        }
+        assert_eq!(config.get_tick_period(0), Some(Duration::from_secs(30)));
+        assert_eq!(config.get_tick_period(1), None);
+        assert_eq!(config.tick_period(), Some(Duration::from_secs(30)));
+    }
 
-        let output_lists: &[&[&[(usize, usize)]]] = &[
-            &[&[(6, 1)], &[(4, 0)], &[]],
-            &[&[], &[]],
-            &[&[], &[]],
-            &[&[], &[]],
-            &[&[(5, 0)]],
-            &[&[(6, 0)], &[], &[]],
-            &[],
-        ];
-        for (i, &output_list) in output_lists.iter().enumerate() {
-            let given: Vec<_> = output_list.iter().collect();
-            let computed: Vec<_> = config.graph.each_output(i).collect();
-            assert_eq!(given, computed);
-        }
+    #[test]
+    fn config_tick_period_gcd() {
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+
+        let config = accept_config(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "TOKEN",
+                        "type": "call",
+                        "url": "http://example.com/token",
+                        "tick_period_ms": 40000
+                    },
+                    {
+                        "name": "KEYS",
+                        "type": "call",
+                        "url": "http://example.com/keys",
+                        "tick_period_ms": 60000
+                    }
+                ]
+            }"#,
+        );
+        // the shortest period, 40s, would run KEYS every 80s
+        assert_eq!(config.tick_period(), Some(Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn config_when() {
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let config = accept_config(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "CALL",
+                        "type": "call",
+                        "url": "http://example.com"
+                    },
+                    {
+                        "name": "JQ",
+                        "type": "jq",
+                        "inputs": { "a": "CALL.body" },
+                        "when": "$a.ok"
+                    }
+                ]
+            }"#,
+        );
+        assert!(config.get_when(0).is_none());
+
+        let when = config.get_when(1).expect("JQ has a condition");
+        let ok = Payload::Json(json!({ "ok": true }));
+        let not_ok = Payload::Json(json!({ "ok": false }));
+        assert_eq!(Ok(true), when.holds(&[Some(&ok)], None));
+        assert_eq!(Ok(false), when.holds(&[Some(&not_ok)], None));
+        assert_eq!(Ok(false), when.holds(&[None], None));
+
+        reject_config_with(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "JQ",
+                        "type": "jq",
+                        "when": "nope!"
+                    }
+                ]
+            }"#,
+            "failed checking configuration: in node `JQ` of type `jq`: invalid `when`: invalid filter",
+        );
+    }
+
+    #[test]
+    fn config_phases() {
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let config = accept_config(
+            r#"{
+                "nodes": [
+                    { "name": "ANY", "type": "jq" },
+                    {
+                        "name": "LATE",
+                        "type": "jq",
+                        "phases": ["response_headers", "response_body"]
+                    }
+                ]
+            }"#,
+        );
+        assert!(config.runs_in_phase(0, Phase::HttpRequestHeaders));
+        assert!(!config.runs_in_phase(1, Phase::HttpRequestHeaders));
+        assert!(config.runs_in_phase(1, Phase::HttpResponseBody));
+        assert!(config.phase_has_work(Phase::HttpResponseBody));
+
+        reject_config_with(
+            r#"{ "nodes": [ { "name": "JQ", "type": "jq", "phases": ["response"] } ] }"#,
+            "failed checking configuration: in node `JQ` of type `jq`: \
+             invalid `phases`: unknown phase \"response\"",
+        );
+        reject_config_with(
+            r#"{ "nodes": [ { "name": "JQ", "type": "jq", "phases": [] } ] }"#,
+            "failed checking configuration: in node `JQ` of type `jq`: \
+             invalid `phases`: must be a non-empty array of phase names",
+        );
+    }
+
+    #[test]
+    fn config_transforms() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let config = Config::new(
+            br#"{
+                "jq_prelude": "def wrap: { data: . };",
+                "nodes": [
+                    {
+                        "name": "JQ",
+                        "type": "jq",
+                        "inputs": { "body": "request.body", "headers": "request.headers" },
+                        "transforms": { "headers": ".[\"x-user\"] | wrap" }
+                    }
+                ]
+            }"#
+            .to_vec(),
+            &declare_implicits(),
+        )
+        .unwrap();
+        let i = config.node_count() - 1;
+        let transforms = config.get_transforms(i);
+        assert_eq!(2, transforms.len());
+        assert!(transforms[0].is_none());
+
+        let headers = Payload::Json(json!({ "x-user": "ann" }));
+        let transform = transforms[1].as_ref().unwrap();
+        assert_eq!(Ok(json!({ "data": "ann" })), transform(&headers));
+
+        reject_config_with(
+            r#"{ "nodes": [ { "name": "JQ", "type": "jq", "input": "request.body",
+                              "transforms": { "body": "." } } ] }"#,
+            "failed checking configuration: in node `JQ` of type `jq`: \
+             invalid `transforms`: `body` is not an input port of the node",
+        );
+        reject_config_with(
+            r#"{ "nodes": [ { "name": "JQ", "type": "jq", "inputs": { "b": "request.body" },
+                              "transforms": { "b": 1 } } ] }"#,
+            "failed checking configuration: in node `JQ` of type `jq`: \
+             invalid `transforms`: the transform of `b` must be a string",
+        );
+        reject_config_with(
+            r#"{ "nodes": [ { "name": "JQ", "type": "jq", "transforms": [] } ] }"#,
+            "failed checking configuration: in node `JQ` of type `jq`: \
+             invalid `transforms`: must be an object",
+        );
+    }
+
+    #[test]
+    fn config_exit_without_inputs() {
+        nodes::register_node("exit", Box::new(nodes::exit::ExitFactory {}));
+        let exit_only = |strict: bool| {
+            format!(
+                r#"{{
+                    "strict": {strict},
+                    "nodes": [
+                        {{
+                            "name": "EXIT",
+                            "type": "exit",
+                            "status": 403
+                        }}
+                    ]
+                }}"#
+            )
+        };
+
+        let config = accept_config(&exit_only(false));
+        assert_eq!(
+            vec!["node `EXIT` of type `exit` has no connected inputs \
+                  and would produce an empty response"
+                .to_string()],
+            config.warnings()
+        );
+        reject_config_with(
+            &exit_only(true),
+            "failed checking configuration: node `EXIT` of type `exit` has no connected inputs \
+             and would produce an empty response",
+        );
+
+        let config = Config::builder()
+            .add_node("exit", "EXIT", BTreeMap::new())
+            .link("request.headers", "EXIT.headers")
+            .strict(true)
+            .build(&declare_implicits());
+        assert!(config.is_ok_and(|config| config.warnings().is_empty()));
+    }
+
+    #[test]
+    fn config_sink_to_implicit() {
+        nodes::register_node("property", Box::new(nodes::property::PropertyFactory {}));
+        let set_property = |strict: bool| {
+            format!(
+                r#"{{
+                    "strict": {strict},
+                    "nodes": [
+                        {{
+                            "name": "SET",
+                            "type": "property",
+                            "property": "my.property",
+                            "mode": "set",
+                            "input": "request.body",
+                            "output": "response.body"
+                        }}
+                    ]
+                }}"#
+            )
+        };
+
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        let config = Config::new(set_property(false).into_bytes(), &declare_implicits()).unwrap();
+        assert_eq!(
+            vec![
+                "node `SET` only performs a side effect and produces no data, \
+                  but is connected to `response.body`"
+                    .to_string()
+            ],
+            config.warnings()
+        );
+        reject_config_with(
+            &set_property(true),
+            "failed checking configuration: node `SET` only performs a side effect \
+             and produces no data, but is connected to `response.body`",
+        );
+    }
+
+    #[test]
+    fn config_phase_has_work() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+        let config_bytes = br#"{
+            "nodes": [ {
+                "name": "JQ",
+                "type": "jq",
+                "input": "request.body",
+                "output": "response.headers",
+                "jq": "{}"
+            } ]
+        }"#;
+
+        // without the phases given by the host, only the nodes tell
+        let config = Config::new(config_bytes.to_vec(), &declare_implicits()).unwrap();
+        assert!(!config.phase_has_work(Phase::HttpRequestBody));
+        assert!(config.phase_has_work(Phase::HttpRequestHeaders));
+
+        let implicits: Vec<ImplicitNode> = declare_implicits()
+            .into_iter()
+            .map(|inode| match inode.name() {
+                "request" => inode.in_phase(Phase::HttpRequestBody, &["body"], &[]),
+                "response" => inode
+                    .in_phase(Phase::HttpResponseHeaders, &[], &["headers", "body"])
+                    .in_phase(Phase::HttpResponseBody, &[], &["body"]),
+                _ => inode,
+            })
+            .collect();
+        let config = Config::new(config_bytes.to_vec(), &implicits).unwrap();
+
+        assert!(config.phase_has_work(Phase::HttpRequestBody));
+        assert!(config.phase_has_work(Phase::HttpResponseHeaders));
+        assert!(config.phase_has_work(Phase::HttpRequestHeaders));
+        assert!(!config.phase_has_work(Phase::HttpResponseBody));
+        assert!(!config.phase_has_work(Phase::HttpCallResponse));
+        assert!(!config.phase_has_work(Phase::Tick));
+    }
+
+    #[test]
+    fn config_aliases() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+        let implicits = declare_implicits();
+
+        let jq_config = |input: &str, output: &str, aliases: &str| {
+            format!(
+                r#"{{
+                    "aliases": {{ {aliases} }},
+                    "nodes": [ {{
+                        "name": "JQ",
+                        "type": "jq",
+                        "input": "{input}",
+                        "output": "{output}",
+                        "jq": "."
+                    }} ]
+                }}"#
+            )
+        };
+
+        let aliased = jq_config(
+            "req.body",
+            "resp.body",
+            r#""req": "request", "resp": "response""#,
+        );
+        let canonical = jq_config("request.body", "response.body", "");
+        assert_eq!(
+            Config::new(aliased.into_bytes(), &implicits).unwrap(),
+            Config::new(canonical.into_bytes(), &implicits).unwrap()
+        );
+
+        let config = Config::builder()
+            .add_node("jq", "JQ", BTreeMap::from([("jq".into(), ".".into())]))
+            .alias("req", "request")
+            .link("req.body", "JQ")
+            .link("JQ", "response.body")
+            .build(&implicits);
+        assert!(config.is_ok());
+
+        reject_config_with(
+            &jq_config("request.body", "response.body", r#""request": "response""#),
+            "failed checking configuration: alias `request` is the name of an implicit node",
+        );
+        reject_config_with(
+            &jq_config("request.body", "response.body", r#""j": "JQ""#),
+            "failed checking configuration: alias `j` refers to `JQ`, which is not an implicit node",
+        );
+        reject_config_with(
+            &jq_config("request.body", "response.body", r#""JQ": "request""#),
+            "failed checking configuration: in node `JQ` of type `jq`: cannot use reserved node name",
+        );
+    }
+
+    #[test]
+    fn config_jq_prelude() {
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let config = |prelude: &str| {
+            format!(
+                r#"{{
+                    "jq_prelude": "{prelude}",
+                    "nodes": [
+                        {{ "name": "A", "type": "jq", "jq": "1 | double" }},
+                        {{ "name": "B", "type": "jq", "jq": "2 | double", "input": "A" }}
+                    ]
+                }}"#
+            )
+        };
+
+        accept_config(&config("def double: . * 2;"));
+
+        // without the prelude, `double` is not defined
+        reject_config_with(
+            &config(""),
+            "failed checking configuration: in node `A` of type `jq`: filter compilation failed",
+        );
+
+        // an invalid prelude is reported once, not for each node
+        let err = Config::new(config("def double: nope;").into_bytes(), &[]).unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidJqPrelude(_)));
+    }
+
+    #[test]
+    fn config_deadline() {
+        let config = accept_config(r#"{ "nodes": [], "deadline_ms": 1500 }"#);
+        assert_eq!(config.deadline(), Some(Duration::from_millis(1500)));
+
+        let config = accept_config(r#"{ "nodes": [] }"#);
+        assert_eq!(config.deadline(), None);
+
+        reject_config_with(
+            r#"{ "nodes": [], "deadline_ms": 0 }"#,
+            "failed checking configuration: `deadline_ms` must be a positive integer",
+        );
+    }
+
+    #[test]
+    fn config_max_body_bytes() {
+        let config = accept_config(r#"{ "nodes": [], "max_body_bytes": 1048576 }"#);
+        assert_eq!(config.max_body_bytes(), Some(1048576));
+
+        let config = accept_config(r#"{ "nodes": [] }"#);
+        assert_eq!(config.max_body_bytes(), None);
+
+        reject_config_with(
+            r#"{ "nodes": [], "max_body_bytes": 0 }"#,
+            "failed checking configuration: `max_body_bytes` must be a positive integer",
+        );
+    }
+
+    #[test]
+    fn config_dedupe_calls() {
+        let config = accept_config(r#"{ "nodes": [], "dedupe_calls": true }"#);
+        assert!(config.dedupe_calls());
+
+        let config = accept_config(r#"{ "nodes": [] }"#);
+        assert!(!config.dedupe_calls());
+    }
+
+    #[test]
+    fn config_sniff_content_type() {
+        let config = accept_config(r#"{ "nodes": [], "sniff_content_type": true }"#);
+        assert!(config.sniff_content_type());
+
+        let config = accept_config(r#"{ "nodes": [] }"#);
+        assert!(!config.sniff_content_type());
+    }
+
+    #[test]
+    fn config_compress() {
+        let config = accept_config(r#"{ "nodes": [], "compress": "auto" }"#);
+        assert_eq!(Compress::Auto, config.compress());
+
+        let config = accept_config(r#"{ "nodes": [] }"#);
+        assert_eq!(Compress::Never, config.compress());
+
+        reject_config_with(
+            r#"{ "nodes": [], "compress": "br" }"#,
+            "failed checking configuration: invalid `compress` value `br`, \
+             expected auto, gzip or none",
+        );
+    }
+
+    #[test]
+    fn config_max_node_runs() {
+        let config = accept_config(r#"{ "nodes": [], "max_node_runs": 50 }"#);
+        assert_eq!(config.max_node_runs(), 50);
+
+        let config = accept_config(r#"{ "nodes": [] }"#);
+        assert_eq!(config.max_node_runs(), DEFAULT_MAX_NODE_RUNS);
+
+        reject_config_with(
+            r#"{ "nodes": [], "max_node_runs": 0 }"#,
+            "failed checking configuration: `max_node_runs` must be a positive integer",
+        );
+    }
+
+    #[test]
+    fn config_debug_filter() {
+        let config = accept_config(r#"{ "nodes": [] }"#);
+        assert_eq!(config.debug_nodes(), None);
+        assert!(config.debug_exclude().is_empty());
+
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+        let config = accept_config(
+            r#"{
+                "nodes": [
+                    { "name": "A", "type": "jq", "jq": "." },
+                    { "name": "B", "type": "jq", "jq": "." }
+                ],
+                "debug": true,
+                "debug_nodes": ["A"],
+                "debug_exclude": ["B"]
+            }"#,
+        );
+        assert_eq!(config.debug_nodes(), Some(&["A".to_string()][..]));
+        assert_eq!(config.debug_exclude(), &["B".to_string()]);
+
+        reject_config_with(
+            r#"{ "nodes": [], "debug_nodes": ["NOPE"] }"#,
+            "failed checking configuration: debug filter refers to unknown node `NOPE`",
+        );
+    }
+
+    #[test]
+    fn config_contending_implicit_inputs() {
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+        reject_config_with(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "MY_BODY",
+                        "type": "jq",
+                        "jq": "{}",
+                        "output": "response.body"
+                    },
+                    {
+                        "name": "OTHER_BODY",
+                        "type": "jq",
+                        "jq": "{}",
+                        "output": "response.body"
+                    }
+                ]
+            }"#,
+            "failed checking configuration: nodes `MY_BODY` and `OTHER_BODY` are both \
+             connected to `response.body`, which can only take one input",
+        );
+    }
+
+    #[test]
+    fn config_port_index() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let uc = deserialize_user_config(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "mycall",
+                        "type": "call",
+                        "url": "http://example.com",
+                        "inputs": {
+                            "1": "request.1"
+                        }
+                    },
+                    {
+                        "name": "jq1",
+                        "type": "jq",
+                        "jq": ".",
+                        "input": "mycall.2",
+                        "output": "response.0"
+                    }
+                ]
+            }"#,
+        );
+        let config = uc.into_config(&declare_implicits()).unwrap();
+
+        // mycall.headers <- request.headers
+        assert_eq!(config.graph.get_provider(4, 1), Some((0, 1)));
+        // jq1 <- mycall.error
+        assert_eq!(config.node_input_ports(5), ["mycall.error"]);
+        assert_eq!(config.graph.get_provider(5, 0), Some((4, 2)));
+        // response.body <- jq1
+        assert_eq!(config.graph.get_provider(3, 0), Some((5, 0)));
+
+        reject_config_with(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "mycall",
+                        "type": "call",
+                        "url": "http://example.com",
+                        "input": "request.3"
+                    }
+                ]
+            }"#,
+            "failed checking configuration: in node `mycall` of type `call`: \
+             invalid output port index request.3 (node has 3 output ports)",
+        );
+        reject_config_with(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "mycall",
+                        "type": "call",
+                        "url": "http://example.com",
+                        "inputs": {
+                            "7": "request.body"
+                        }
+                    }
+                ]
+            }"#,
+            "failed checking configuration: in node `mycall` of type `call`: \
+             invalid input port index mycall.7 (node has 3 input ports)",
+        );
+    }
+
+    #[test]
+    fn config_run_order() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let uc = deserialize_user_config(
+            r#"{
+                "nodes": [
+                    { "name": "second", "type": "jq", "input": "first" },
+                    { "name": "first", "type": "jq" },
+                    { "name": "third", "type": "jq", "input": "request.body" },
+                    { "name": "fourth", "type": "jq", "inputs": ["second", "third"] },
+                    { "name": "cycle_a", "type": "jq", "input": "cycle_b" },
+                    { "name": "cycle_b", "type": "jq", "input": "cycle_a" }
+                ]
+            }"#,
+        );
+        let config = uc.into_config(&declare_implicits()).unwrap();
+
+        let names: Vec<_> = config
+            .run_order()
+            .iter()
+            .map(|&i| config.get_node_name(i))
+            .collect();
+        assert_eq!(
+            names,
+            ["first", "second", "third", "fourth", "cycle_a", "cycle_b"]
+        );
+    }
+
+    #[test]
+    fn config_validate() {
+        nodes::register_builtin_nodes();
+        let implicits = declare_implicits();
+
+        let errors = Config::validate(
+            br#"{
+                "nodes": [
+                    { "name": "request", "type": "jq" },
+                    { "name": "a", "type": "nope" },
+                    { "name": "b", "type": "jq", "input": "c.value" },
+                    { "name": "b", "type": "jq" }
+                ]
+            }"#,
+            &implicits,
+        )
+        .unwrap_err();
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "failed checking configuration: in node `request` of type `jq`: cannot use reserved node name",
+                "failed checking configuration: in node `a` of type `nope`: unknown node type",
+                "failed checking configuration: multiple definitions of node `b`",
+                "failed checking configuration: in node `b` of type `jq`: unknown node in link: c.value",
+            ]
+        );
+
+        let errors = Config::validate(
+            br#"{
+                "nodes": [
+                    { "name": "a", "type": "jq", "input": "b" },
+                    { "name": "b", "type": "jq", "input": "a" },
+                    { "name": "c", "type": "jq", "input": "b" },
+                    { "name": "d", "type": "jq", "input": "request.body" }
+                ]
+            }"#,
+            &implicits,
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            vec![ConfigError::Cycle(vec!["a".into(), "b".into(), "c".into()])]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "failed checking configuration: \
+             nodes `a`, `b`, `c` are in a cycle or depend on one, so they can never run"
+        );
+
+        assert_eq!(
+            Ok(()),
+            Config::validate(
+                br#"{ "nodes": [ { "name": "a", "type": "jq", "input": "request.body" } ] }"#,
+                &implicits,
+            )
+        );
+
+        // attribute errors are found while building, one at a time
+        let errors = Config::validate(
+            br#"{
+                "nodes": [
+                    { "name": "b", "type": "rate_limit", "limit": 0 },
+                    { "name": "c", "type": "rate_limit", "limit": -1 }
+                ]
+            }"#,
+            &implicits,
+        )
+        .unwrap_err();
+        assert_eq!(1, errors.len());
+    }
+
+    #[test]
+    fn config_tick_period_with_inputs() {
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+        reject_config_with(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "TOKEN",
+                        "type": "call",
+                        "url": "http://example.com/token",
+                        "input": "request.body",
+                        "tick_period_ms": 30000
+                    }
+                ]
+            }"#,
+            "failed checking configuration: node `TOKEN` with `tick_period_ms` cannot have connected inputs",
+        );
+        reject_config_with(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "TOKEN",
+                        "type": "call",
+                        "url": "http://example.com/token",
+                        "tick_period_ms": "soon"
+                    }
+                ]
+            }"#,
+            "failed checking configuration: in node `TOKEN` of type `call`: `tick_period_ms` must be a positive integer",
+        );
+    }
+
+    struct IgnoreConfig {}
+    impl NodeConfig for IgnoreConfig {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn new_node_incompatible_config() {
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+
+        let err = nodes::new_node("call", &IgnoreConfig {}).err();
+        assert_eq!(err, Some("call: incompatible NodeConfig".to_string()));
+    }
+
+    #[derive(Default)]
+    struct Mock {}
+
+    #[mock_proxy_wasm_context]
+    impl Context for Mock {}
+
+    #[mock_proxy_wasm_http_context]
+    impl HttpContext for Mock {}
+
+    #[test]
+    fn build_nodes_unregistered_type() {
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+        nodes::register_node("ephemeral", Box::new(nodes::call::CallFactory {}));
+
+        let config = deserialize_user_config(
+            r#"{
+                "nodes": [
+                    { "name": "gone", "type": "ephemeral", "url": "http://example.com" },
+                    { "name": "after", "type": "jq", "input": "gone.headers" }
+                ]
+            }"#,
+        )
+        .into_config(&declare_implicits())
+        .unwrap();
+
+        nodes::unregister_node("ephemeral");
+
+        let built = config.build_nodes();
+        assert_eq!(built.len(), config.node_count());
+
+        // the placeholder fails rather than letting the request through
+        let input = Input {
+            data: &[InputPort::Unconnected; 3],
+            phase: Phase::HttpRequestHeaders,
+        };
+        let state = built[4].run(&Mock::default(), &input);
+        let State::Fail(ports) = state else {
+            panic!("expected State::Fail(...)");
+        };
+        assert_eq!(3, ports.len());
+        assert!(matches!(
+            &ports[0],
+            Some(Payload::Error(_, e)) if e.starts_with("failed building node gone")
+        ));
+    }
+
+    #[test]
+    fn convert_complete_example() {
+        let uc = deserialize_user_config(
+            r#"{
+                "nodes": [
+                    {
+                        "name": "jq1",
+                        "type": "jq",
+                        "input": "request.headers",
+                        "jq": "{ \"x-bar\": $request_headers[\"x-foo\"] }"
+                    },
+                    {
+                        "name": "mycall",
+                        "type": "call",
+                        "input": "jq1",
+                        "url": "http://example.com"
+                    },
+                    {
+                        "name": "jq2",
+                        "type": "jq",
+                        "inputs": {
+                            "$mycall": "mycall",
+                            "$request": "request.body"
+                        },
+                        "jq": "{ \"bee\": $mycall.bee, \"boo\": $request.boo }"
+                    }
+                ]
+            }"#,
+        );
+
+        nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
+        nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
+        nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
+
+        let implicits = declare_implicits();
+
+        let config = uc.into_config(&implicits).unwrap();
+        assert!(!config.debug);
+        assert_eq!(config.n_nodes, 7);
+        assert_eq!(config.n_implicits, 4);
+        assert_eq!(
+            config.node_list,
+            vec![
+                NodeInfo {
+                    name: "request".into(),
+                    node_type: "implicit".into(),
+                    node_config: Box::new(IgnoreConfig {}),
+                    tick_period: None,
+                    when: None,
+                    phases: None,
+                    transforms: vec![],
+                },
+                NodeInfo {
+                    name: "service_request".into(),
+                    node_type: "implicit".into(),
+                    node_config: Box::new(IgnoreConfig {}),
+                    tick_period: None,
+                    when: None,
+                    phases: None,
+                    transforms: vec![],
+                },
+                NodeInfo {
+                    name: "service_response".into(),
+                    node_type: "implicit".into(),
+                    node_config: Box::new(IgnoreConfig {}),
+                    tick_period: None,
+                    when: None,
+                    phases: None,
+                    transforms: vec![],
+                },
+                NodeInfo {
+                    name: "response".into(),
+                    node_type: "implicit".into(),
+                    node_config: Box::new(IgnoreConfig {}),
+                    tick_period: None,
+                    when: None,
+                    phases: None,
+                    transforms: vec![],
+                },
+                NodeInfo {
+                    name: "jq1".into(),
+                    node_type: "jq".into(),
+                    node_config: Box::new(IgnoreConfig {}),
+                    tick_period: None,
+                    when: None,
+                    phases: None,
+                    transforms: vec![],
+                },
+                NodeInfo {
+                    name: "mycall".into(),
+                    node_type: "call".into(),
+                    node_config: Box::new(IgnoreConfig {}),
+                    tick_period: None,
+                    when: None,
+                    phases: None,
+                    transforms: vec![],
+                },
+                NodeInfo {
+                    name: "jq2".into(),
+                    node_type: "jq".into(),
+                    node_config: Box::new(IgnoreConfig {}),
+                    tick_period: None,
+                    when: None,
+                    phases: None,
+                    transforms: vec![],
+                },
+            ]
+        );
+        let input_lists: &[&[Option<(usize, usize)>]] = &[
+            &[],
+            &[None, None, None],
+            &[],
+            &[None, None],
+            &[Some((0, 1))],
+            &[Some((4, 0)), None, None, None],
+            &[Some((5, 0)), Some((0, 0))],
+        ];
+        for (i, &input_list) in input_lists.iter().enumerate() {
+            let given: Vec<_> = input_list.iter().collect();
+            let computed: Vec<_> = config.graph.each_input(i).collect();
+            assert_eq!(given, computed);
+        }
+
+        let output_lists: &[&[&[(usize, usize)]]] = &[
+            &[&[(6, 1)], &[(4, 0)], &[]],
+            &[&[], &[]],
+            &[&[], &[]],
+            &[&[], &[]],
+            &[&[(5, 0)]],
+            &[&[(6, 0)], &[], &[]],
+            &[],
+        ];
+        for (i, &output_list) in output_lists.iter().enumerate() {
+            let given: Vec<_> = output_list.iter().collect();
+            let computed: Vec<_> = config.graph.each_output(i).collect();
+            assert_eq!(given, computed);
+        }
+
+        assert_eq!(config.node_input_ports(1), ["body", "headers", "query"]);
+        assert_eq!(config.node_output_ports(1), ["body", "headers"]);
+        assert_eq!(
+            config.node_input_ports(5),
+            ["body", "headers", "query", "trailers"]
+        );
+        assert_eq!(config.node_output_ports(5), ["body", "headers", "error"]);
+        assert_eq!(config.node_input_ports(6), ["$mycall", "$request"]);
+        assert!(config.node_output_ports(6).is_empty());
     }
 }
@@ -1,31 +1,121 @@
+use serde_json::Value;
+use std::cell::OnceCell;
+use std::rc::Rc;
+
 use crate::dependency_graph::DependencyGraph;
-use crate::payload::Payload;
+use crate::payload::{ErrorKind, Payload};
+
+/// A transform of the value read by an input port, producing the value
+/// the node gets instead. They are built by the configuration.
+pub type Transform = Rc<dyn Fn(&Payload) -> Result<Value, String>>;
 
 #[allow(clippy::enum_variant_names)]
-#[derive(PartialEq, Clone, Copy)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Phase {
     HttpRequestHeaders,
     HttpRequestBody,
     HttpResponseHeaders,
     HttpResponseBody,
     HttpCallResponse,
+    /// Carries the gRPC status code of the response.
+    GrpcCallResponse(u32),
+    Tick,
+}
+
+impl Phase {
+    /// The name of the phase, as seen by jq programs.
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::HttpRequestHeaders => "request_headers",
+            Phase::HttpRequestBody => "request_body",
+            Phase::HttpResponseHeaders => "response_headers",
+            Phase::HttpResponseBody => "response_body",
+            Phase::HttpCallResponse => "call_response",
+            Phase::GrpcCallResponse(_) => "grpc_call_response",
+            Phase::Tick => "tick",
+        }
+    }
+}
+
+/// The value of an input port of a node about to run.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InputPort<'a> {
+    /// No link is connected to the port.
+    Unconnected,
+    /// The port is connected, but its provider finished without
+    /// producing a value. Only nodes with optional inputs see this.
+    NotReady,
+    Value(&'a Payload),
+}
+
+impl<'a> InputPort<'a> {
+    pub fn payload(self) -> Option<&'a Payload> {
+        match self {
+            InputPort::Value(payload) => Some(payload),
+            InputPort::Unconnected | InputPort::NotReady => None,
+        }
+    }
 }
 
 pub struct Input<'a> {
-    pub data: &'a [Option<&'a Payload>],
+    pub data: &'a [InputPort<'a>],
     pub phase: Phase,
 }
 
+impl<'a> Input<'a> {
+    /// The value of an input port, if it has one.
+    pub fn get(&self, port: usize) -> Option<&'a Payload> {
+        self.data.get(port).and_then(|p| p.payload())
+    }
+
+    /// Whether a link is connected to an input port, regardless of
+    /// whether its provider produced a value.
+    pub fn is_connected(&self, port: usize) -> bool {
+        !matches!(self.data.get(port), None | Some(InputPort::Unconnected))
+    }
+
+    /// The values of all input ports, for nodes that do not care
+    /// why a port has no value.
+    pub fn payloads(&self) -> Vec<Option<&'a Payload>> {
+        self.data.iter().map(|p| p.payload()).collect()
+    }
+}
+
+/// A response produced by a node in place of the one from the upstream.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u32,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum State {
     Waiting(u32),
     Done(Vec<Option<Payload>>),
     Fail(Vec<Option<Payload>>),
+    /// Respond immediately, without running any further nodes
+    /// for the request.
+    ShortCircuit(Response),
 }
 
 pub struct Data {
     graph: DependencyGraph,
     states: Vec<Option<State>>,
+    /// Implicit nodes come first, and fill their ports over time,
+    /// so a missing port of theirs may still arrive later.
+    n_implicits: usize,
+    optional_inputs: Vec<bool>,
+    /// The `transforms` of the input ports of each node, and their
+    /// results, computed the first time the inputs are read.
+    transforms: Vec<Vec<Option<Transform>>>,
+    transformed: Vec<Vec<OnceCell<Payload>>>,
+    /// Whether each input port can be read, and the number of connected
+    /// input ports of each node that cannot be read yet. They are kept
+    /// up to date as providers change state, so that checking whether
+    /// a node is ready does not walk its inputs.
+    available: Vec<Vec<bool>>,
+    pending: Vec<usize>,
 }
 
 fn set_port(
@@ -52,14 +142,65 @@ where
 }
 
 impl Data {
-    pub fn new(graph: DependencyGraph) -> Data {
+    pub fn new(
+        graph: DependencyGraph,
+        n_implicits: usize,
+        optional_inputs: Vec<bool>,
+        transforms: Vec<Vec<Option<Transform>>>,
+    ) -> Data {
         let n = graph.number_of_nodes();
         let states = default_vec(n);
-        Data { graph, states }
+        let transformed = (0..n)
+            .map(|node| default_vec(graph.number_of_inputs(node)))
+            .collect();
+        let available = (0..n)
+            .map(|node| default_vec(graph.number_of_inputs(node)))
+            .collect();
+        let pending = (0..n)
+            .map(|node| graph.each_input(node).filter(|i| i.is_some()).count())
+            .collect();
+        Data {
+            graph,
+            states,
+            n_implicits,
+            optional_inputs,
+            transforms,
+            transformed,
+            available,
+            pending,
+        }
     }
 
     pub fn set(&mut self, node: usize, state: State) {
-        self.states[node] = Some(state);
+        self.states[node] = Some(self.check_outputs(node, state));
+        self.update_dependents(node);
+    }
+
+    /// Check that a node produced one value per output port. A `Done`
+    /// state of the wrong size is a bug in the node, so it is turned
+    /// into a failure instead of leaving ports silently without data.
+    /// Failures usually carry a single error, so they are padded.
+    pub fn check_outputs(&self, node: usize, state: State) -> State {
+        let n = self.graph.number_of_outputs(node);
+        match state {
+            State::Done(ports) if ports.len() != n => {
+                let msg = format!(
+                    "node {} produced {} values for {n} output ports",
+                    self.graph.node_name(node),
+                    ports.len()
+                );
+                log::error!("{msg}");
+
+                let mut ports = vec![Some(Payload::Error(ErrorKind::InternalError, msg))];
+                ports.resize_with(n.max(1), Default::default);
+                State::Fail(ports)
+            }
+            State::Fail(mut ports) if ports.len() < n => {
+                ports.resize_with(n, Default::default);
+                State::Fail(ports)
+            }
+            state => state,
+        }
     }
 
     pub fn fill_port(
@@ -78,8 +219,47 @@ impl Data {
                 Ok(())
             }
             Some(State::Waiting(_)) => Err("cannot force payload on a waiting node"),
+            Some(State::ShortCircuit(_)) => Err("cannot force payload on a short-circuited node"),
             Some(State::Done(ports)) => set_port(ports, port, payload),
             Some(State::Fail(ports)) => set_port(ports, port, payload),
+        }?;
+        self.update_dependents(node);
+        Ok(())
+    }
+
+    /// Whether input port of node `i` connected to output port `p` of
+    /// node `n` can be read, with or without a value.
+    fn is_available(&self, i: usize, n: usize, p: usize) -> bool {
+        match &self.states[n] {
+            Some(State::Done(ports)) => match ports[p] {
+                Some(_) => true,
+                // a user node that is Done will not produce it
+                // anymore, so an optional input is skipped;
+                // otherwise wait until the payload is available
+                None => self.optional_inputs[i] && n >= self.n_implicits,
+            },
+            Some(State::Waiting(_)) => false,
+            Some(State::Fail(_)) => false,
+            Some(State::ShortCircuit(_)) => false,
+            None => false,
+        }
+    }
+
+    /// Refresh the readiness of the inputs fed by node `n`,
+    /// after its state changed.
+    fn update_dependents(&mut self, n: usize) {
+        for (p, dependents) in self.graph.each_output(n).enumerate() {
+            for &(i, port) in dependents {
+                let now = self.is_available(i, n, p);
+                if self.available[i][port] != now {
+                    self.available[i][port] = now;
+                    if now {
+                        self.pending[i] -= 1;
+                    } else {
+                        self.pending[i] += 1;
+                    }
+                }
+            }
         }
     }
 
@@ -93,7 +273,7 @@ impl Data {
     pub fn fetch_port(&self, node: usize, port: usize) -> Option<&Payload> {
         match self.graph.get_provider(node, port) {
             Some((n, p)) => match self.states.get(n).unwrap() {
-                Some(State::Waiting(_)) => None,
+                Some(State::Waiting(_)) | Some(State::ShortCircuit(_)) => None,
                 Some(State::Done(ports)) | Some(State::Fail(ports)) => match ports.get(p) {
                     Some(Some(ref payload)) => Some(payload),
                     Some(None) => None,
@@ -105,6 +285,13 @@ impl Data {
         }
     }
 
+    fn fetch_output(&self, n: usize, p: usize) -> Option<&Payload> {
+        match &self.states[n] {
+            Some(State::Done(ports)) => ports[p].as_ref(),
+            _ => None,
+        }
+    }
+
     fn can_trigger(&self, i: usize, waiting: Option<u32>) -> bool {
         // This is intentionally written with all of the match arms
         // stated explicitly (instead of using _ catch-alls),
@@ -118,6 +305,8 @@ impl Data {
                 State::Done(_) => false,
                 // never retrigger Fail
                 State::Fail(_) => false,
+                // never retrigger ShortCircuit
+                State::ShortCircuit(_) => false,
                 State::Waiting(w) => match &waiting {
                     // we're waiting on the right id, allow triggering
                     Some(id) if w == id => true,
@@ -130,60 +319,245 @@ impl Data {
         }
     }
 
-    fn for_each_input<'a, T>(
-        &'a self,
-        i: usize,
-        f: impl for<'b> Fn(Option<&'a Payload>, &'b mut T),
-        mut t: T,
-    ) -> Option<T> {
-        for input in self.graph.each_input(i) {
-            // if input port is connected in the graph
-            match *input {
-                Some((n, p)) => {
-                    // check if other node is Done
-                    match &self.states[n] {
-                        Some(State::Done(ports)) => {
-                            // check if port has payload available
-                            match &ports[p] {
-                                // ok, has payload
-                                Some(payload) => f(Some(payload), &mut t),
-                                // no payload available
-                                None => return None,
-                            }
-                        }
-                        Some(State::Waiting(_)) => return None,
-                        Some(State::Fail(_)) => return None,
-                        None => return None,
-                    }
-                }
-                None => f(None, &mut t), // ok, port is not connected
+    /// The value read by an input port, once its transform is applied.
+    /// A transform that fails produces an error in place of the value,
+    /// as does an input that is an error already.
+    fn transform<'a>(&'a self, node: usize, port: usize, payload: &'a Payload) -> &'a Payload {
+        let transform = self
+            .transforms
+            .get(node)
+            .and_then(|t| t.get(port))
+            .and_then(Option::as_ref);
+        let Some(transform) = transform else {
+            return payload;
+        };
+
+        self.transformed[node][port].get_or_init(|| {
+            if let Payload::Error(..) = payload {
+                return payload.clone();
             }
-        }
+            match transform(payload) {
+                Ok(value) => Payload::Json(value),
+                Err(e) => Payload::Error(
+                    ErrorKind::ConfigError,
+                    format!(
+                        "transform of input port `{}` failed: {e}",
+                        self.graph.input_names(node)[port]
+                    ),
+                ),
+            }
+        })
+    }
 
-        Some(t)
+    /// The nodes waiting on a call, with the token of the call.
+    pub fn waiting_nodes(&self) -> Vec<(usize, u32)> {
+        self.states
+            .iter()
+            .enumerate()
+            .filter_map(|(i, state)| match state {
+                Some(State::Waiting(id)) => Some((i, *id)),
+                _ => None,
+            })
+            .collect()
     }
 
-    pub fn get_inputs_for(
-        &self,
-        node: usize,
-        waiting: Option<u32>,
-    ) -> Option<Vec<Option<&Payload>>> {
-        if !self.can_trigger(node, waiting) {
+    pub fn get_inputs_for(&self, node: usize, waiting: Option<u32>) -> Option<Vec<InputPort<'_>>> {
+        if self.pending[node] > 0 || !self.can_trigger(node, waiting) {
             return None;
         }
 
-        // Check first that all connected inputs are ready
-        self.for_each_input(node, |_, _| (), &mut ())?;
+        // all connected inputs are available, so each of them either
+        // has a value or is an optional input without one
+        let inputs = self
+            .graph
+            .each_input(node)
+            .enumerate()
+            .map(|(port, input)| {
+                let Some((n, p)) = *input else {
+                    return InputPort::Unconnected;
+                };
+                match self.fetch_output(n, p) {
+                    Some(payload) => InputPort::Value(self.transform(node, port, payload)),
+                    None => InputPort::NotReady,
+                }
+            });
+        Some(inputs.collect())
+    }
+}
 
-        // If so, allocate the vector with the result.
-        let n = self.graph.number_of_inputs(node);
-        self.for_each_input(
-            node,
-            |payload, v: &mut Vec<Option<&Payload>>| match payload {
-                Some(p) => v.push(Some(p)),
-                None => v.push(None),
-            },
-            Vec::with_capacity(n),
-        )
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    // implicit `request`, then user nodes `A` and `B` feeding `C`
+    fn new_data(optional: bool) -> Data {
+        let mut graph = DependencyGraph::new(
+            names(&["request", "A", "B", "C"]),
+            vec![vec![], vec![], vec![], names(&["a", "b", "r"])],
+            vec![
+                names(&["body"]),
+                names(&["value"]),
+                names(&["value"]),
+                vec![],
+            ],
+        );
+        graph.add("A", "value", "C", "a").unwrap();
+        graph.add("B", "value", "C", "b").unwrap();
+        graph.add("request", "body", "C", "r").unwrap();
+        Data::new(graph, 1, vec![false, false, false, optional], vec![])
+    }
+
+    #[test]
+    fn optional_inputs() {
+        let body = Payload::Raw(b"body".to_vec());
+        let b = Payload::Raw(b"b".to_vec());
+
+        for optional in [false, true] {
+            let mut data = new_data(optional);
+            data.fill_port(0, 0, body.clone()).unwrap();
+            data.set(1, State::Done(vec![None]));
+            assert!(data.get_inputs_for(3, None).is_none());
+
+            data.set(2, State::Done(vec![Some(b.clone())]));
+            let inputs = data.get_inputs_for(3, None);
+            if optional {
+                assert_eq!(
+                    Some(vec![
+                        InputPort::NotReady,
+                        InputPort::Value(&b),
+                        InputPort::Value(&body)
+                    ]),
+                    inputs
+                );
+            } else {
+                assert_eq!(None, inputs);
+            }
+        }
+
+        // a missing port of an implicit node may still be filled
+        let mut data = new_data(true);
+        data.set(1, State::Done(vec![None]));
+        data.set(2, State::Done(vec![Some(b.clone())]));
+        data.set(0, State::Done(vec![None]));
+        assert!(data.get_inputs_for(3, None).is_none());
+    }
+
+    #[test]
+    fn readiness() {
+        let b = Payload::Raw(b"b".to_vec());
+        let mut data = new_data(false);
+        assert_eq!(3, data.pending[3]);
+
+        data.set(1, State::Waiting(1));
+        data.set(2, State::Done(vec![Some(b.clone())]));
+        data.fill_port(0, 0, b.clone()).unwrap();
+        assert_eq!(1, data.pending[3]);
+        assert!(data.get_inputs_for(3, None).is_none());
+
+        data.set(1, State::Done(vec![Some(b.clone())]));
+        assert_eq!(0, data.pending[3]);
+        assert!(data.get_inputs_for(3, None).is_some());
+
+        // a node waiting on a call is resumed with the same inputs
+        data.set(3, State::Waiting(7));
+        assert!(data.get_inputs_for(3, None).is_none());
+        assert!(data.get_inputs_for(3, Some(6)).is_none());
+        assert_eq!(
+            Some(vec![
+                InputPort::Value(&b),
+                InputPort::Value(&b),
+                InputPort::Value(&b)
+            ]),
+            data.get_inputs_for(3, Some(7))
+        );
+    }
+
+    #[test]
+    fn transforms() {
+        let transform = |f: fn(&Payload) -> Result<Value, String>| Some(Rc::new(f) as Transform);
+        let mut data = new_data(true);
+        data.transforms = vec![
+            vec![],
+            vec![],
+            vec![],
+            vec![
+                transform(|p| Ok(json!({ "user": p.to_json()?["name"] }))),
+                transform(|_| Ok(Value::Null)),
+                transform(|_| Err("cannot index number with \"x\"".into())),
+            ],
+        ];
+
+        let body = Payload::Raw(b"not json".to_vec());
+        data.fill_port(0, 0, body).unwrap();
+        data.set(
+            1,
+            State::Done(vec![Some(Payload::Json(json!({ "name": "ann" })))]),
+        );
+        data.set(2, State::Done(vec![Some(Payload::Json(json!(1)))]));
+
+        let inputs = data.get_inputs_for(3, None).unwrap();
+        let user = Payload::Json(json!({ "user": "ann" }));
+        assert_eq!(InputPort::Value(&user), inputs[0]);
+        assert_eq!(InputPort::Value(&Payload::Json(Value::Null)), inputs[1]);
+        assert!(matches!(
+            inputs[2],
+            InputPort::Value(Payload::Error(ErrorKind::ConfigError, e))
+                if e.starts_with("transform of input port `r` failed")
+        ));
+
+        // ports without a value are left alone
+        let mut data = new_data(true);
+        data.transforms = vec![
+            vec![],
+            vec![],
+            vec![],
+            vec![transform(|_| Ok(json!(1))), None, None],
+        ];
+        data.fill_port(0, 0, Payload::Raw(b"body".to_vec()))
+            .unwrap();
+        data.set(1, State::Done(vec![None]));
+        data.set(2, State::Done(vec![None]));
+        let inputs = data.get_inputs_for(3, None).unwrap();
+        assert_eq!(InputPort::NotReady, inputs[0]);
+    }
+
+    #[test]
+    fn check_outputs() {
+        let data = new_data(false);
+        let b = Payload::Raw(b"b".to_vec());
+
+        let done = State::Done(vec![Some(b.clone())]);
+        assert_eq!(
+            State::Done(vec![Some(b.clone())]),
+            data.check_outputs(1, done)
+        );
+
+        // a node producing more or fewer values than it has ports fails
+        let done = State::Done(vec![Some(b.clone()), None]);
+        assert_eq!(
+            State::Fail(vec![Some(Payload::Error(
+                ErrorKind::InternalError,
+                "node A produced 2 values for 1 output ports".into()
+            ))]),
+            data.check_outputs(1, done)
+        );
+        assert!(matches!(
+            data.check_outputs(2, State::Done(vec![])),
+            State::Fail(ports) if ports.len() == 1
+        ));
+
+        // failures are padded
+        assert_eq!(
+            State::Fail(vec![]),
+            data.check_outputs(3, State::Fail(vec![]))
+        );
+        let mut data = new_data(false);
+        data.set(0, State::Fail(vec![]));
+        assert_eq!(Ok(&State::Fail(vec![None])), data.get_state(0));
     }
 }
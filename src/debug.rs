@@ -1,10 +1,10 @@
 use crate::config::Config;
-use crate::data::State;
-use crate::payload::Payload;
+use crate::data::{InputPort, State};
+use crate::payload::{ErrorKind, Payload};
 
 use serde::Serialize;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::time::{Duration, SystemTime};
 
 pub enum RunMode {
@@ -12,10 +12,64 @@ pub enum RunMode {
     Resume,
 }
 
+/// Where the trace is reported.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TraceSink {
+    /// Replace the response body with the trace.
+    Body,
+    /// Write the trace to the proxy log, leaving the response intact.
+    Log,
+}
+
+/// Which nodes are recorded in the trace. By default, all of them are.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct NodeFilter {
+    include: Option<HashSet<String>>,
+    exclude: HashSet<String>,
+}
+
+impl NodeFilter {
+    pub fn new(include: Option<&[String]>, exclude: &[String]) -> NodeFilter {
+        NodeFilter {
+            include: include.map(|names| names.iter().cloned().collect()),
+            exclude: exclude.iter().cloned().collect(),
+        }
+    }
+
+    /// Apply the parameters given in the trace header after the mode,
+    /// as in `on; nodes=a,b; exclude=c`. A `nodes` list replaces the
+    /// configured one, while an `exclude` list adds to it.
+    pub fn apply_header_params(&mut self, params: &str) {
+        let names = |list: &str| -> HashSet<String> {
+            list.split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+
+        for param in params.split(';') {
+            match param.split_once('=') {
+                Some((key, list)) if key.trim() == "nodes" => self.include = Some(names(list)),
+                Some((key, list)) if key.trim() == "exclude" => self.exclude.extend(names(list)),
+                _ => {}
+            }
+        }
+    }
+
+    fn traces(&self, name: &str) -> bool {
+        self.include
+            .as_ref()
+            .map_or(true, |names| names.contains(name))
+            && !self.exclude.contains(name)
+    }
+}
+
 pub enum DataMode {
     Done,
     Waiting,
     Fail,
+    ShortCircuit,
 }
 
 struct RunOperation {
@@ -24,6 +78,7 @@ struct RunOperation {
     action: RunMode,
     at: Option<Duration>,
     duration: Option<Duration>,
+    request: Option<Value>,
 }
 
 #[derive(Serialize)]
@@ -45,12 +100,18 @@ enum Operation {
 }
 
 pub struct Debug {
-    trace: bool,
+    trace: Option<TraceSink>,
+    filter: NodeFilter,
     operations: Vec<Operation>,
     node_types: HashMap<String, String>,
     orig_response_body_content_type: Option<String>,
     start_time: SystemTime,
     node_starts: HashMap<String, SystemTime>,
+    /// The sizes in bytes of the bodies read or written,
+    /// by implicit node name.
+    body_sizes: BTreeMap<&'static str, usize>,
+    /// Pretty-print the trace, for reading it by hand.
+    pretty: bool,
 }
 
 impl State {
@@ -59,6 +120,7 @@ impl State {
             State::Done(_) => DataMode::Done,
             State::Waiting(_) => DataMode::Waiting,
             State::Fail(_) => DataMode::Fail,
+            State::ShortCircuit(_) => DataMode::ShortCircuit,
         }
     }
 }
@@ -67,6 +129,10 @@ fn payloads_to_values(payloads: &[Option<Payload>], default_type: &str) -> Vec<P
     payloads
         .iter()
         .map(|p| match p {
+            Some(Payload::Error(kind, message)) => PortValue {
+                data_type: "fail".into(),
+                value: Some(serde_json::json!({ "kind": kind, "message": message })),
+            },
             Some(payload) => match payload.to_json() {
                 Ok(v) => PortValue {
                     data_type: payload.content_type().unwrap_or(default_type).to_string(),
@@ -74,7 +140,7 @@ fn payloads_to_values(payloads: &[Option<Payload>], default_type: &str) -> Vec<P
                 },
                 Err(e) => PortValue {
                     data_type: "fail".into(),
-                    value: Some(serde_json::json!(e)),
+                    value: Some(serde_json::json!({ "kind": ErrorKind::ParseError, "message": e })),
                 },
             },
             None => PortValue {
@@ -94,16 +160,23 @@ impl Debug {
 
         Debug {
             node_types,
-            trace: false,
+            trace: None,
+            filter: NodeFilter::new(config.debug_nodes(), config.debug_exclude()),
             operations: vec![],
             orig_response_body_content_type: None,
             start_time: SystemTime::now(),
             node_starts: HashMap::new(),
+            body_sizes: BTreeMap::new(),
+            pretty: false,
         }
     }
 
+    fn traces(&self, name: &str) -> bool {
+        self.trace.is_some() && self.filter.traces(name)
+    }
+
     pub fn set_data(&mut self, name: &str, state: &State) {
-        if self.trace {
+        if self.traces(name) {
             self.operations.push(Operation::Set(SetOperation {
                 node_name: name.to_string(),
                 status: state.to_data_mode(),
@@ -111,14 +184,27 @@ impl Debug {
                     State::Waiting(_) => vec![],
                     State::Done(p) => payloads_to_values(p, "raw"),
                     State::Fail(p) => payloads_to_values(p, "fail"),
+                    State::ShortCircuit(response) => vec![PortValue {
+                        data_type: "status".into(),
+                        value: Some(response.status.into()),
+                    }],
                 },
                 at: Some(self.start_time.elapsed().unwrap()),
             }));
         }
     }
 
-    pub fn run(&mut self, name: &str, _args: &[Option<&Payload>], state: &State, action: RunMode) {
-        if self.trace {
+    /// Record a run of a node, along with the outgoing
+    /// request it made, if any.
+    pub fn run(
+        &mut self,
+        name: &str,
+        _args: &[InputPort],
+        state: &State,
+        action: RunMode,
+        request: Option<Value>,
+    ) {
+        if self.traces(name) {
             let node_type = self.node_types.get(name).expect("node exists");
 
             let mut at = None;
@@ -139,12 +225,20 @@ impl Debug {
                 node_type: node_type.to_string(),
                 at,
                 duration,
+                request,
             }));
 
             self.set_data(name, state);
         }
     }
 
+    /// Record the size of a body, regardless of which nodes are traced.
+    pub fn body_size(&mut self, name: &'static str, size: usize) {
+        if self.trace.is_some() {
+            self.body_sizes.insert(name, size);
+        }
+    }
+
     pub fn save_response_body_content_type(&mut self, ct: Option<String>) {
         self.orig_response_body_content_type = ct;
     }
@@ -153,11 +247,20 @@ impl Debug {
         &self.orig_response_body_content_type
     }
 
-    pub fn set_tracing(&mut self, enable: bool) {
-        self.trace = enable;
+    pub fn set_tracing(&mut self, sink: Option<TraceSink>) {
+        self.trace = sink;
+    }
+
+    /// Apply the parameters given in the trace header after the mode:
+    /// the node filters, and `pretty` to pretty-print the trace.
+    pub fn apply_header_params(&mut self, header_params: &str) {
+        self.filter.apply_header_params(header_params);
+        self.pretty = header_params
+            .split(';')
+            .any(|param| param.trim() == "pretty");
     }
 
-    pub fn is_tracing(&self) -> bool {
+    pub fn trace_sink(&self) -> Option<TraceSink> {
         self.trace
     }
 
@@ -165,6 +268,7 @@ impl Debug {
         #[derive(Serialize)]
         struct TraceAction<'a> {
             action: &'static str,
+            #[serde(skip_serializing_if = "str::is_empty")]
             name: &'a str,
             #[serde(skip_serializing_if = "Option::is_none")]
             r#type: Option<&'a str>,
@@ -174,6 +278,10 @@ impl Debug {
             at: Option<f32>,
             #[serde(skip_serializing_if = "Option::is_none")]
             duration: Option<f32>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            request: Option<&'a Value>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            body_sizes: Option<&'a BTreeMap<&'static str, usize>>,
         }
 
         let mut actions: Vec<TraceAction> = vec![];
@@ -190,6 +298,8 @@ impl Debug {
                     values: None,
                     at: run.at.map(|d| d.as_secs_f32()),
                     duration: run.duration.map(|d| d.as_secs_f32()),
+                    request: run.request.as_ref(),
+                    body_sizes: None,
                 },
                 Operation::Set(set) => match set.status {
                     DataMode::Done => TraceAction {
@@ -199,6 +309,8 @@ impl Debug {
                         values: Some(&set.values),
                         at: set.at.map(|d| d.as_secs_f32()),
                         duration: None,
+                        request: None,
+                        body_sizes: None,
                     },
                     DataMode::Waiting => TraceAction {
                         action: "wait",
@@ -207,6 +319,8 @@ impl Debug {
                         values: None,
                         at: set.at.map(|d| d.as_secs_f32()),
                         duration: None,
+                        request: None,
+                        body_sizes: None,
                     },
                     DataMode::Fail => TraceAction {
                         action: "fail",
@@ -215,11 +329,139 @@ impl Debug {
                         values: Some(&set.values),
                         at: set.at.map(|d| d.as_secs_f32()),
                         duration: None,
+                        request: None,
+                        body_sizes: None,
+                    },
+                    DataMode::ShortCircuit => TraceAction {
+                        action: "short_circuit",
+                        name: &set.node_name,
+                        r#type: None,
+                        values: Some(&set.values),
+                        at: set.at.map(|d| d.as_secs_f32()),
+                        duration: None,
+                        request: None,
+                        body_sizes: None,
                     },
                 },
             });
         }
 
-        serde_json::json!(actions).to_string()
+        if !self.body_sizes.is_empty() {
+            actions.push(TraceAction {
+                action: "summary",
+                name: "",
+                r#type: None,
+                values: None,
+                at: Some(self.start_time.elapsed().unwrap().as_secs_f32()),
+                duration: None,
+                request: None,
+                body_sizes: Some(&self.body_sizes),
+            });
+        }
+
+        let trace = serde_json::json!(actions);
+        if self.pretty {
+            serde_json::to_string_pretty(&trace).expect("serializable trace")
+        } else {
+            trace.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn node_filter() {
+        let all = NodeFilter::default();
+        assert!(all.traces("a"));
+
+        let mut filter = NodeFilter::new(Some(&["a".to_string(), "b".to_string()]), &[]);
+        assert!(filter.traces("a"));
+        assert!(!filter.traces("c"));
+
+        filter.apply_header_params(" exclude=b ; bogus");
+        assert!(filter.traces("a"));
+        assert!(!filter.traces("b"));
+
+        filter.apply_header_params("nodes= c, d");
+        assert!(!filter.traces("a"));
+        assert!(filter.traces("c"));
+        assert!(filter.traces("d"));
+    }
+
+    #[test]
+    fn trace_call_request() {
+        let mut debug = Debug {
+            trace: Some(TraceSink::Body),
+            filter: NodeFilter::default(),
+            operations: vec![],
+            node_types: HashMap::from([("mycall".to_string(), "call".to_string())]),
+            orig_response_body_content_type: None,
+            start_time: SystemTime::now(),
+            node_starts: HashMap::new(),
+            body_sizes: BTreeMap::new(),
+            pretty: false,
+        };
+        let request = serde_json::json!({ "method": "GET", "url": "http://example.com/" });
+
+        debug.run(
+            "mycall",
+            &[],
+            &State::Waiting(1),
+            RunMode::Run,
+            Some(request.clone()),
+        );
+        debug.run(
+            "mycall",
+            &[],
+            &State::Done(vec![None]),
+            RunMode::Resume,
+            None,
+        );
+
+        let trace: Value = serde_json::from_str(&debug.get_trace()).unwrap();
+        assert_eq!(request, trace[0]["request"]);
+        assert_eq!("resume", trace[2]["action"]);
+        assert!(trace[2].get("request").is_none());
+    }
+
+    #[test]
+    fn trace_body_sizes() {
+        let mut debug = Debug {
+            trace: Some(TraceSink::Log),
+            filter: NodeFilter::new(Some(&[]), &[]),
+            operations: vec![],
+            node_types: HashMap::from([("mycall".to_string(), "call".to_string())]),
+            orig_response_body_content_type: None,
+            start_time: SystemTime::now(),
+            node_starts: HashMap::new(),
+            body_sizes: BTreeMap::new(),
+            pretty: false,
+        };
+
+        debug.run("mycall", &[], &State::Done(vec![None]), RunMode::Run, None);
+        debug.body_size("request", 12);
+        debug.body_size("response", 0);
+
+        let compact = debug.get_trace();
+        debug.apply_header_params(" pretty");
+        let pretty = debug.get_trace();
+        assert!(!compact.contains('\n'));
+        assert!(pretty.contains("\n  {"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&compact).unwrap(),
+            serde_json::from_str::<Value>(&pretty).unwrap()
+        );
+
+        let trace: Value = serde_json::from_str(&debug.get_trace()).unwrap();
+        assert_eq!(1, trace.as_array().unwrap().len());
+        assert_eq!("summary", trace[0]["action"]);
+        assert!(trace[0].get("name").is_none());
+        assert_eq!(
+            serde_json::json!({ "request": 12, "response": 0 }),
+            trace[0]["body_sizes"]
+        );
     }
 }
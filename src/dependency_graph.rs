@@ -1,3 +1,7 @@
+use crate::config::{Config, ConfigError};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct DependencyGraph {
     node_names: Vec<String>,
@@ -53,6 +57,10 @@ impl DependencyGraph {
         }
     }
 
+    pub fn node_name(&self, node: usize) -> &str {
+        &self.node_names[node]
+    }
+
     pub fn number_of_nodes(&self) -> usize {
         self.node_names.len()
     }
@@ -65,6 +73,15 @@ impl DependencyGraph {
         self.input_names[node].len()
     }
 
+    pub fn input_names(&self, node: usize) -> &[String] {
+        &self.input_names[node]
+    }
+
+    #[cfg(test)]
+    pub fn output_names(&self, node: usize) -> &[String] {
+        &self.output_names[node]
+    }
+
     fn add_dependent(&mut self, node: usize, port: usize, entry: (usize, usize)) {
         let node_list = &mut self.dependents;
         let port_list = node_list.get_mut(node).expect("valid node index");
@@ -77,7 +94,7 @@ impl DependencyGraph {
         node: usize,
         port: usize,
         entry: (usize, usize),
-    ) -> Result<(), String> {
+    ) -> Result<(), ConfigError> {
         let node_list = &mut self.providers;
         let port_list = node_list.get_mut(node).expect("valid node index");
         match *port_list.get(port).expect("valid port index") {
@@ -95,14 +112,13 @@ impl DependencyGraph {
         p: usize,
         oth_n: usize,
         oth_p: usize,
-    ) -> Result<(), String> {
-        let this_node = self.node_names.get(n).expect("valid node");
-        let this_port = self.output_names[n].get(p).expect("valid port");
-        let other_node = self.node_names.get(oth_n).expect("valid node");
-        let other_port = self.output_names[oth_n].get(oth_p).expect("valid port");
-        Err(format!(
-            "{this_node}.{this_port} is already connected to {other_node}.{other_port}"
-        ))
+    ) -> Result<(), ConfigError> {
+        Err(ConfigError::AlreadyConnected {
+            node: self.node_names[n].clone(),
+            port: self.input_names[n][p].clone(),
+            other_node: self.node_names[oth_n].clone(),
+            other_port: self.output_names[oth_n][oth_p].clone(),
+        })
     }
 
     pub fn add(
@@ -111,7 +127,7 @@ impl DependencyGraph {
         src_port: &str,
         dst_node: &str,
         dst_port: &str,
-    ) -> Result<(), String> {
+    ) -> Result<(), ConfigError> {
         let (sn, sp) = find(src_node, src_port, &self.node_names, &self.output_names);
         let (dn, dp) = find(dst_node, dst_port, &self.node_names, &self.input_names);
         self.add_dependent(sn, sp, (dn, dp));
@@ -126,17 +142,110 @@ impl DependencyGraph {
         self.providers[node][port].is_some()
     }
 
+    /// Whether any input port of the node is connected.
+    pub fn has_providers(&self, node: usize) -> bool {
+        self.providers[node].iter().any(Option::is_some)
+    }
+
     pub fn get_provider(&self, node: usize, port: usize) -> Option<(usize, usize)> {
         self.providers[node][port]
     }
 
-    pub fn each_input(&self, node: usize) -> std::slice::Iter<Option<(usize, usize)>> {
+    pub fn each_input(&self, node: usize) -> std::slice::Iter<'_, Option<(usize, usize)>> {
         self.providers[node].iter()
     }
 
-    /// used in tests only
-    #[allow(dead_code)]
-    pub fn each_output(&self, node: usize) -> std::slice::Iter<Vec<(usize, usize)>> {
+    /// Order the nodes starting at `first` so that each one comes after
+    /// all of its providers in that range. Nodes that do not depend on
+    /// each other keep their relative order, so that the result only
+    /// depends on the graph and never on the data. Nodes in a cycle,
+    /// which can never trigger, are placed at the end.
+    pub fn stable_topological_order(&self, first: usize) -> Vec<usize> {
+        let mut order = self.acyclic_order(first);
+        for node in first..self.number_of_nodes() {
+            if !order.contains(&node) {
+                order.push(node);
+            }
+        }
+        order
+    }
+
+    /// The nodes starting at `first` which are in a cycle,
+    /// or which depend on a node in a cycle.
+    #[cfg(test)]
+    pub fn cyclic_nodes(&self, first: usize) -> Vec<usize> {
+        let order = self.acyclic_order(first);
+        (first..self.number_of_nodes())
+            .filter(|node| !order.contains(node))
+            .collect()
+    }
+
+    /// The stable topological order of the nodes starting at `first`,
+    /// leaving out the ones that cannot be ordered due to cycles.
+    fn acyclic_order(&self, first: usize) -> Vec<usize> {
+        let n = self.number_of_nodes();
+        let mut pending: Vec<usize> = vec![0; n];
+        for (node, count) in pending.iter_mut().enumerate().skip(first) {
+            *count = self.providers[node]
+                .iter()
+                .flatten()
+                .filter(|(provider, _)| *provider >= first)
+                .count();
+        }
+
+        let mut ready: BTreeSet<usize> = (first..n).filter(|&node| pending[node] == 0).collect();
+        let mut order = Vec::with_capacity(n - first);
+        while let Some(node) = ready.pop_first() {
+            order.push(node);
+            for &(dependent, _) in self.dependents[node].iter().flatten() {
+                if dependent < first {
+                    continue;
+                }
+                pending[dependent] -= 1;
+                if pending[dependent] == 0 {
+                    ready.insert(dependent);
+                }
+            }
+        }
+
+        order
+    }
+
+    /// A Graphviz DOT representation of the graph, with the type of each
+    /// node and the ports of each link. Implicit nodes are drawn as boxes.
+    pub fn to_dot(&self, config: &Config) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+
+        let mut dot = String::from("digraph datakit {\n");
+        for (node, name) in self.node_names.iter().enumerate() {
+            let attrs = match config.get_node_type(node) {
+                "implicit" => "shape=box".to_string(),
+                node_type => format!("label={}", quote(&format!("{name} ({node_type})"))),
+            };
+            let _ = writeln!(dot, "    {} [{attrs}];", quote(name));
+        }
+        for (src, ports) in self.dependents.iter().enumerate() {
+            for (src_port, links) in ports.iter().enumerate() {
+                for &(dst, dst_port) in links {
+                    let label = format!(
+                        "{} -> {}",
+                        self.output_names[src][src_port], self.input_names[dst][dst_port]
+                    );
+                    let _ = writeln!(
+                        dot,
+                        "    {} -> {} [label={}];",
+                        quote(&self.node_names[src]),
+                        quote(&self.node_names[dst]),
+                        quote(&label)
+                    );
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn each_output(&self, node: usize) -> std::slice::Iter<'_, Vec<(usize, usize)>> {
         self.dependents[node].iter()
     }
 }
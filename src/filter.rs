@@ -1,7 +1,12 @@
 use lazy_static::lazy_static;
 use payload::URLENCODED_CONTENT_TYPE;
 use proxy_wasm::{traits::*, types::*};
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
+use std::time::{Duration, SystemTime};
 
 mod config;
 mod data;
@@ -10,12 +15,12 @@ mod dependency_graph;
 mod nodes;
 mod payload;
 
-use crate::config::{Config, ImplicitNode};
-use crate::data::{Data, Input, Phase, Phase::*, State};
-use crate::debug::{Debug, RunMode};
+use crate::config::{find_implicit_port, Config, ImplicitNode};
+use crate::data::{Data, Input, InputPort, Phase, Phase::*, Response, State};
+use crate::debug::{Debug, RunMode, TraceSink};
 use crate::dependency_graph::DependencyGraph;
-use crate::nodes::{Node, NodeVec, PortConfig};
-use crate::payload::Payload;
+use crate::nodes::{replace_response_body, Node, NodeVec, PortConfig};
+use crate::payload::{Compress, ErrorKind, Payload};
 use crate::ImplicitNodeId::*;
 use crate::ImplicitPortId::*;
 
@@ -23,42 +28,126 @@ use crate::ImplicitPortId::*;
 // Implicit nodes
 // -----------------------------------------------------------------------------
 
+// The HTTP model of the filter: the graph and the configuration only know
+// implicit nodes by the names given in IMPLICIT_NODES, and so do these ids.
+
 #[derive(Copy, Clone)]
 enum ImplicitNodeId {
-    Request = 0,
-    ServiceRequest = 1,
-    ServiceResponse = 2,
-    Response = 3,
+    Request,
+    ServiceRequest,
+    ServiceResponse,
+    Response,
+}
+
+impl ImplicitNodeId {
+    const ALL: [ImplicitNodeId; 4] = [Request, ServiceRequest, ServiceResponse, Response];
+
+    fn name(self) -> &'static str {
+        match self {
+            Request => "request",
+            ServiceRequest => "service_request",
+            ServiceResponse => "service_response",
+            Response => "response",
+        }
+    }
 }
 
 impl From<ImplicitNodeId> for usize {
     fn from(n: ImplicitNodeId) -> Self {
-        n as usize
+        IMPLICIT_POSITIONS.nodes[n as usize]
     }
 }
 
 #[derive(Copy, Clone)]
 enum ImplicitPortId {
-    Body = 0,
-    Headers = 1,
-    Query = 2,
+    Body,
+    Headers,
+    Query,
+    Cookies,
+    SetCookies,
+    RawBody,
+    Meta,
+    Tls,
 }
 
-impl From<ImplicitPortId> for usize {
-    fn from(p: ImplicitPortId) -> Self {
-        p as usize
+impl ImplicitPortId {
+    const ALL: [ImplicitPortId; 8] = [
+        Body, Headers, Query, Cookies, SetCookies, RawBody, Meta, Tls,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Body => "body",
+            Headers => "headers",
+            Query => "query",
+            Cookies => "cookies",
+            SetCookies => "set_cookies",
+            RawBody => "raw_body",
+            Meta => "meta",
+            Tls => "tls",
+        }
+    }
+
+    /// Position of the port in the given implicit node.
+    fn at(self, node: ImplicitNodeId) -> usize {
+        match IMPLICIT_POSITIONS.ports[node as usize][self as usize] {
+            Some(port) => port,
+            None => unreachable!("no port {} in {}", self.name(), node.name()),
+        }
+    }
+}
+
+/// The positions of the implicit nodes and of their ports in a list of
+/// implicit nodes, indexed by id. They are resolved once by name, so the
+/// ids do not depend on the order of the list.
+struct ImplicitPositions {
+    nodes: [usize; ImplicitNodeId::ALL.len()],
+    ports: [[Option<usize>; ImplicitPortId::ALL.len()]; ImplicitNodeId::ALL.len()],
+}
+
+impl ImplicitPositions {
+    fn new(implicits: &[ImplicitNode]) -> ImplicitPositions {
+        let mut positions = ImplicitPositions {
+            nodes: [0; ImplicitNodeId::ALL.len()],
+            ports: [[None; ImplicitPortId::ALL.len()]; ImplicitNodeId::ALL.len()],
+        };
+        for node in ImplicitNodeId::ALL {
+            let n = implicits
+                .iter()
+                .position(|inode| inode.name() == node.name())
+                .expect("implicit node declared in IMPLICIT_NODES");
+            positions.nodes[node as usize] = n;
+            for port in ImplicitPortId::ALL {
+                positions.ports[node as usize][port as usize] =
+                    find_implicit_port(implicits, node.name(), port.name()).map(|(_, p)| p);
+            }
+        }
+        positions
     }
 }
 
 lazy_static! {
     static ref REQ_PORTS: Vec<String> = PortConfig::names(&["body", "headers", "query"]);
+    static ref REQ_OUT_PORTS: Vec<String> =
+        PortConfig::names(&["body", "headers", "query", "cookies", "raw_body", "meta", "tls"]);
     static ref RESP_PORTS: Vec<String> = PortConfig::names(&["body", "headers"]);
+    static ref RESP_OUT_PORTS: Vec<String> = PortConfig::names(&["body", "headers", "raw_body"]);
+    static ref RESP_IN_PORTS: Vec<String> = PortConfig::names(&["body", "headers", "set_cookies"]);
+    // the ports processed in the body and response headers phases, so
+    // that the configuration tells which of these phases have work
     static ref IMPLICIT_NODES: Vec<ImplicitNode> = vec![
-        ImplicitNode::new("request", vec![], REQ_PORTS.clone()),
-        ImplicitNode::new("service_request", REQ_PORTS.clone(), RESP_PORTS.clone()),
-        ImplicitNode::new("service_response", vec![], RESP_PORTS.clone()),
-        ImplicitNode::new("response", RESP_PORTS.clone(), RESP_PORTS.clone()),
+        ImplicitNode::new(Request.name(), vec![], REQ_OUT_PORTS.clone())
+            .in_phase(HttpRequestBody, &["body", "raw_body"], &[]),
+        ImplicitNode::new(ServiceRequest.name(), REQ_PORTS.clone(), RESP_PORTS.clone())
+            .in_phase(HttpRequestBody, &[], &["body"]),
+        ImplicitNode::new(ServiceResponse.name(), vec![], RESP_OUT_PORTS.clone())
+            .in_phase(HttpResponseHeaders, &["headers"], &[])
+            .in_phase(HttpResponseBody, &["body", "raw_body"], &[]),
+        ImplicitNode::new(Response.name(), RESP_IN_PORTS.clone(), RESP_PORTS.clone())
+            .in_phase(HttpResponseHeaders, &[], &["headers", "set_cookies", "body"])
+            .in_phase(HttpResponseBody, &[], &["body"]),
     ];
+    static ref IMPLICIT_POSITIONS: ImplicitPositions = ImplicitPositions::new(&IMPLICIT_NODES);
 }
 
 // -----------------------------------------------------------------------------
@@ -67,30 +156,169 @@ lazy_static! {
 
 struct DataKitFilterRootContext {
     config: Option<Rc<Config>>,
+    /// Identifies the configuration in the keys of the data stored by its
+    /// background nodes, since shared data is visible to all the filters
+    /// of the VM. Shared data cannot be deleted, so the data stored under
+    /// the namespace of a previous configuration is left behind.
+    tick_namespace: u64,
+    /// The background nodes, by node index.
+    tick_nodes: BTreeMap<usize, Box<dyn Node>>,
+    tick_last_run: Vec<Option<SystemTime>>,
+    tick_waiting: BTreeMap<u32, usize>,
+}
+
+/// Whether the body of the service request is replaced, which is only the
+/// case when some node produces it. Nodes reading the body of the incoming
+/// request do not consume it, so otherwise the original bytes flow upstream.
+fn replaces_service_request_body(graph: &DependencyGraph) -> bool {
+    graph.has_provider(ServiceRequest.into(), Body.at(ServiceRequest))
+}
+
+fn tick_namespace(config_bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    config_bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn tick_data_key(namespace: u64, name: &str) -> String {
+    format!("datakit:tick:{namespace:016x}:{name}")
+}
+
+impl DataKitFilterRootContext {
+    fn tick_done(&mut self, i: usize, state: State) {
+        let config = self.config.clone().expect("configured");
+        let name = config.get_node_name(i);
+
+        match state {
+            State::Waiting(id) => {
+                self.tick_waiting.insert(id, i);
+            }
+            State::Done(payloads) => match serde_json::to_vec(&payloads) {
+                Ok(bytes) => {
+                    let key = tick_data_key(self.tick_namespace, name);
+                    if let Err(e) = self.set_shared_data(&key, Some(&bytes), None) {
+                        log::warn!("tick: failed storing data for node {name}: {e:?}");
+                    }
+                }
+                Err(e) => log::warn!("tick: failed serializing data for node {name}: {e}"),
+            },
+            State::Fail(_) => {
+                log::warn!("tick: node {name} failed");
+            }
+            State::ShortCircuit(_) => {
+                log::warn!("tick: node {name} cannot respond outside of a request");
+            }
+        }
+    }
+
+    fn tick_resume(&mut self, token_id: u32, phase: Phase) {
+        if let Some(i) = self.tick_waiting.remove(&token_id) {
+            let Some(node) = self.tick_nodes.get(&i) else {
+                return;
+            };
+            let input = Input { data: &[], phase };
+            let state = node.resume(self as &dyn HttpContext, &input);
+            self.tick_done(i, state);
+        }
+    }
 }
 
-impl Context for DataKitFilterRootContext {}
+impl Context for DataKitFilterRootContext {
+    fn on_http_call_response(
+        &mut self,
+        token_id: u32,
+        _nheaders: usize,
+        _body_size: usize,
+        _num_trailers: usize,
+    ) {
+        self.tick_resume(token_id, HttpCallResponse);
+    }
+
+    fn on_grpc_call_response(&mut self, token_id: u32, status_code: u32, _response_size: usize) {
+        self.tick_resume(token_id, GrpcCallResponse(status_code));
+    }
+}
+
+// Background nodes run from the root context, and nodes
+// perform their calls through an HttpContext.
+impl HttpContext for DataKitFilterRootContext {}
 
 impl RootContext for DataKitFilterRootContext {
     fn on_configure(&mut self, _config_size: usize) -> bool {
-        match self.get_plugin_configuration() {
-            Some(config_bytes) => match Config::new(config_bytes, &IMPLICIT_NODES) {
-                Ok(config) => {
-                    self.config = Some(Rc::new(config));
-                    true
+        let Some(config_bytes) = self.get_plugin_configuration() else {
+            log::warn!("on_configure: failed getting configuration");
+            return false;
+        };
+
+        let namespace = tick_namespace(&config_bytes);
+
+        match Config::new(config_bytes, &IMPLICIT_NODES) {
+            Ok(config) => {
+                for warning in config.warnings() {
+                    log::warn!("on_configure: {warning}");
                 }
-                Err(err) => {
-                    log::warn!("on_configure: {err}");
-                    false
+                if config.debug() {
+                    let dot = config.get_graph().to_dot(&config);
+                    log::debug!("on_configure: dependency graph:\n{dot}");
                 }
-            },
-            None => {
-                log::warn!("on_configure: failed getting configuration");
+                // a previous configuration may have had background nodes
+                self.tick_nodes = (config.number_of_implicits()..config.node_count())
+                    .filter(|&i| config.get_tick_period(i).is_some())
+                    .map(|i| (i, config.build_node(i)))
+                    .collect();
+                self.tick_last_run = vec![None; config.node_count()];
+                self.tick_waiting.clear();
+                self.set_tick_period(config.tick_period().unwrap_or(Duration::ZERO));
+                self.tick_namespace = namespace;
+                self.config = Some(Rc::new(config));
+                true
+            }
+            Err(err) => {
+                log::warn!("on_configure: {err}");
                 false
             }
         }
     }
 
+    fn on_tick(&mut self) {
+        let Some(config) = self.config.clone() else {
+            return;
+        };
+
+        let now = self.get_current_time();
+
+        for i in config.number_of_implicits()..config.node_count() {
+            let Some(period) = config.get_tick_period(i) else {
+                continue;
+            };
+
+            // do not overlap runs of the same node
+            if self.tick_waiting.values().any(|&n| n == i) {
+                continue;
+            }
+
+            if let Some(last) = self.tick_last_run[i] {
+                if now.duration_since(last).is_ok_and(|d| d < period) {
+                    continue;
+                }
+            }
+            self.tick_last_run[i] = Some(now);
+
+            log::debug!("tick: running node {}", config.get_node_name(i));
+
+            let Some(node) = self.tick_nodes.get(&i) else {
+                continue;
+            };
+            let inputs = vec![InputPort::Unconnected; config.get_graph().number_of_inputs(i)];
+            let input = Input {
+                data: &inputs,
+                phase: Tick,
+            };
+            let state = node.run(self as &dyn HttpContext, &input);
+            self.tick_done(i, state);
+        }
+    }
+
     fn get_type(&self) -> Option<ContextType> {
         Some(ContextType::HttpContext)
     }
@@ -100,46 +328,11 @@ impl RootContext for DataKitFilterRootContext {
 
         let config = self.config.clone()?;
 
-        let nodes = config.build_nodes();
-        let graph = config.get_graph();
-        let debug = config.debug().then(|| Debug::new(&config));
-
-        // FIXME: is it possible to do lifetime annotations
-        // to avoid cloning every time?
-        let data = Data::new(graph.clone());
-
-        let do_request_headers = graph.has_dependents(Request.into(), Headers.into());
-        let do_request_query = graph.has_dependents(Request.into(), Query.into());
-        let do_request_body = graph.has_dependents(Request.into(), Body.into());
-
-        let do_service_request_headers = graph.has_provider(ServiceRequest.into(), Headers.into());
-        let do_service_request_query = graph.has_provider(ServiceRequest.into(), Query.into());
-        let do_service_request_body = graph.has_provider(ServiceRequest.into(), Body.into());
-
-        let do_service_response_headers =
-            graph.has_dependents(ServiceResponse.into(), Headers.into());
-        let do_service_response_body = graph.has_dependents(ServiceResponse.into(), Body.into());
-
-        let do_response_headers = graph.has_provider(Response.into(), Headers.into());
-        let do_response_body = graph.has_provider(Response.into(), Body.into());
-
-        Some(Box::new(DataKitFilter {
+        Some(Box::new(DataKitFilter::new(
             config,
-            nodes,
-            debug,
-            data,
-            failed: false,
-            do_request_headers,
-            do_request_query,
-            do_request_body,
-            do_service_request_headers,
-            do_service_request_query,
-            do_service_request_body,
-            do_service_response_headers,
-            do_service_response_body,
-            do_response_headers,
-            do_response_body,
-        }))
+            Box::new(Host),
+            self.tick_namespace,
+        )))
     }
 }
 
@@ -147,22 +340,87 @@ impl RootContext for DataKitFilterRootContext {
 // Filter Context
 // -----------------------------------------------------------------------------
 
+/// The proxy-wasm host, reached through the default implementations
+/// of the context traits.
+struct Host;
+
+impl Context for Host {}
+
+impl HttpContext for Host {}
+
 pub struct DataKitFilter {
     config: Rc<Config>,
+    /// The host calls made by the filter and its nodes go through `host`,
+    /// so that the filter can run against a mock in tests.
+    host: Box<dyn HttpContext>,
     nodes: NodeVec,
     data: Data,
     debug: Option<Debug>,
     failed: bool,
+    short_circuited: bool,
+    response_sent: Cell<bool>,
+    /// HTTP calls in flight, by the key of their request, when
+    /// identical calls share a single dispatch.
+    calls_in_flight: RefCell<BTreeMap<u64, u32>>,
+    /// The response body, compressed when sending the response headers.
+    compressed_body: Option<Vec<u8>>,
+    deadline: Option<SystemTime>,
+    node_runs: usize,
+    /// The phase of the body that was larger than `max_body_bytes`, so that
+    /// the chunks after the one that exceeded it are not buffered either.
+    max_body_exceeded: Option<Phase>,
+    tick_namespace: u64,
     do_request_headers: bool,
     do_request_query: bool,
     do_request_body: bool,
+    do_request_cookies: bool,
+    do_request_raw_body: bool,
+    do_request_meta: bool,
+    do_request_tls: bool,
     do_service_request_headers: bool,
     do_service_request_query: bool,
     do_service_request_body: bool,
     do_service_response_headers: bool,
     do_service_response_body: bool,
+    do_service_response_raw_body: bool,
     do_response_headers: bool,
     do_response_body: bool,
+    do_response_set_cookies: bool,
+}
+
+/// Whether a local response can be sent. Only one can be sent per request,
+/// so any later one, such as a failure after an `exit` node responded, is
+/// dropped with a warning.
+fn claim_response(response_sent: &Cell<bool>, status: u32) -> bool {
+    if response_sent.replace(true) {
+        log::warn!("response already sent, ignoring response with status {status}");
+        return false;
+    }
+    true
+}
+
+fn send_json_error(ctx: &dyn HttpContext, status: u32, message: &str) {
+    let body =
+        payload::to_json_error_body(message, ctx.get_property(vec!["ngx", "kong_request_id"]));
+    ctx.send_http_response(
+        status,
+        vec![("Content-Type", "application/json")],
+        Some(&body.into_bytes()),
+    );
+}
+
+/// A key identifying an HTTP call by everything that is sent: the upstream,
+/// the headers, which include the method and the URL, the body and the
+/// trailers.
+fn call_key(
+    upstream: &str,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+    trailers: &[(&str, &str)],
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (upstream, headers, body, trailers).hash(&mut hasher);
+    hasher.finish()
 }
 
 fn header_to_bool(header_value: &Option<String>) -> bool {
@@ -172,22 +430,133 @@ fn header_to_bool(header_value: &Option<String>) -> bool {
     }
 }
 
+fn header_to_trace_sink(header_value: &Option<String>) -> Option<TraceSink> {
+    match header_value.as_deref() {
+        _ if !header_to_bool(header_value) => None,
+        Some("log") => Some(TraceSink::Log),
+        _ => Some(TraceSink::Body),
+    }
+}
+
 impl DataKitFilter {
+    fn new(config: Rc<Config>, host: Box<dyn HttpContext>, tick_namespace: u64) -> DataKitFilter {
+        let nodes = config.build_nodes();
+        let graph = config.get_graph();
+        let debug = config.debug().then(|| Debug::new(&config));
+        let deadline = config.deadline().map(|d| host.get_current_time() + d);
+
+        let optional_inputs = (0..config.node_count())
+            .map(|i| config.has_optional_inputs(i))
+            .collect();
+        let transforms = (0..config.node_count())
+            .map(|i| config.get_transforms(i).to_vec())
+            .collect();
+
+        // FIXME: is it possible to do lifetime annotations
+        // to avoid cloning every time?
+        let data = Data::new(
+            graph.clone(),
+            config.number_of_implicits(),
+            optional_inputs,
+            transforms,
+        );
+
+        let has_dependents =
+            |n: ImplicitNodeId, p: ImplicitPortId| graph.has_dependents(n.into(), p.at(n));
+        let has_provider =
+            |n: ImplicitNodeId, p: ImplicitPortId| graph.has_provider(n.into(), p.at(n));
+
+        let do_request_headers = has_dependents(Request, Headers);
+        let do_request_query = has_dependents(Request, Query);
+        let do_request_body = has_dependents(Request, Body);
+        let do_request_cookies = has_dependents(Request, Cookies);
+        let do_request_raw_body = has_dependents(Request, RawBody);
+        let do_request_meta = has_dependents(Request, Meta);
+        let do_request_tls = has_dependents(Request, Tls);
+
+        let do_service_request_headers = has_provider(ServiceRequest, Headers);
+        let do_service_request_query = has_provider(ServiceRequest, Query);
+        let do_service_request_body = replaces_service_request_body(graph);
+
+        let do_service_response_headers = has_dependents(ServiceResponse, Headers);
+        let do_service_response_body = has_dependents(ServiceResponse, Body);
+        let do_service_response_raw_body = has_dependents(ServiceResponse, RawBody);
+
+        let do_response_headers = has_provider(Response, Headers);
+        let do_response_body = has_provider(Response, Body);
+        let do_response_set_cookies = has_provider(Response, SetCookies);
+
+        DataKitFilter {
+            config,
+            host,
+            nodes,
+            debug,
+            data,
+            failed: false,
+            short_circuited: false,
+            response_sent: Cell::new(false),
+            calls_in_flight: RefCell::new(BTreeMap::new()),
+            compressed_body: None,
+            deadline,
+            node_runs: 0,
+            max_body_exceeded: None,
+            tick_namespace,
+            do_request_headers,
+            do_request_query,
+            do_request_body,
+            do_request_cookies,
+            do_request_raw_body,
+            do_request_meta,
+            do_request_tls,
+            do_service_request_headers,
+            do_service_request_query,
+            do_service_request_body,
+            do_service_response_headers,
+            do_service_response_body,
+            do_service_response_raw_body,
+            do_response_headers,
+            do_response_body,
+            do_response_set_cookies,
+        }
+    }
+
     fn debug_init(&mut self) {
-        let trace_header = &self.get_http_request_header("X-DataKit-Debug-Trace");
-        if header_to_bool(trace_header) {
-            if let Some(ref mut debug) = self.debug {
-                debug.set_tracing(true);
-            }
+        let trace_header = self.get_http_request_header("X-DataKit-Debug-Trace");
+        let (mode, params) = match trace_header.as_deref().map(|v| v.split_once(';')) {
+            Some(Some((mode, params))) => (Some(mode.trim().to_string()), params),
+            Some(None) => (trace_header.clone(), ""),
+            None => (None, ""),
+        };
+        let sink = header_to_trace_sink(&mode);
+        if let Some(ref mut debug) = self.debug {
+            debug.set_tracing(sink);
+            debug.apply_header_params(params);
+        }
+        // Only buffer the response body to record it in the trace
+        // if the graph processes it; the trace replaces it anyway.
+        if sink == Some(TraceSink::Body)
+            && (self.do_service_response_body || self.do_service_response_raw_body)
+        {
             self.do_response_body = true;
         }
     }
 
+    /// Whether the response is replaced by the trace, in which case
+    /// the regular response is not produced.
+    fn traces_to_body(&self) -> bool {
+        self.debug
+            .as_ref()
+            .is_some_and(|d| d.trace_sink() == Some(TraceSink::Body))
+    }
+
+    /// Save the content type of the upstream response body, so that the
+    /// body can be recorded in the trace whatever the sink, and replace
+    /// the response headers if the trace goes into the body.
     fn debug_done_headers(&mut self) {
         let ct = self.get_http_response_header("Content-Type");
         if let Some(ref mut debug) = self.debug {
-            if debug.is_tracing() {
-                debug.save_response_body_content_type(ct);
+            debug.save_response_body_content_type(ct);
+            if debug.trace_sink() == Some(TraceSink::Body) {
                 self.set_http_response_header("Content-Type", Some("application/json"));
                 self.set_http_response_header("Content-Length", None);
                 self.set_http_response_header("Content-Encoding", None);
@@ -195,30 +564,167 @@ impl DataKitFilter {
         }
     }
 
-    fn debug_done(&mut self) {
+    fn debug_done(&mut self, body_size: usize) {
         if let Some(ref mut debug) = self.debug {
-            if debug.is_tracing() {
+            if debug.trace_sink() == Some(TraceSink::Body) {
                 let trace = debug.get_trace();
-                let bytes = trace.as_bytes();
-                self.set_http_response_body(0, bytes.len(), bytes);
+                replace_response_body(self, body_size, trace.as_bytes());
             }
         }
     }
 
+    fn debug_body_size(&mut self, node: ImplicitNodeId, size: usize) {
+        if let Some(ref mut debug) = self.debug {
+            debug.body_size(node.name(), size);
+        }
+    }
+
+    /// Drop the values produced by a node whose outputs are sinks, so that
+    /// they are never mistaken for data, such as a response body.
+    fn sink_state(&self, i: usize, state: State) -> State {
+        match state {
+            State::Done(ports) if self.config.has_sink_outputs(i) => {
+                State::Done(vec![None; ports.len()])
+            }
+            state => state,
+        }
+    }
+
+    fn send_fail_response(&self, status: u32, message: &str) {
+        send_json_error(self, status, message);
+    }
+
     fn send_default_fail_response(&self) {
-        let body = payload::to_json_error_body(
-            "An unexpected error ocurred",
-            self.get_property(vec!["ngx", "kong_request_id"]),
-        );
-        self.send_http_response(
-            500,
-            vec![("Content-Type", "application/json")],
-            Some(&body.into_bytes()),
-        );
+        self.send_fail_response(500, "An unexpected error ocurred");
+    }
+
+    /// Abort processing once the configured deadline has passed. Nodes that
+    /// did not run yet never run, and the data produced so far is discarded.
+    /// Pending gRPC calls are cancelled; HTTP calls cannot be, so their
+    /// responses are ignored when they arrive.
+    fn deadline_exceeded(&mut self) -> bool {
+        let Some(deadline) = self.deadline else {
+            return false;
+        };
+        if self.failed || self.short_circuited || self.get_current_time() < deadline {
+            return false;
+        }
+        self.deadline = None;
+
+        log::warn!("deadline exceeded, aborting");
+        self.failed = true;
+
+        for (i, token_id) in self.data.waiting_nodes() {
+            if self.config.get_node_type(i) == "grpc_call" {
+                self.cancel_grpc_call(token_id);
+            }
+        }
+
+        if !self.traces_to_body() {
+            self.send_fail_response(504, "The request deadline was exceeded");
+        }
+
+        true
+    }
+
+    /// Shorten the timeout of a call so that it expires by the deadline,
+    /// since its response would be ignored past it anyway.
+    fn call_timeout(&self, timeout: Duration) -> Duration {
+        let Some(deadline) = self.deadline else {
+            return timeout;
+        };
+        let left = deadline
+            .duration_since(self.get_current_time())
+            .unwrap_or_default();
+        // a zero timeout would mean no timeout at all to some hosts
+        timeout.min(left.max(Duration::from_millis(1)))
+    }
+
+    /// Count a node run against `max_node_runs`, failing the request once
+    /// the budget is spent, so that a misbehaving configuration fails
+    /// instead of running indefinitely.
+    fn node_budget_exceeded(&mut self) -> bool {
+        let max = self.config.max_node_runs();
+        if self.node_runs < max {
+            self.node_runs += 1;
+            return false;
+        }
+
+        log::error!("node run budget exceeded: ran {max} nodes for this request, aborting");
+        self.failed = true;
+
+        if !self.traces_to_body() {
+            self.send_default_fail_response();
+        }
+
+        true
+    }
+
+    /// Respond with a response produced by a node, skipping the upstream
+    /// if it was not contacted yet. No further nodes run for the request.
+    fn short_circuit(&mut self, response: &Response, phase: Phase) {
+        self.short_circuited = true;
+
+        if self.traces_to_body() {
+            return;
+        }
+
+        if phase == HttpResponseBody {
+            log::warn!("cannot short-circuit when processing response body, headers already sent");
+            return;
+        }
+
+        let headers = response
+            .headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        self.send_http_response(response.status, headers, response.body.as_deref());
+    }
+
+    /// Do not feed a truncated body into the nodes if the host
+    /// returns less data than it announced. Once response headers
+    /// were sent, the failure can only be logged.
+    fn fail_truncated_body(&mut self, phase: Phase, got: usize, expected: usize) {
+        log::warn!("body truncated: got {got} of {expected} bytes");
+        self.failed = true;
+
+        if phase == HttpRequestBody && !self.traces_to_body() {
+            self.send_default_fail_response();
+        }
+    }
+
+    /// Refuse to buffer a body larger than `max_body_bytes` for processing.
+    /// A request body is rejected with a 413; once response headers were
+    /// sent, the body is passed through unprocessed, unless the graph
+    /// replaces it.
+    fn exceeds_max_body(&mut self, phase: Phase, body_size: usize) -> bool {
+        if self.max_body_exceeded == Some(phase) {
+            return true;
+        }
+        let Some(max) = self.config.max_body_bytes() else {
+            return false;
+        };
+        if body_size <= max {
+            return false;
+        }
+        self.max_body_exceeded = Some(phase);
+
+        if !self.failed {
+            log::warn!(
+                "body exceeds max_body_bytes: got at least {body_size} of at most {max} bytes"
+            );
+            self.failed = true;
+
+            if phase == HttpRequestBody && !self.traces_to_body() {
+                self.send_fail_response(413, "The request body is too large");
+            }
+        }
+        true
     }
 
     fn set_implicit_data(&mut self, node: ImplicitNodeId, port: ImplicitPortId, payload: Payload) {
-        let r = self.data.fill_port(node.into(), port.into(), payload);
+        let r = self.data.fill_port(node.into(), port.at(node), payload);
         match r {
             Ok(()) => {
                 if let Some(debug) = &mut self.debug {
@@ -237,6 +743,11 @@ impl DataKitFilter {
         self.set_implicit_data(node, Headers, payload);
     }
 
+    fn set_cookies_data(&mut self, node: ImplicitNodeId, vec: &[(String, String)]) {
+        let payload = payload::from_pwm_cookies(vec);
+        self.set_implicit_data(node, Cookies, payload);
+    }
+
     fn set_query_data(&mut self, node: ImplicitNodeId, query: &str) {
         if let Some(payload) =
             Payload::from_bytes(query.as_bytes().to_vec(), Some(URLENCODED_CONTENT_TYPE))
@@ -250,75 +761,135 @@ impl DataKitFilter {
     }
 
     fn get_headers_data(&self, node: ImplicitNodeId) -> Option<&Payload> {
-        self.data.fetch_port(node.into(), Headers.into())
+        self.data.fetch_port(node.into(), Headers.at(node))
     }
 
     fn get_query_data(&self, node: ImplicitNodeId) -> Option<&Payload> {
-        self.data.fetch_port(node.into(), Query.into())
+        self.data.fetch_port(node.into(), Query.at(node))
     }
 
     fn get_body_data(&self, node: ImplicitNodeId) -> Option<&Payload> {
-        self.data.fetch_port(node.into(), Body.into())
+        self.data.fetch_port(node.into(), Body.at(node))
+    }
+
+    /// Parse a body according to its content type. Without one, the body
+    /// is only taken as JSON when `sniff_content_type` is enabled.
+    fn parse_body(&self, bytes: Vec<u8>, content_type: Option<&str>) -> Option<Payload> {
+        match content_type {
+            None if self.config.sniff_content_type() => Some(Payload::sniff(bytes)),
+            _ => Payload::from_bytes(bytes, content_type),
+        }
+    }
+
+    /// Background nodes produce the data stored by their latest run;
+    /// until they complete a run, their output ports are empty.
+    fn get_tick_state(&self, i: usize) -> State {
+        let name = self.config.get_node_name(i);
+        let (bytes, _) = self.get_shared_data(&tick_data_key(self.tick_namespace, name));
+
+        match bytes.and_then(|b| serde_json::from_slice(&b).ok()) {
+            Some(payloads) => State::Done(payloads),
+            None => State::Done(vec![None; self.config.get_graph().number_of_outputs(i)]),
+        }
+    }
+
+    /// The state of a node whose `when` condition does not hold: it is
+    /// Done without producing any values. None if the node should run.
+    fn skipped_state(&self, i: usize, input: &Input) -> Option<State> {
+        let when = self.config.get_when(i)?;
+        match when.holds(&input.payloads(), Some(input.phase)) {
+            Ok(true) => None,
+            Ok(false) => {
+                log::debug!(
+                    "skipping node {}: `when` does not hold",
+                    self.config.get_node_name(i)
+                );
+                Some(State::Done(vec![
+                    None;
+                    self.config
+                        .get_graph()
+                        .number_of_outputs(i)
+                ]))
+            }
+            Err(e) => Some(State::Fail(vec![Some(Payload::Error(
+                ErrorKind::ConfigError,
+                format!("when: {e}"),
+            ))])),
+        }
     }
 
     fn run_nodes(&mut self, phase: Phase) -> Action {
         let mut ret = Action::Continue;
 
-        let mut debug_is_tracing = false;
-        if let Some(ref mut debug) = self.debug {
-            debug_is_tracing = debug.is_tracing();
-        }
+        let traces_to_body = self.traces_to_body();
 
-        let from = self.config.number_of_implicits();
-        let to = self.config.node_count();
+        // Nodes are visited in topological order, so a single pass runs
+        // every node whose inputs are ready, including the ones that
+        // became ready in the same pass. Since a node never triggers
+        // twice, each node runs at most once per phase.
+        let config = self.config.clone();
+        for &i in config.run_order() {
+            if self.failed || self.short_circuited || self.deadline_exceeded() {
+                break;
+            }
 
-        while !self.failed {
-            let mut any_ran = false;
-            for i in from..to {
-                let node: &dyn Node = self
-                    .nodes
-                    .get(i)
-                    .expect("self.nodes doesn't match node_count")
-                    .as_ref();
-                if let Some(inputs) = self.data.get_inputs_for(i, None) {
-                    any_ran = true;
-
-                    let input = Input {
-                        data: &inputs,
-                        phase,
-                    };
-
-                    log::debug!(
-                        "running node {} of type {}",
-                        self.config.get_node_name(i),
-                        self.config.get_node_type(i)
-                    );
-
-                    let state = node.run(self as &dyn HttpContext, &input);
-
-                    if let Some(ref mut debug) = self.debug {
-                        let name = self.config.get_node_name(i);
-                        debug.run(name, &inputs, &state, RunMode::Run);
-                    }
+            let node: &dyn Node = self
+                .nodes
+                .get(i)
+                .expect("self.nodes doesn't match node_count")
+                .as_ref();
+            // the node stays untriggered until a phase it may run in
+            if !config.runs_in_phase(i, phase) {
+                continue;
+            }
+            if let Some(inputs) = self.data.get_inputs_for(i, None) {
+                if self.node_budget_exceeded() {
+                    break;
+                }
 
-                    match state {
-                        State::Done(_) => {}
-                        State::Waiting(_) => {
-                            ret = Action::Pause;
-                        }
-                        State::Fail(_) => {
-                            self.failed = true;
-                            if !debug_is_tracing {
-                                self.send_default_fail_response();
-                            }
+                let input = Input {
+                    data: &inputs,
+                    phase,
+                };
+
+                log::debug!(
+                    "running node {} of type {}",
+                    self.config.get_node_name(i),
+                    self.config.get_node_type(i)
+                );
+
+                let state = if let Some(state) = self.skipped_state(i, &input) {
+                    state
+                } else if self.config.get_tick_period(i).is_some() {
+                    self.get_tick_state(i)
+                } else {
+                    node.run(self as &dyn HttpContext, &input)
+                };
+                let state = self.data.check_outputs(i, state);
+                let state = self.sink_state(i, state);
+
+                if let Some(ref mut debug) = self.debug {
+                    let name = self.config.get_node_name(i);
+                    debug.run(name, &inputs, &state, RunMode::Run, node.debug_request());
+                }
+
+                match state {
+                    State::Done(_) => {}
+                    State::Waiting(_) => {
+                        ret = Action::Pause;
+                    }
+                    State::Fail(_) => {
+                        self.failed = true;
+                        if !traces_to_body {
+                            self.send_default_fail_response();
                         }
                     }
-
-                    self.data.set(i, state);
+                    State::ShortCircuit(ref response) => {
+                        self.short_circuit(response, phase);
+                    }
                 }
-            }
-            if !any_ran {
-                break;
+
+                self.data.set(i, state);
             }
         }
 
@@ -349,10 +920,11 @@ impl DataKitFilter {
         set_header: impl Fn(&DataKitFilter, &str, Option<&str>),
     ) {
         if let Some(payload) = self.get_body_data(node) {
-            if let Some(content_type) = payload.content_type() {
+            let content_type = payload.content_type();
+            if let Some(content_type) = content_type {
                 set_header(self, "Content-Type", Some(content_type));
             }
-            if let Some(content_length) = payload.len().map(|n| n.to_string()) {
+            if let Some(content_length) = payload.len(content_type).map(|n| n.to_string()) {
                 set_header(self, "Content-Length", Some(&content_length));
             } else {
                 set_header(self, "Content-Length", None);
@@ -363,18 +935,69 @@ impl DataKitFilter {
         set_header(self, "Content-Encoding", None);
     }
 
+    /// Whether an `exit` node with a `body` link may still run when the
+    /// response body arrives, replacing the upstream body after the
+    /// response headers, and the upstream `Content-Length`, were sent.
+    fn exit_may_replace_response_body(&self) -> bool {
+        if self.failed || self.short_circuited {
+            return false;
+        }
+        let config = &self.config;
+        config.run_order().iter().any(|&i| {
+            config.get_node_type(i) == "exit"
+                && config.runs_in_phase(i, HttpResponseBody)
+                && config.get_graph().has_provider(i, 0)
+                && self.data.get_state(i).is_err()
+        })
+    }
+
+    /// Compress the response body if it is already known when the response
+    /// headers are sent, since they have to announce the encoding.
+    fn compress_response_body(&mut self) {
+        let compress = self.config.compress();
+        let accept_encoding = match compress {
+            Compress::Auto => self.get_http_request_header("Accept-Encoding"),
+            _ => None,
+        };
+        if !compress.applies(accept_encoding.as_deref()) {
+            return;
+        }
+        let Some(payload) = self.get_body_data(Response) else {
+            return;
+        };
+
+        let content_type = self.get_http_response_header("Content-Type");
+        match payload
+            .to_bytes(content_type.as_deref())
+            .and_then(|bytes| payload::gzip(&bytes))
+        {
+            Ok(bytes) => {
+                let content_length = bytes.len().to_string();
+                self.set_http_response_header("Content-Length", Some(&content_length));
+                self.set_http_response_header("Content-Encoding", Some(payload::GZIP_ENCODING));
+                if compress == Compress::Auto {
+                    self.add_http_response_header("Vary", "Accept-Encoding");
+                }
+                self.compressed_body = Some(bytes);
+            }
+            Err(e) => log::warn!("cannot compress the response body: {e}"),
+        }
+    }
+
     fn prep_service_request_body(&mut self) {
         if self.do_service_request_body {
             self.set_content_headers(ServiceRequest, |s, k, v| s.set_http_request_header(k, v));
         }
     }
 
-    fn set_service_request_body(&mut self) {
+    /// Replace the whole buffered request body, which had `body_size` bytes.
+    fn set_service_request_body(&mut self, body_size: usize) {
         if self.do_service_request_body {
             if let Some(payload) = self.get_body_data(ServiceRequest) {
                 let content_type = self.get_http_request_header("Content-Type");
                 if let Ok(bytes) = payload.to_bytes(content_type.as_deref()) {
-                    self.set_http_request_body(0, bytes.len(), &bytes);
+                    self.set_http_request_body(0, body_size, &bytes);
+                    self.debug_body_size(ServiceRequest, bytes.len());
                 }
                 self.do_service_request_body = false;
             }
@@ -388,16 +1011,8 @@ fn update_query_in_path(path: &str, qpayload: &Payload) -> String {
         + &qpayload.to_pwm_query()
 }
 
-impl Context for DataKitFilter {
-    fn on_http_call_response(
-        &mut self,
-        token_id: u32,
-        _nheaders: usize,
-        _body_size: usize,
-        _num_trailers: usize,
-    ) {
-        log::debug!("DataKitFilter: on http call response, id = {:?}", token_id);
-
+impl DataKitFilter {
+    fn resume_node(&mut self, token_id: u32, phase: Phase) {
         let from = self.config.number_of_implicits();
         let to = self.config.node_count();
 
@@ -410,7 +1025,7 @@ impl Context for DataKitFilter {
             if let Some(inputs) = self.data.get_inputs_for(i, Some(token_id)) {
                 let input = Input {
                     data: &inputs,
-                    phase: HttpCallResponse,
+                    phase,
                 };
 
                 log::debug!(
@@ -420,18 +1035,31 @@ impl Context for DataKitFilter {
                 );
 
                 let state = node.resume(self, &input);
+                let state = self.data.check_outputs(i, state);
+                let state = self.sink_state(i, state);
 
                 if let Some(ref mut debug) = self.debug {
                     let name = self.config.get_node_name(i);
-                    debug.run(name, &inputs, &state, RunMode::Resume);
+                    debug.run(name, &inputs, &state, RunMode::Resume, None);
+                }
+
+                if let State::ShortCircuit(ref response) = state {
+                    self.short_circuit(response, phase);
                 }
 
+                // several nodes wait on the same call when it is shared
                 self.data.set(i, state);
-                break;
             }
         }
+    }
+
+    fn resume_nodes(&mut self, token_id: u32, phase: Phase) {
+        // a response arriving past the deadline is not processed
+        if !self.deadline_exceeded() {
+            self.resume_node(token_id, phase);
+        }
 
-        self.run_nodes(HttpCallResponse);
+        self.run_nodes(phase);
 
         self.set_service_request_headers();
         self.prep_service_request_body();
@@ -440,14 +1068,226 @@ impl Context for DataKitFilter {
     }
 }
 
+impl Context for DataKitFilter {
+    /// With `dedupe_calls`, a call identical to one still in flight is
+    /// not dispatched again: it gets the token of the pending call, and
+    /// all the nodes waiting on it resume when its response arrives.
+    fn dispatch_http_call(
+        &self,
+        upstream: &str,
+        headers: Vec<(&str, &str)>,
+        body: Option<&[u8]>,
+        trailers: Vec<(&str, &str)>,
+        timeout: Duration,
+    ) -> Result<u32, Status> {
+        let key = self
+            .config
+            .dedupe_calls()
+            .then(|| call_key(upstream, &headers, body, &trailers));
+
+        let in_flight = key.and_then(|k| self.calls_in_flight.borrow().get(&k).copied());
+        if let Some(token_id) = in_flight {
+            log::debug!("sharing call id {token_id} with an identical call");
+            return Ok(token_id);
+        }
+
+        let timeout = self.call_timeout(timeout);
+        let token_id = self
+            .host
+            .dispatch_http_call(upstream, headers, body, trailers, timeout)?;
+        if let Some(key) = key {
+            self.calls_in_flight.borrow_mut().insert(key, token_id);
+        }
+        Ok(token_id)
+    }
+
+    fn on_http_call_response(
+        &mut self,
+        token_id: u32,
+        _nheaders: usize,
+        _body_size: usize,
+        _num_trailers: usize,
+    ) {
+        log::debug!("DataKitFilter: on http call response, id = {:?}", token_id);
+
+        self.calls_in_flight
+            .borrow_mut()
+            .retain(|_, &mut id| id != token_id);
+
+        self.resume_nodes(token_id, HttpCallResponse);
+    }
+
+    fn on_grpc_call_response(&mut self, token_id: u32, status_code: u32, _response_size: usize) {
+        log::debug!(
+            "DataKitFilter: on grpc call response, id = {:?}, status = {}",
+            token_id,
+            status_code
+        );
+
+        self.resume_nodes(token_id, GrpcCallResponse(status_code));
+    }
+
+    fn get_current_time(&self) -> SystemTime {
+        self.host.get_current_time()
+    }
+
+    fn get_property(&self, path: Vec<&str>) -> Option<Bytes> {
+        self.host.get_property(path)
+    }
+
+    fn set_property(&self, path: Vec<&str>, value: Option<&[u8]>) {
+        self.host.set_property(path, value)
+    }
+
+    fn get_shared_data(&self, key: &str) -> (Option<Bytes>, Option<u32>) {
+        self.host.get_shared_data(key)
+    }
+
+    fn set_shared_data(
+        &self,
+        key: &str,
+        value: Option<&[u8]>,
+        cas: Option<u32>,
+    ) -> Result<(), Status> {
+        self.host.set_shared_data(key, value, cas)
+    }
+
+    fn get_http_call_response_headers(&self) -> Vec<(String, String)> {
+        self.host.get_http_call_response_headers()
+    }
+
+    fn get_http_call_response_header(&self, name: &str) -> Option<String> {
+        self.host.get_http_call_response_header(name)
+    }
+
+    fn get_http_call_response_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
+        self.host.get_http_call_response_body(start, max_size)
+    }
+
+    fn dispatch_grpc_call(
+        &self,
+        upstream_name: &str,
+        service_name: &str,
+        method_name: &str,
+        initial_metadata: Vec<(&str, &[u8])>,
+        message: Option<&[u8]>,
+        timeout: Duration,
+    ) -> Result<u32, Status> {
+        self.host.dispatch_grpc_call(
+            upstream_name,
+            service_name,
+            method_name,
+            initial_metadata,
+            message,
+            self.call_timeout(timeout),
+        )
+    }
+
+    fn get_grpc_call_response_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
+        self.host.get_grpc_call_response_body(start, max_size)
+    }
+
+    fn cancel_grpc_call(&self, token_id: u32) {
+        self.host.cancel_grpc_call(token_id)
+    }
+}
+
 impl HttpContext for DataKitFilter {
+    fn send_http_response(
+        &self,
+        status_code: u32,
+        headers: Vec<(&str, &str)>,
+        body: Option<&[u8]>,
+    ) {
+        if claim_response(&self.response_sent, status_code) {
+            self.host.send_http_response(status_code, headers, body);
+        }
+    }
+
+    fn get_http_request_headers(&self) -> Vec<(String, String)> {
+        self.host.get_http_request_headers()
+    }
+
+    fn set_http_request_headers(&self, headers: Vec<(&str, &str)>) {
+        self.host.set_http_request_headers(headers)
+    }
+
+    fn get_http_request_header(&self, name: &str) -> Option<String> {
+        self.host.get_http_request_header(name)
+    }
+
+    fn set_http_request_header(&self, name: &str, value: Option<&str>) {
+        self.host.set_http_request_header(name, value)
+    }
+
+    fn get_http_request_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
+        self.host.get_http_request_body(start, max_size)
+    }
+
+    fn set_http_request_body(&self, start: usize, size: usize, value: &[u8]) {
+        self.host.set_http_request_body(start, size, value)
+    }
+
+    fn resume_http_request(&self) {
+        self.host.resume_http_request()
+    }
+
+    fn get_http_response_headers(&self) -> Vec<(String, String)> {
+        self.host.get_http_response_headers()
+    }
+
+    fn set_http_response_headers(&self, headers: Vec<(&str, &str)>) {
+        self.host.set_http_response_headers(headers)
+    }
+
+    fn get_http_response_header(&self, name: &str) -> Option<String> {
+        self.host.get_http_response_header(name)
+    }
+
+    fn set_http_response_header(&self, name: &str, value: Option<&str>) {
+        self.host.set_http_response_header(name, value)
+    }
+
+    fn add_http_response_header(&self, name: &str, value: &str) {
+        self.host.add_http_response_header(name, value)
+    }
+
+    fn get_http_response_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
+        self.host.get_http_response_body(start, max_size)
+    }
+
+    fn set_http_response_body(&self, start: usize, size: usize, value: &[u8]) {
+        self.host.set_http_response_body(start, size, value)
+    }
+
+    fn set_http_response_trailers(&self, trailers: Vec<(&str, &str)>) {
+        self.host.set_http_response_trailers(trailers)
+    }
+
     fn on_http_request_headers(&mut self, _nheaders: usize, _eof: bool) -> Action {
         if self.debug.is_some() {
             self.debug_init()
         }
 
-        if self.do_request_headers {
-            self.set_headers_data(Request, self.get_http_request_headers());
+        if self.do_request_headers || self.do_request_cookies || self.do_request_meta {
+            let vec = self.get_http_request_headers();
+            if self.do_request_cookies {
+                self.set_cookies_data(Request, &vec);
+            }
+            if self.do_request_meta {
+                self.set_implicit_data(Request, Meta, payload::from_pwm_request_line(&vec));
+            }
+            if self.do_request_headers {
+                self.set_headers_data(Request, vec);
+            }
+        }
+
+        if self.do_request_tls {
+            let tls = payload::from_pwm_tls(|name| {
+                self.get_property(vec!["ngx", name])
+                    .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            });
+            self.set_implicit_data(Request, Tls, tls);
         }
 
         if self.do_request_query {
@@ -471,11 +1311,27 @@ impl HttpContext for DataKitFilter {
     }
 
     fn on_http_request_body(&mut self, body_size: usize, eof: bool) -> Action {
-        if eof && self.do_request_body {
+        if (self.do_request_body || self.do_request_raw_body)
+            && self.exceeds_max_body(HttpRequestBody, body_size)
+        {
+            return Action::Continue;
+        }
+
+        if eof && (self.do_request_body || self.do_request_raw_body) {
             if let Some(bytes) = self.get_http_request_body(0, body_size) {
-                let content_type = self.get_http_request_header("Content-Type");
-                if let Some(payload) = Payload::from_bytes(bytes, content_type.as_deref()) {
-                    self.set_body_data(Request, payload);
+                if bytes.len() < body_size {
+                    self.fail_truncated_body(HttpRequestBody, bytes.len(), body_size);
+                    return Action::Continue;
+                }
+                self.debug_body_size(Request, bytes.len());
+                if self.do_request_raw_body {
+                    self.set_implicit_data(Request, RawBody, Payload::Raw(bytes.clone()));
+                }
+                if self.do_request_body {
+                    let content_type = self.get_http_request_header("Content-Type");
+                    if let Some(payload) = self.parse_body(bytes, content_type.as_deref()) {
+                        self.set_body_data(Request, payload);
+                    }
                 }
             }
         }
@@ -483,7 +1339,7 @@ impl HttpContext for DataKitFilter {
         let action = self.run_nodes(HttpRequestBody);
 
         self.set_service_request_headers();
-        self.set_service_request_body();
+        self.set_service_request_body(body_size);
 
         action
     }
@@ -503,8 +1359,20 @@ impl HttpContext for DataKitFilter {
             }
         }
 
+        if self.do_response_set_cookies {
+            let payload = self
+                .data
+                .fetch_port(Response.into(), SetCookies.at(Response));
+            for value in payload::to_pwm_set_cookies(payload) {
+                self.add_http_response_header("Set-Cookie", &value);
+            }
+        }
+
         if self.do_response_body {
             self.set_content_headers(Response, |s, k, v| s.set_http_response_header(k, v));
+            self.compress_response_body();
+        } else if self.exit_may_replace_response_body() {
+            self.set_http_response_header("Content-Length", None);
         }
 
         if self.debug.is_some() {
@@ -515,15 +1383,51 @@ impl HttpContext for DataKitFilter {
     }
 
     fn on_http_response_body(&mut self, body_size: usize, eof: bool) -> Action {
+        // the limit is on the body that nodes read, not on one they only replace
+        let exceeded = (self.do_service_response_body || self.do_service_response_raw_body)
+            && self.exceeds_max_body(HttpResponseBody, body_size);
+        if exceeded && !self.do_response_body {
+            if eof && self.debug.is_some() {
+                self.debug_done(body_size);
+            }
+            return Action::Continue;
+        }
+
         if !eof {
+            if exceeded {
+                // the headers of the replacement body were already sent:
+                // drop the upstream body instead of passing it through
+                self.set_http_response_body(0, body_size, &[]);
+                return Action::Continue;
+            }
+            if self.traces_to_body() && !self.do_response_body {
+                // stream the body out as the trace is produced,
+                // dropping the chunks that it replaces
+                self.set_http_response_body(0, body_size, &[]);
+                return Action::Continue;
+            }
+            if self.debug.is_none() && !self.config.phase_has_work(HttpResponseBody) {
+                // nothing reads or replaces the body: do not buffer it
+                return Action::Continue;
+            }
             return Action::Pause;
         }
 
-        if eof && self.do_service_response_body {
+        if !exceeded && (self.do_service_response_body || self.do_service_response_raw_body) {
             if let Some(bytes) = self.get_http_response_body(0, body_size) {
-                let content_type = self.get_http_response_header("Content-Type");
-                if let Some(payload) = Payload::from_bytes(bytes, content_type.as_deref()) {
-                    self.set_body_data(ServiceResponse, payload);
+                if bytes.len() < body_size {
+                    self.fail_truncated_body(HttpResponseBody, bytes.len(), body_size);
+                    return Action::Continue;
+                }
+                self.debug_body_size(ServiceResponse, bytes.len());
+                if self.do_service_response_raw_body {
+                    self.set_implicit_data(ServiceResponse, RawBody, Payload::Raw(bytes.clone()));
+                }
+                if self.do_service_response_body {
+                    let content_type = self.get_http_response_header("Content-Type");
+                    if let Some(payload) = self.parse_body(bytes, content_type.as_deref()) {
+                        self.set_body_data(ServiceResponse, payload);
+                    }
                 }
             }
         }
@@ -531,43 +1435,60 @@ impl HttpContext for DataKitFilter {
         let action = self.run_nodes(HttpResponseBody);
 
         if self.do_response_body {
-            if let Some(payload) = self.get_body_data(Response) {
+            if let Some(bytes) = self.compressed_body.take() {
+                replace_response_body(self, body_size, &bytes);
+                self.debug_body_size(Response, bytes.len());
+            } else if let Some(payload) = self.get_body_data(Response) {
                 let content_type = self.get_http_response_header("Content-Type");
                 if let Ok(bytes) = payload.to_bytes(content_type.as_deref()) {
-                    self.set_http_response_body(0, bytes.len(), &bytes);
+                    replace_response_body(self, body_size, &bytes);
+                    self.debug_body_size(Response, bytes.len());
                 } else {
-                    self.set_http_response_body(0, 0, &[]);
+                    replace_response_body(self, body_size, &[]);
                 }
-            } else if let Some(debug) = &self.debug {
+            } else if exceeded {
+                replace_response_body(self, body_size, &[]);
+            } else if let Some(debug) = self.debug.as_ref().filter(|d| d.trace_sink().is_some()) {
+                // record the body passed through from the upstream,
+                // parsed with its original content type
                 if let Some(bytes) = self.get_http_response_body(0, body_size) {
+                    let size = bytes.len();
                     let content_type = debug.response_body_content_type();
-                    if let Some(payload) = Payload::from_bytes(bytes, content_type.as_deref()) {
+                    if let Some(payload) = self.parse_body(bytes, content_type.as_deref()) {
                         self.set_body_data(Response, payload);
                     }
+                    self.debug_body_size(Response, size);
                 }
             }
         }
 
         if self.debug.is_some() {
-            self.debug_done()
+            self.debug_done(body_size)
         }
 
         action
     }
+
+    fn on_log(&mut self) {
+        if let Some(debug) = &self.debug {
+            if debug.trace_sink() == Some(TraceSink::Log) {
+                log::info!("datakit trace: {}", debug.get_trace());
+            }
+        }
+    }
 }
 
 proxy_wasm::main! {{
-    nodes::register_node("implicit", Box::new(nodes::implicit::ImplicitFactory {}));
-    nodes::register_node("handlebars", Box::new(nodes::handlebars::HandlebarsFactory {}));
-    nodes::register_node("call", Box::new(nodes::call::CallFactory {}));
-    nodes::register_node("exit", Box::new(nodes::exit::ExitFactory {}));
-    nodes::register_node("jq", Box::new(nodes::jq::JqFactory {}));
-    nodes::register_node("property", Box::new(nodes::property::PropertyFactory {}));
+    nodes::register_builtin_nodes();
 
     proxy_wasm::set_log_level(LogLevel::Debug);
     proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
         Box::new(DataKitFilterRootContext {
             config: None,
+            tick_namespace: 0,
+            tick_nodes: BTreeMap::new(),
+            tick_last_run: vec![],
+            tick_waiting: BTreeMap::new(),
         })
     });
 }}
@@ -576,3 +1497,497 @@ proxy_wasm::main! {{
 // multiple callouts at once with different settings: http 1.0, 1.1, chunked encoding, content-length
 
 // test with bad responses
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mock_proxy_wasm::*;
+    use std::cell::RefCell;
+
+    /// What the filter did to the request and the response through
+    /// the host, shared between the test and the filter's `MockHost`.
+    #[derive(Default)]
+    struct HostState {
+        request_headers: RefCell<Vec<(String, String)>>,
+        request_body: RefCell<Vec<u8>>,
+        response_headers: RefCell<Vec<(String, String)>>,
+        response_body: RefCell<Vec<u8>>,
+        /// Set once the response headers were sent downstream, after
+        /// which they cannot be changed anymore.
+        headers_sent: Cell<bool>,
+        responses: RefCell<Vec<u32>>,
+        /// The time elapsed since the filter was created.
+        elapsed: Cell<Duration>,
+        call_timeouts: RefCell<Vec<Duration>>,
+    }
+
+    fn get_header(headers: &RefCell<Vec<(String, String)>>, name: &str) -> Option<String> {
+        headers
+            .borrow()
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.clone())
+    }
+
+    fn set_header(headers: &RefCell<Vec<(String, String)>>, name: &str, value: Option<&str>) {
+        let mut headers = headers.borrow_mut();
+        headers.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+        if let Some(value) = value {
+            headers.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    fn replace_body(body: &RefCell<Vec<u8>>, start: usize, size: usize, value: &[u8]) {
+        let mut body = body.borrow_mut();
+        let end = (start + size).min(body.len());
+        body.splice(start..end, value.iter().copied());
+    }
+
+    struct MockHost(Rc<HostState>);
+
+    impl MockHost {
+        fn check_headers_not_sent(&self) {
+            assert!(
+                !self.0.headers_sent.get(),
+                "response headers changed after they were sent"
+            );
+        }
+    }
+
+    #[mock_proxy_wasm_context]
+    impl Context for MockHost {
+        fn get_property(&self, _path: Vec<&str>) -> Option<Bytes> {
+            None
+        }
+
+        fn get_current_time(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH + self.0.elapsed.get()
+        }
+
+        fn dispatch_http_call(
+            &self,
+            _upstream: &str,
+            _headers: Vec<(&str, &str)>,
+            _body: Option<&[u8]>,
+            _trailers: Vec<(&str, &str)>,
+            timeout: Duration,
+        ) -> Result<u32, Status> {
+            let mut timeouts = self.0.call_timeouts.borrow_mut();
+            timeouts.push(timeout);
+            Ok(timeouts.len() as u32)
+        }
+    }
+
+    #[mock_proxy_wasm_http_context]
+    impl HttpContext for MockHost {
+        fn get_http_request_headers(&self) -> Vec<(String, String)> {
+            self.0.request_headers.borrow().clone()
+        }
+
+        fn get_http_request_header(&self, name: &str) -> Option<String> {
+            get_header(&self.0.request_headers, name)
+        }
+
+        fn set_http_request_headers(&self, headers: Vec<(&str, &str)>) {
+            *self.0.request_headers.borrow_mut() = headers
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+        }
+
+        fn set_http_request_header(&self, name: &str, value: Option<&str>) {
+            set_header(&self.0.request_headers, name, value);
+        }
+
+        fn get_http_request_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
+            let body = self.0.request_body.borrow();
+            Some(body.iter().skip(start).take(max_size).copied().collect())
+        }
+
+        fn set_http_request_body(&self, start: usize, size: usize, value: &[u8]) {
+            replace_body(&self.0.request_body, start, size, value);
+        }
+
+        fn get_http_response_headers(&self) -> Vec<(String, String)> {
+            self.0.response_headers.borrow().clone()
+        }
+
+        fn get_http_response_header(&self, name: &str) -> Option<String> {
+            get_header(&self.0.response_headers, name)
+        }
+
+        fn set_http_response_header(&self, name: &str, value: Option<&str>) {
+            self.check_headers_not_sent();
+            set_header(&self.0.response_headers, name, value);
+        }
+
+        fn add_http_response_header(&self, name: &str, value: &str) {
+            self.check_headers_not_sent();
+            let mut headers = self.0.response_headers.borrow_mut();
+            headers.push((name.to_string(), value.to_string()));
+        }
+
+        fn get_http_response_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
+            let body = self.0.response_body.borrow();
+            Some(body.iter().skip(start).take(max_size).copied().collect())
+        }
+
+        fn set_http_response_body(&self, start: usize, size: usize, value: &[u8]) {
+            replace_body(&self.0.response_body, start, size, value);
+        }
+
+        fn send_http_response(
+            &self,
+            status_code: u32,
+            _headers: Vec<(&str, &str)>,
+            _body: Option<&[u8]>,
+        ) {
+            let mut responses = self.0.responses.borrow_mut();
+            assert!(responses.is_empty(), "a second response was sent");
+            responses.push(status_code);
+        }
+    }
+
+    fn filter(config: &str) -> (DataKitFilter, Rc<HostState>) {
+        nodes::register_builtin_nodes();
+        let config = Config::new(config.as_bytes().to_vec(), &IMPLICIT_NODES).unwrap();
+        let host = Rc::new(HostState::default());
+        let filter = DataKitFilter::new(Rc::new(config), Box::new(MockHost(host.clone())), 0);
+        (filter, host)
+    }
+
+    fn service_request_graph(output: &str) -> DependencyGraph {
+        nodes::register_builtin_nodes();
+        let config = format!(
+            r#"{{ "nodes": [ {{
+                "name": "hash",
+                "type": "jq",
+                "input": "request.body",
+                "output": "{output}",
+                "jq": "length"
+            }} ] }}"#
+        );
+        Config::new(config.into_bytes(), &IMPLICIT_NODES)
+            .unwrap()
+            .get_graph()
+            .clone()
+    }
+
+    #[test]
+    fn call_keys() {
+        let headers = [(":method", "GET"), (":path", "/users/1")];
+        let key = call_key("users", &headers, None, &[]);
+        assert_eq!(key, call_key("users", &headers, None, &[]));
+
+        let other = [(":method", "GET"), (":path", "/users/2")];
+        assert_ne!(key, call_key("users", &other, None, &[]));
+        assert_ne!(key, call_key("accounts", &headers, None, &[]));
+        assert_ne!(key, call_key("users", &headers, Some(b""), &[]));
+    }
+
+    #[test]
+    fn implicit_port_positions() {
+        assert_eq!(0, usize::from(Request));
+        assert_eq!(3, usize::from(Response));
+        assert_eq!(0, Body.at(ServiceRequest));
+        assert_eq!(2, Query.at(ServiceRequest));
+        assert_eq!(4, RawBody.at(Request));
+        assert_eq!(5, Meta.at(Request));
+        assert_eq!(6, Tls.at(Request));
+        assert_eq!(2, RawBody.at(ServiceResponse));
+        assert_eq!(2, SetCookies.at(Response));
+
+        // the ids do not depend on the order of the implicit nodes
+        let reversed = [
+            ImplicitNode::new(Response.name(), RESP_IN_PORTS.clone(), RESP_PORTS.clone()),
+            ImplicitNode::new(Request.name(), vec![], REQ_OUT_PORTS.clone()),
+            ImplicitNode::new(ServiceRequest.name(), REQ_PORTS.clone(), RESP_PORTS.clone()),
+            ImplicitNode::new(ServiceResponse.name(), vec![], RESP_OUT_PORTS.clone()),
+        ];
+        let positions = ImplicitPositions::new(&reversed);
+        assert_eq!(0, positions.nodes[Response as usize]);
+        assert_eq!(1, positions.nodes[Request as usize]);
+        assert_eq!(
+            Some(2),
+            positions.ports[Response as usize][SetCookies as usize]
+        );
+        assert_eq!(None, positions.ports[Response as usize][Tls as usize]);
+    }
+
+    #[test]
+    fn tick_data_keys() {
+        // nodes of the same name in different configurations do not
+        // share their data
+        let config = |url: &str| {
+            format!(r#"{{"nodes":[{{"name":"refresh","type":"call","url":"{url}"}}]}}"#)
+        };
+        let a = tick_namespace(config("http://a.example.com").as_bytes());
+        let b = tick_namespace(config("http://b.example.com").as_bytes());
+        assert_ne!(tick_data_key(a, "refresh"), tick_data_key(b, "refresh"));
+        assert_eq!(tick_data_key(a, "refresh"), tick_data_key(a, "refresh"));
+    }
+
+    #[test]
+    fn service_request_body_tee() {
+        // reading the request body leaves the upstream body alone
+        let graph = service_request_graph("response.body");
+        assert!(graph.has_dependents(Request.into(), Body.at(Request)));
+        assert!(!replaces_service_request_body(&graph));
+
+        let graph = service_request_graph("service_request.headers");
+        assert!(!replaces_service_request_body(&graph));
+
+        // producing it replaces it
+        let graph = service_request_graph("service_request.body");
+        assert!(replaces_service_request_body(&graph));
+    }
+
+    #[test]
+    fn call_timeout_clamped_to_deadline() {
+        let (mut filter, host) = filter(
+            r#"{
+                "deadline_ms": 500,
+                "nodes": [ {
+                    "name": "SLOW",
+                    "type": "call",
+                    "url": "http://example.com/slow",
+                    "timeout": 5
+                }, {
+                    "name": "FAST",
+                    "type": "call",
+                    "url": "http://example.com/fast",
+                    "timeout": "100ms"
+                } ]
+            }"#,
+        );
+
+        host.elapsed.set(Duration::from_millis(200));
+        assert_eq!(Action::Pause, filter.on_http_request_headers(0, false));
+
+        // only the time left before the deadline is waited for
+        assert_eq!(
+            vec![Duration::from_millis(300), Duration::from_millis(100)],
+            *host.call_timeouts.borrow()
+        );
+    }
+
+    /// Send a JSON request body through the filter, returning what
+    /// the mock host holds for the upstream request afterwards.
+    fn send_request_body(config: &str, body: &[u8]) -> Rc<HostState> {
+        let (mut filter, host) = filter(config);
+        *host.request_headers.borrow_mut() = vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Content-Length".to_string(), body.len().to_string()),
+        ];
+        *host.request_body.borrow_mut() = body.to_vec();
+
+        filter.on_http_request_headers(2, false);
+        filter.on_http_request_body(body.len(), true);
+        host
+    }
+
+    #[test]
+    fn request_body_tee() {
+        let body = br#"{ "user": { "id": 42 } }"#;
+
+        // reading the request body leaves the upstream body alone
+        let host = send_request_body(
+            r#"{ "nodes": [ {
+                "name": "USER_ID",
+                "type": "jq",
+                "inputs": { "body": "request.body", "headers": "request.headers" },
+                "output": "service_request.headers",
+                "jq": "$headers + { \"x-user-id\": ($body.user.id | tostring) }"
+            } ] }"#,
+            body,
+        );
+        assert_eq!(body.to_vec(), *host.request_body.borrow());
+        let header = |name| get_header(&host.request_headers, name);
+        assert_eq!(Some("42".to_string()), header("x-user-id"));
+        assert_eq!(Some(body.len().to_string()), header("Content-Length"));
+
+        // producing it replaces it
+        let host = send_request_body(
+            r#"{ "nodes": [ {
+                "name": "USER",
+                "type": "jq",
+                "input": "request.body",
+                "output": "service_request.body",
+                "jq": "$request_body.user"
+            } ] }"#,
+            body,
+        );
+        assert_eq!(br#"{"id":42}"#.to_vec(), *host.request_body.borrow());
+        assert_eq!(None, get_header(&host.request_headers, "Content-Length"));
+    }
+
+    #[test]
+    fn fail_after_exit_response() {
+        let (mut filter, host) = filter(
+            r#"{ "nodes": [ {
+                "name": "DENY",
+                "type": "exit",
+                "status": 403,
+                "inputs": { "body": "request.headers" }
+            }, {
+                "name": "BOOM",
+                "type": "jq",
+                "input": "request.headers",
+                "jq": "error(\"boom\")"
+            } ] }"#,
+        );
+
+        // the mock host panics on a second response
+        filter.on_http_request_headers(0, false);
+
+        assert!(filter.failed);
+        assert_eq!(vec![403], *host.responses.borrow());
+    }
+
+    #[test]
+    fn trace_sink_from_header() {
+        let sink = |v: Option<&str>| header_to_trace_sink(&v.map(str::to_string));
+        assert_eq!(None, sink(None));
+        assert_eq!(None, sink(Some("off")));
+        assert_eq!(None, sink(Some("0")));
+        assert_eq!(Some(TraceSink::Body), sink(Some("on")));
+        assert_eq!(Some(TraceSink::Body), sink(Some("body")));
+        assert_eq!(Some(TraceSink::Log), sink(Some("log")));
+    }
+
+    /// Send the upstream response headers through the filter, after
+    /// which the mock host rejects any change to them.
+    fn response_headers(filter: &mut DataKitFilter, host: &HostState, headers: &[(&str, &str)]) {
+        *host.response_headers.borrow_mut() = headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        filter.on_http_response_headers(headers.len(), false);
+        host.headers_sent.set(true);
+    }
+
+    const UPSTREAM_BODY: &[u8] = br#"{"a longer":"upstream body"}"#;
+
+    #[test]
+    fn response_body_replaced_smaller() {
+        let (mut filter, host) = filter(
+            r#"{ "nodes": [ {
+                "name": "SHORT",
+                "type": "jq",
+                "input": "service_response.body",
+                "output": "response.body",
+                "jq": "\"short\""
+            } ] }"#,
+        );
+
+        let content_length = UPSTREAM_BODY.len().to_string();
+        response_headers(
+            &mut filter,
+            &host,
+            &[
+                ("Content-Type", "application/json"),
+                ("Content-Length", &content_length),
+            ],
+        );
+        assert_eq!(None, get_header(&host.response_headers, "Content-Length"));
+
+        *host.response_body.borrow_mut() = UPSTREAM_BODY.to_vec();
+        filter.on_http_response_body(UPSTREAM_BODY.len(), true);
+        assert_eq!(br#""short""#.to_vec(), *host.response_body.borrow());
+    }
+
+    #[test]
+    fn max_body_not_applied_to_replaced_body() {
+        let (mut filter, host) = filter(
+            r#"{ "max_body_bytes": 8, "nodes": [ {
+                "name": "REPLACED",
+                "type": "static",
+                "value": "replaced",
+                "output": "response.body"
+            } ] }"#,
+        );
+        filter.on_http_request_headers(0, false);
+
+        let content_length = UPSTREAM_BODY.len().to_string();
+        response_headers(
+            &mut filter,
+            &host,
+            &[
+                ("Content-Type", "application/json"),
+                ("Content-Length", &content_length),
+            ],
+        );
+        assert_eq!(
+            Some("10".to_string()),
+            get_header(&host.response_headers, "Content-Length")
+        );
+
+        *host.response_body.borrow_mut() = UPSTREAM_BODY.to_vec();
+        filter.on_http_response_body(UPSTREAM_BODY.len(), true);
+        assert_eq!(br#""replaced""#.to_vec(), *host.response_body.borrow());
+    }
+
+    #[test]
+    fn max_body_exceeded_drops_replaced_body() {
+        let (mut filter, host) = filter(
+            r#"{ "max_body_bytes": 8, "nodes": [ {
+                "name": "SHORT",
+                "type": "jq",
+                "input": "service_response.body",
+                "output": "response.body",
+                "jq": "\"short\""
+            } ] }"#,
+        );
+
+        response_headers(&mut filter, &host, &[("Content-Type", "application/json")]);
+
+        // the chunk over the limit is dropped, and so are the next ones
+        let (first, last) = UPSTREAM_BODY.split_at(12);
+        *host.response_body.borrow_mut() = first.to_vec();
+        assert_eq!(
+            Action::Continue,
+            filter.on_http_response_body(first.len(), false)
+        );
+        assert!(host.response_body.borrow().is_empty());
+
+        *host.response_body.borrow_mut() = last[..4].to_vec();
+        assert_eq!(Action::Continue, filter.on_http_response_body(4, false));
+        assert!(host.response_body.borrow().is_empty());
+
+        *host.response_body.borrow_mut() = last[4..].to_vec();
+        filter.on_http_response_body(last.len() - 4, true);
+        assert!(host.response_body.borrow().is_empty());
+    }
+
+    #[test]
+    fn exit_replaces_response_body() {
+        let (mut filter, host) = filter(
+            r#"{ "nodes": [ {
+                "name": "SHORT",
+                "type": "jq",
+                "input": "service_response.body",
+                "jq": "\"short\""
+            }, {
+                "name": "EXIT",
+                "type": "exit",
+                "inputs": { "body": "SHORT" }
+            } ] }"#,
+        );
+
+        // the exit only runs with the body, after the headers are sent
+        let content_length = UPSTREAM_BODY.len().to_string();
+        response_headers(
+            &mut filter,
+            &host,
+            &[
+                ("Content-Type", "application/json"),
+                ("Content-Length", &content_length),
+            ],
+        );
+        assert_eq!(None, get_header(&host.response_headers, "Content-Length"));
+
+        *host.response_body.borrow_mut() = UPSTREAM_BODY.to_vec();
+        filter.on_http_response_body(UPSTREAM_BODY.len(), true);
+        assert_eq!(br#""short""#.to_vec(), *host.response_body.borrow());
+    }
+}
@@ -5,12 +5,35 @@ use std::collections::BTreeMap;
 use std::sync::{Mutex, OnceLock};
 
 use crate::data::{Input, State, State::*};
+use crate::payload::{ErrorKind, Payload};
 
+pub mod aggregate;
+pub mod assert;
 pub mod call;
+pub mod coalesce;
+pub mod concat;
+pub mod csv;
+pub mod dedupe;
+pub mod equals;
 pub mod exit;
+pub mod filter_headers;
+pub mod form;
+pub mod grpc_call;
 pub mod handlebars;
+pub mod header_list;
+pub mod join;
 pub mod jq;
+pub mod jsonpath;
+pub mod limit;
+pub mod patch;
 pub mod property;
+pub mod protobuf;
+pub mod rate_limit;
+pub mod select;
+pub mod split_path;
+pub mod static_value;
+pub mod substitute;
+pub mod urlcodec;
 
 pub type NodeVec = Vec<Box<dyn Node>>;
 
@@ -50,6 +73,12 @@ pub trait Node {
     fn resume(&self, _ctx: &dyn HttpContext, _input: &Input) -> State {
         Done(vec![None])
     }
+
+    /// The outgoing request made by the latest run, if any,
+    /// to be shown in debug traces.
+    fn debug_request(&self) -> Option<Value> {
+        None
+    }
 }
 
 pub struct NodeDefaultLink {
@@ -68,6 +97,19 @@ pub trait NodeConfig {
     fn default_outputs(&self) -> Option<Vec<NodeDefaultLink>> {
         None
     }
+
+    /// Whether the node runs without the inputs that user nodes finished
+    /// without producing, instead of waiting for them forever.
+    fn optional_inputs(&self) -> bool {
+        false
+    }
+
+    /// Whether the outputs of the node only signal that its side effect
+    /// was performed, such as setting a property. They never carry data,
+    /// whatever the node produces for them.
+    fn sink_outputs(&self) -> bool {
+        false
+    }
 }
 
 pub trait NodeFactory: Send {
@@ -79,7 +121,7 @@ pub trait NodeFactory: Send {
         bt: &BTreeMap<String, Value>,
     ) -> Result<Box<dyn NodeConfig>, String>;
 
-    fn new_node(&self, config: &dyn NodeConfig) -> Box<dyn Node>;
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String>;
 
     fn default_input_ports(&self) -> PortConfig;
 
@@ -97,6 +139,57 @@ pub fn register_node(name: &str, factory: Box<dyn NodeFactory>) {
     node_types().lock().unwrap().insert(name.into(), factory);
 }
 
+#[cfg(test)]
+pub fn unregister_node(name: &str) {
+    node_types().lock().unwrap().remove(name);
+}
+
+/// Register all node types implemented by DataKit.
+pub fn register_builtin_nodes() {
+    register_node("implicit", Box::new(implicit::ImplicitFactory {}));
+    register_node("grpc_call", Box::new(grpc_call::GrpcCallFactory {}));
+    register_node("handlebars", Box::new(handlebars::HandlebarsFactory {}));
+    register_node("aggregate", Box::new(aggregate::AggregateFactory {}));
+    register_node("assert", Box::new(assert::AssertFactory {}));
+    register_node("call", Box::new(call::CallFactory {}));
+    register_node("coalesce", Box::new(coalesce::CoalesceFactory {}));
+    register_node("concat", Box::new(concat::ConcatFactory {}));
+    register_node("csv", Box::new(csv::CsvFactory {}));
+    register_node("dedupe", Box::new(dedupe::DedupeFactory {}));
+    register_node("equals", Box::new(equals::EqualsFactory {}));
+    register_node("exit", Box::new(exit::ExitFactory {}));
+    register_node(
+        "filter_headers",
+        Box::new(filter_headers::FilterHeadersFactory::filter()),
+    );
+    register_node("form", Box::new(form::FormFactory {}));
+    register_node("join", Box::new(join::JoinFactory {}));
+    register_node(
+        "join_header",
+        Box::new(header_list::HeaderListFactory::join()),
+    );
+    register_node("jq", Box::new(jq::JqFactory {}));
+    register_node("jsonpath", Box::new(jsonpath::JsonPathFactory {}));
+    register_node("limit", Box::new(limit::LimitFactory {}));
+    register_node("patch", Box::new(patch::PatchFactory {}));
+    register_node("property", Box::new(property::PropertyFactory {}));
+    register_node("protobuf", Box::new(protobuf::ProtobufFactory {}));
+    register_node("rate_limit", Box::new(rate_limit::RateLimitFactory {}));
+    register_node(
+        "remove_headers",
+        Box::new(filter_headers::FilterHeadersFactory::remove()),
+    );
+    register_node("select", Box::new(select::SelectFactory {}));
+    register_node(
+        "split_header",
+        Box::new(header_list::HeaderListFactory::split()),
+    );
+    register_node("split_path", Box::new(split_path::SplitPathFactory {}));
+    register_node("static", Box::new(static_value::StaticFactory {}));
+    register_node("substitute", Box::new(substitute::SubstituteFactory {}));
+    register_node("urlcodec", Box::new(urlcodec::UrlCodecFactory {}));
+}
+
 fn with_node_type<T>(node_type: &str, f: impl Fn(&Box<dyn NodeFactory>) -> T) -> Option<T>
 where
     T: Sized,
@@ -131,8 +224,46 @@ pub fn new_config(
 }
 
 pub fn new_node(node_type: &str, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
-    with_node_type(node_type, |nf| nf.new_node(config))
-        .ok_or(format!("no such node type: {node_type}"))
+    match with_node_type(node_type, |nf| nf.new_node(config)) {
+        Some(Ok(ok)) => Ok(ok),
+        Some(Err(e)) => Err(format!("{node_type}: {e}")),
+        None => Err(format!("no such node type: {node_type}")),
+    }
+}
+
+/// Replace the whole buffered response body, which had `body_size` bytes,
+/// with `bytes`. The response headers are already sent at this point, so
+/// a `Content-Length` announcing the upstream length must be dropped when
+/// sending them, as the filter does whenever the body may be replaced.
+pub fn replace_response_body(ctx: &dyn HttpContext, body_size: usize, bytes: &[u8]) {
+    ctx.set_http_response_body(0, body_size, bytes);
+}
+
+/// Stands in for a node that could not be built, so that the list of
+/// nodes stays aligned with the node indices of the graph. It fails
+/// whenever it runs, so that a node that could not be built, such as an
+/// `exit` node guarding the upstream, never lets a request through.
+pub struct Placeholder {
+    n_outputs: usize,
+    error: String,
+}
+
+impl Placeholder {
+    pub fn new(n_outputs: usize, error: String) -> Self {
+        Placeholder { n_outputs, error }
+    }
+}
+
+impl Node for Placeholder {
+    fn run(&self, _ctx: &dyn HttpContext, _input: &Input) -> State {
+        let mut ports = vec![None; self.n_outputs.max(1)];
+        ports[0] = Some(Payload::Error(ErrorKind::InternalError, self.error.clone()));
+        Fail(ports)
+    }
+
+    fn resume(&self, ctx: &dyn HttpContext, input: &Input) -> State {
+        self.run(ctx, input)
+    }
 }
 
 pub mod implicit {
@@ -179,8 +310,8 @@ pub mod implicit {
             Ok(Box::new(ImplicitConfig {}))
         }
 
-        fn new_node(&self, _config: &dyn NodeConfig) -> Box<dyn Node> {
-            Box::new(Implicit {})
+        fn new_node(&self, _config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+            Ok(Box::new(Implicit {}))
         }
     }
 }
@@ -0,0 +1,269 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+#[derive(Clone, Debug)]
+pub struct AggregateConfig {
+    op: Op,
+    by: Option<Vec<String>>,
+    strict: bool,
+}
+
+impl NodeConfig for AggregateConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Aggregate {
+    config: AggregateConfig,
+}
+
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |v, key| v.get(key))
+}
+
+/// The sum of the numbers, as an integer unless some of them
+/// are not integers or the sum does not fit in one.
+fn sum(numbers: &[&Value]) -> Value {
+    let integers: Option<i64> = numbers
+        .iter()
+        .try_fold(0i64, |acc, n| n.as_i64().and_then(|n| acc.checked_add(n)));
+    match integers {
+        Some(total) => total.into(),
+        None => numbers
+            .iter()
+            .filter_map(|n| n.as_f64())
+            .sum::<f64>()
+            .into(),
+    }
+}
+
+/// The smallest or largest of the numbers, as given in the input.
+fn extreme(numbers: &[&Value], largest: bool) -> Value {
+    let value = |n: &&Value| n.as_f64().unwrap_or(f64::NAN);
+    let found = numbers.iter().copied().reduce(|a, b| {
+        let b_wins = if largest {
+            value(&b) > value(&a)
+        } else {
+            value(&b) < value(&a)
+        };
+        if b_wins {
+            b
+        } else {
+            a
+        }
+    });
+    found.cloned().unwrap_or(Value::Null)
+}
+
+impl Aggregate {
+    fn aggregate(&self, value: &Value) -> Result<Value, String> {
+        let Value::Array(entries) = value else {
+            return Err("aggregate: input must be an array".into());
+        };
+
+        let values: Vec<Option<&Value>> = entries
+            .iter()
+            .map(|entry| match &self.config.by {
+                Some(path) => lookup(entry, path),
+                None => Some(entry),
+            })
+            .collect();
+
+        if self.config.op == Op::Count {
+            let count = values
+                .iter()
+                .filter(|v| !matches!(v, None | Some(Value::Null)))
+                .count();
+            return Ok(count.into());
+        }
+
+        let mut numbers = Vec::with_capacity(values.len());
+        for (i, v) in values.into_iter().enumerate() {
+            match v {
+                Some(n @ Value::Number(_)) => numbers.push(n),
+                _ if self.config.strict => {
+                    return Err(format!("aggregate: entry {i} is not a number"));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(match self.config.op {
+            Op::Count => unreachable!("counted above"),
+            Op::Sum => sum(&numbers),
+            Op::Min => extreme(&numbers, false),
+            Op::Max => extreme(&numbers, true),
+            Op::Avg if numbers.is_empty() => Value::Null,
+            Op::Avg => {
+                let total: f64 = numbers.iter().filter_map(|n| n.as_f64()).sum();
+                (total / numbers.len() as f64).into()
+            }
+        })
+    }
+}
+
+impl Node for Aggregate {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        let result = match input.get(0) {
+            None => return Done(vec![None]),
+            Some(Payload::Error(_, e)) => Err(e.clone()),
+            Some(payload) => payload.to_json().and_then(|value| self.aggregate(&value)),
+        };
+
+        match result {
+            Ok(value) => Done(vec![Some(Payload::Json(value))]),
+            Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+        }
+    }
+}
+
+pub struct AggregateFactory {}
+
+impl NodeFactory for AggregateFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let op = match get_config_value::<String>(bt, "op").as_deref() {
+            Some("count") => Op::Count,
+            Some("sum") => Op::Sum,
+            Some("min") => Op::Min,
+            Some("max") => Op::Max,
+            Some("avg") => Op::Avg,
+            Some(other) => return Err(format!("aggregate: invalid 'op': {other}")),
+            None => return Err("aggregate: missing 'op'".into()),
+        };
+
+        Ok(Box::new(AggregateConfig {
+            op,
+            by: get_config_value::<String>(bt, "by")
+                .map(|by| by.split('.').map(str::to_owned).collect()),
+            strict: get_config_value(bt, "strict").unwrap_or(false),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<AggregateConfig>() {
+            Some(ac) => Ok(Box::new(Aggregate { config: ac.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn aggregate(op: Op, by: Option<&str>, strict: bool, value: Value) -> Result<Value, String> {
+        let node = Aggregate {
+            config: AggregateConfig {
+                op,
+                by: by.map(|by| by.split('.').map(str::to_owned).collect()),
+                strict,
+            },
+        };
+        node.aggregate(&value)
+    }
+
+    #[test]
+    fn aggregate_numbers() {
+        let numbers = json!([3, 1, 4, 1, 5]);
+        let run = |op| aggregate(op, None, false, numbers.clone());
+        assert_eq!(Ok(json!(5)), run(Op::Count));
+        assert_eq!(Ok(json!(14)), run(Op::Sum));
+        assert_eq!(Ok(json!(1)), run(Op::Min));
+        assert_eq!(Ok(json!(5)), run(Op::Max));
+        assert_eq!(Ok(json!(2.8)), run(Op::Avg));
+
+        assert_eq!(
+            Ok(json!(4.0)),
+            aggregate(Op::Sum, None, false, json!([1.5, 2.5]))
+        );
+    }
+
+    #[test]
+    fn aggregate_by_key() {
+        let items = json!([
+            { "item": { "price": 10 } },
+            { "item": { "price": 2.5 } },
+            { "item": { "price": null } },
+            { "other": 1 },
+        ]);
+        let run = |op| aggregate(op, Some("item.price"), false, items.clone());
+        assert_eq!(Ok(json!(2)), run(Op::Count));
+        assert_eq!(Ok(json!(12.5)), run(Op::Sum));
+        assert_eq!(Ok(json!(2.5)), run(Op::Min));
+        assert_eq!(Ok(json!(10)), run(Op::Max));
+        assert_eq!(Ok(json!(6.25)), run(Op::Avg));
+    }
+
+    #[test]
+    fn aggregate_strict() {
+        let values = json!([1, "2", 3]);
+        assert_eq!(
+            Ok(json!(4)),
+            aggregate(Op::Sum, None, false, values.clone())
+        );
+        assert_eq!(
+            Err("aggregate: entry 1 is not a number".to_string()),
+            aggregate(Op::Sum, None, true, values.clone())
+        );
+        // counting accepts any value
+        assert_eq!(Ok(json!(3)), aggregate(Op::Count, None, true, values));
+    }
+
+    #[test]
+    fn aggregate_empty() {
+        let run = |op| aggregate(op, None, false, json!([]));
+        assert_eq!(Ok(json!(0)), run(Op::Count));
+        assert_eq!(Ok(json!(0)), run(Op::Sum));
+        assert_eq!(Ok(Value::Null), run(Op::Min));
+        assert_eq!(Ok(Value::Null), run(Op::Avg));
+
+        assert!(aggregate(Op::Sum, None, false, json!({ "a": 1 })).is_err());
+    }
+
+    #[test]
+    fn aggregate_invalid_op() {
+        let bt = BTreeMap::from([("op".to_string(), json!("median"))]);
+        assert!(AggregateFactory {}.new_config("A", &[], &[], &bt).is_err());
+        assert!(AggregateFactory {}
+            .new_config("A", &[], &[], &BTreeMap::new())
+            .is_err());
+    }
+}
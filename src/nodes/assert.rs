@@ -0,0 +1,230 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::config::get_config_value;
+use crate::data::{Input, Phase, State, State::*};
+use crate::nodes::jq::Jq;
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+const DEFAULT_MESSAGE: &str = "assertion failed";
+
+#[derive(Clone)]
+enum Condition {
+    /// A jq program whose first result must be truthy,
+    /// run with the input as `.`.
+    Jq(Rc<Jq>),
+    Equals(Value),
+}
+
+#[derive(Clone)]
+pub struct AssertConfig {
+    condition: Condition,
+    message: String,
+}
+
+impl NodeConfig for AssertConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Assert {
+    config: AssertConfig,
+}
+
+impl Assert {
+    fn holds(
+        &self,
+        value: &Value,
+        input: Option<&Payload>,
+        phase: Option<Phase>,
+    ) -> Result<bool, String> {
+        match &self.config.condition {
+            Condition::Jq(jq) => jq.holds(&[input], phase),
+            Condition::Equals(expected) => Ok(value == expected),
+        }
+    }
+
+    /// The failure message, with `${value}` replaced by the input.
+    fn message(&self, input: Option<&Payload>) -> String {
+        let value = match input {
+            Some(payload) => payload
+                .to_bytes(None)
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                .unwrap_or_default(),
+            None => "null".into(),
+        };
+        self.config.message.replace("${value}", &value)
+    }
+
+    fn check(&self, input: Option<&Payload>, phase: Option<Phase>) -> State {
+        let value = match input.map(Payload::to_json) {
+            Some(Ok(value)) => value,
+            Some(Err(e)) => {
+                return Fail(vec![Some(Payload::Error(
+                    ErrorKind::ParseError,
+                    format!("assert: {e}"),
+                ))])
+            }
+            None => Value::Null,
+        };
+
+        match self.holds(&value, input, phase) {
+            Ok(true) => Done(vec![input.cloned()]),
+            Ok(false) => Fail(vec![Some(Payload::Error(
+                ErrorKind::AssertionFailed,
+                self.message(input),
+            ))]),
+            Err(e) => Fail(vec![Some(Payload::Error(
+                ErrorKind::ConfigError,
+                format!("assert: {e}"),
+            ))]),
+        }
+    }
+}
+
+impl Node for Assert {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        self.check(input.get(0), Some(input.phase))
+    }
+}
+
+pub struct AssertFactory {}
+
+impl NodeFactory for AssertFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let condition = match (
+            get_config_value::<String>(bt, "condition"),
+            bt.get("equals"),
+        ) {
+            (Some(condition), None) => {
+                let prelude: String = get_config_value(bt, "jq_prelude").unwrap_or_default();
+                let program = format!("$value | ({condition})");
+                let jq =
+                    Jq::with_prelude(&program, &prelude, vec!["value".into()], BTreeMap::new())
+                        .map_err(|e| format!("assert: invalid 'condition': {e}"))?;
+                Condition::Jq(Rc::new(jq))
+            }
+            (None, Some(expected)) => Condition::Equals(expected.clone()),
+            _ => return Err("assert: exactly one of 'condition' or 'equals' is required".into()),
+        };
+
+        Ok(Box::new(AssertConfig {
+            condition,
+            message: get_config_value(bt, "message").unwrap_or(DEFAULT_MESSAGE.into()),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<AssertConfig>() {
+            Some(ac) => Ok(Box::new(Assert { config: ac.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn new_assert(bt: Value) -> Result<Assert, String> {
+        let Value::Object(map) = bt else {
+            panic!("expected an object");
+        };
+        let bt = map.into_iter().collect();
+        let config = AssertFactory {}.new_config("ASSERT", &[], &[], &bt)?;
+        let config = config.as_any().downcast_ref::<AssertConfig>().unwrap();
+        Ok(Assert {
+            config: config.clone(),
+        })
+    }
+
+    fn failure(message: &str) -> State {
+        Fail(vec![Some(Payload::Error(
+            ErrorKind::AssertionFailed,
+            message.into(),
+        ))])
+    }
+
+    #[test]
+    fn assert_condition() {
+        let node = new_assert(json!({
+            "condition": ".status == 200",
+            "message": "unexpected response: ${value}"
+        }))
+        .unwrap();
+
+        let ok = Payload::Json(json!({ "status": 200 }));
+        assert_eq!(Done(vec![Some(ok.clone())]), node.check(Some(&ok), None));
+
+        let bad = Payload::Json(json!({ "status": 500 }));
+        assert_eq!(
+            failure(r#"unexpected response: {"status":500}"#),
+            node.check(Some(&bad), None)
+        );
+    }
+
+    #[test]
+    fn assert_truthiness() {
+        let node = new_assert(json!({ "condition": ".items | length" })).unwrap();
+        let input = Payload::Json(json!({ "items": [] }));
+        assert_eq!(
+            Done(vec![Some(input.clone())]),
+            node.check(Some(&input), None)
+        );
+
+        let node = new_assert(json!({ "condition": ".missing" })).unwrap();
+        assert_eq!(failure("assertion failed"), node.check(Some(&input), None));
+
+        let node = new_assert(json!({ "condition": "empty" })).unwrap();
+        assert_eq!(failure("assertion failed"), node.check(Some(&input), None));
+    }
+
+    #[test]
+    fn assert_equals() {
+        let node = new_assert(json!({ "equals": "ok", "message": "got ${value}" })).unwrap();
+        let ok = Payload::Raw(b"ok".to_vec());
+        assert_eq!(Done(vec![Some(ok.clone())]), node.check(Some(&ok), None));
+
+        let ok = Payload::Json(json!("ok"));
+        assert_eq!(Done(vec![Some(ok.clone())]), node.check(Some(&ok), None));
+
+        assert_eq!(
+            failure("got nope"),
+            node.check(Some(&Payload::Json(json!("nope"))), None)
+        );
+        assert_eq!(failure("got null"), node.check(None, None));
+    }
+
+    #[test]
+    fn assert_invalid_config() {
+        assert!(new_assert(json!({})).is_err());
+        assert!(new_assert(json!({ "condition": ".", "equals": 1 })).is_err());
+        assert!(new_assert(json!({ "condition": "nope!" })).is_err());
+    }
+}
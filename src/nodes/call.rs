@@ -2,15 +2,16 @@ use log;
 use proxy_wasm::traits::*;
 use serde_json::Value;
 use std::any::Any;
+use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::time::Duration;
 use url::Url;
 
-use crate::config::get_config_value;
+use crate::config::{check_resolved, get_config_value};
 use crate::data::{Input, State, State::*};
 use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
 use crate::payload;
-use crate::payload::Payload;
+use crate::payload::{ErrorKind, Payload};
 
 #[derive(Clone, Debug)]
 pub struct CallConfig {
@@ -20,7 +21,8 @@ pub struct CallConfig {
     // node-specific configuration fields:
     url: String,
     method: String,
-    timeout: u32,
+    timeout: Duration,
+    cluster: Option<String>,
 }
 
 impl NodeConfig for CallConfig {
@@ -31,13 +33,14 @@ impl NodeConfig for CallConfig {
 
 pub struct Call {
     config: CallConfig,
+    dispatched: RefCell<Option<Value>>,
 }
 
-fn fail(msg: String) -> State {
-    Fail(vec![Some(Payload::Error(msg))])
+fn fail(kind: ErrorKind, msg: String) -> State {
+    Fail(vec![Some(Payload::Error(kind, msg))])
 }
 
-fn path_with_query(call_url: &Url, query: &Option<&Payload>) -> String {
+fn path_with_query(call_url: &Url, query: Option<&Payload>) -> String {
     let p = call_url.path().to_owned();
     match query {
         Some(q) => p + "?" + &*q.to_pwm_query(),
@@ -48,25 +51,47 @@ fn path_with_query(call_url: &Url, query: &Option<&Payload>) -> String {
     }
 }
 
+/// Describe a dispatched request for debug traces.
+fn dispatched_request(method: &str, url: &str, upstream: &str, headers: &[(&str, &str)]) -> Value {
+    let headers = headers
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let headers = payload::from_pwm_headers(headers)
+        .to_json()
+        .unwrap_or(Value::Null);
+
+    serde_json::json!({
+        "method": method,
+        "url": url,
+        "upstream": upstream,
+        "headers": headers,
+    })
+}
+
 impl Node for Call {
     fn run(&self, ctx: &dyn HttpContext, input: &Input) -> State {
-        let body = input.data.first().unwrap_or(&None);
-        let headers = input.data.get(1).unwrap_or(&None);
-        let query = input.data.get(2).unwrap_or(&None);
+        let body = input.get(0);
+        let headers = input.get(1);
+        let query = input.get(2);
+        let trailers = input.get(3);
 
         let call_url = Url::parse(self.config.url.as_str()).unwrap();
 
         let Some(host) = call_url.host_str() else {
-            return fail("call: failed getting host from URL".into());
+            return fail(
+                ErrorKind::ConfigError,
+                "call: failed getting host from URL".into(),
+            );
         };
 
-        let body_slice = match payload::to_pwm_body(*body) {
+        let body_slice = match payload::to_pwm_body(body) {
             Ok(slice) => slice,
-            Err(e) => return fail(e),
+            Err(e) => return fail(ErrorKind::ParseError, e),
         };
 
-        let trailers = vec![];
-        let timeout = Duration::from_secs(self.config.timeout.into());
+        let trailers = payload::to_pwm_headers(trailers);
+        let timeout = self.config.timeout;
 
         let host_port = match call_url.port() {
             Some(port) => format!("{host}:{port}"),
@@ -75,14 +100,25 @@ impl Node for Call {
 
         let path = path_with_query(&call_url, query);
 
-        let mut headers_vec = payload::to_pwm_headers(*headers);
+        let mut headers_vec = payload::to_pwm_headers(headers);
         headers_vec.push((":method", self.config.method.as_str()));
         headers_vec.push((":path", &path));
         headers_vec.push((":scheme", call_url.scheme()));
         headers_vec.push((":authority", &host_port));
 
+        // a configured cluster takes precedence over the URL's host:port
+        // as the upstream; the URL still provides the request's authority
+        let upstream = self.config.cluster.as_deref().unwrap_or(&host_port);
+
+        *self.dispatched.borrow_mut() = Some(dispatched_request(
+            &self.config.method,
+            &format!("{}://{host_port}{path}", call_url.scheme()),
+            upstream,
+            &headers_vec,
+        ));
+
         let result = ctx.dispatch_http_call(
-            &host_port,
+            upstream,
             headers_vec,
             body_slice.as_deref(),
             trailers,
@@ -96,7 +132,10 @@ impl Node for Call {
             }
             Err(status) => {
                 log::debug!("call: dispatch call failed: {:?}", status);
-                fail(format!("call error: {:?}", status))
+                fail(
+                    ErrorKind::UpstreamError,
+                    format!("call error: {:?}", status),
+                )
             }
         }
     }
@@ -113,7 +152,22 @@ impl Node for Call {
             }
         }
 
+        // The host delivers the complete response at once;
+        // see docs/streaming-calls.md for forwarding large bodies.
         let body = if let Some(body) = ctx.get_http_call_response_body(0, usize::MAX) {
+            let content_length = headers.get_str("content-length");
+            if let Some(expected) = content_length.and_then(|cl| cl.parse::<usize>().ok()) {
+                if body.len() < expected {
+                    return fail(
+                        ErrorKind::UpstreamError,
+                        format!(
+                            "call: response body truncated: got {} of {expected} bytes",
+                            body.len()
+                        ),
+                    );
+                }
+            }
+
             let content_type = ctx.get_http_call_response_header("Content-Type");
 
             Payload::from_bytes(body, content_type.as_deref())
@@ -125,6 +179,39 @@ impl Node for Call {
 
         Done(vec![body, Some(headers), None])
     }
+
+    fn debug_request(&self) -> Option<Value> {
+        self.dispatched.borrow().clone()
+    }
+}
+
+/// Parse a timeout given either as a number of seconds
+/// or as a string with a `ms` or `s` suffix, such as `"250ms"` or `"1.5s"`.
+pub fn parse_timeout(node_type: &str, value: &Value) -> Result<Duration, String> {
+    let err = || {
+        format!(
+            "{node_type}: invalid 'timeout' value {value}, \
+             expected seconds or a string such as \"250ms\" or \"1.5s\""
+        )
+    };
+
+    let secs = match value {
+        Value::Number(n) => n.as_u64().ok_or_else(err)? as f64,
+        Value::String(s) => {
+            let s = s.trim();
+            let (num, scale) = if let Some(ms) = s.strip_suffix("ms") {
+                (ms, 1000.0)
+            } else if let Some(secs) = s.strip_suffix('s') {
+                (secs, 1.0)
+            } else {
+                (s, 1.0)
+            };
+            num.trim().parse::<f64>().map_err(|_| err())? / scale
+        }
+        _ => return Err(err()),
+    };
+
+    Duration::try_from_secs_f64(secs).map_err(|_| err())
 }
 
 pub struct CallFactory {}
@@ -132,7 +219,7 @@ pub struct CallFactory {}
 impl NodeFactory for CallFactory {
     fn default_input_ports(&self) -> PortConfig {
         PortConfig {
-            defaults: Some(PortConfig::names(&["body", "headers", "query"])),
+            defaults: Some(PortConfig::names(&["body", "headers", "query", "trailers"])),
             user_defined_ports: false,
         }
     }
@@ -154,6 +241,8 @@ impl NodeFactory for CallFactory {
             return Err("call: 'url' is a required attribute".into());
         };
 
+        check_resolved("call", "url", &url)?;
+
         if Url::parse(&url).is_err() {
             return Err("call: 'url' is not a valid URL".into());
         }
@@ -161,14 +250,213 @@ impl NodeFactory for CallFactory {
         Ok(Box::new(CallConfig {
             url,
             method: get_config_value(bt, "method").unwrap_or_else(|| String::from("GET")),
-            timeout: get_config_value(bt, "timeout").unwrap_or(60),
+            timeout: match bt.get("timeout") {
+                Some(value) => parse_timeout("call", value)?,
+                None => Duration::from_secs(60),
+            },
+            cluster: get_config_value(bt, "cluster"),
         }))
     }
 
-    fn new_node(&self, config: &dyn NodeConfig) -> Box<dyn Node> {
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
         match config.as_any().downcast_ref::<CallConfig>() {
-            Some(cc) => Box::new(Call { config: cc.clone() }),
-            None => panic!("incompatible NodeConfig"),
+            Some(cc) => Ok(Box::new(Call {
+                config: cc.clone(),
+                dispatched: RefCell::new(None),
+            })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{InputPort, Phase};
+    use mock_proxy_wasm::*;
+    use proxy_wasm::types::Bytes;
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Mock {
+        upstream: RefCell<String>,
+        headers: RefCell<Vec<(String, String)>>,
+        trailers: RefCell<Vec<(String, String)>>,
+    }
+
+    fn to_owned_pairs(pairs: Vec<(&str, &str)>) -> Vec<(String, String)> {
+        pairs
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[mock_proxy_wasm_context]
+    impl Context for Mock {
+        fn dispatch_http_call(
+            &self,
+            upstream: &str,
+            headers: Vec<(&str, &str)>,
+            _body: Option<&[u8]>,
+            trailers: Vec<(&str, &str)>,
+            _timeout: Duration,
+        ) -> Result<u32, proxy_wasm::types::Status> {
+            *self.upstream.borrow_mut() = upstream.to_string();
+            *self.headers.borrow_mut() = to_owned_pairs(headers);
+            *self.trailers.borrow_mut() = to_owned_pairs(trailers);
+            Ok(1)
+        }
+    }
+
+    #[mock_proxy_wasm_http_context]
+    impl HttpContext for Mock {}
+
+    fn new_call() -> Call {
+        Call {
+            config: CallConfig {
+                url: "http://example.com/svc".into(),
+                method: "POST".into(),
+                timeout: Duration::from_secs(1),
+                cluster: None,
+            },
+            dispatched: RefCell::new(None),
+        }
+    }
+
+    fn dispatch_trailers(trailers: Option<&Payload>) -> Vec<(String, String)> {
+        let call = new_call();
+        let ctx = Mock::default();
+        let input = Input {
+            data: &[
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+                trailers.map_or(InputPort::Unconnected, InputPort::Value),
+            ],
+            phase: Phase::HttpRequestHeaders,
+        };
+        assert!(matches!(call.run(&ctx, &input), Waiting(1)));
+        ctx.trailers.take()
+    }
+
+    #[test]
+    fn call_trailers() {
+        assert!(dispatch_trailers(None).is_empty());
+        assert!(dispatch_trailers(Some(&Payload::Json(json!({})))).is_empty());
+
+        let trailers = Payload::Json(json!({
+            "grpc-status": "0",
+            "x-checksum": ["a", "b"],
+        }));
+        assert_eq!(
+            vec![
+                ("grpc-status".to_string(), "0".to_string()),
+                ("x-checksum".to_string(), "a".to_string()),
+                ("x-checksum".to_string(), "b".to_string()),
+            ],
+            dispatch_trailers(Some(&trailers))
+        );
+    }
+
+    #[test]
+    fn call_cluster_upstream() {
+        let mut call = new_call();
+        call.config.url = "https://api.example.com:8443/users?active=1".into();
+        call.config.cluster = Some("mesh-users".into());
+
+        let ctx = Mock::default();
+        let input = Input {
+            data: &[InputPort::Unconnected; 4],
+            phase: Phase::HttpRequestHeaders,
+        };
+        assert!(matches!(call.run(&ctx, &input), Waiting(1)));
+
+        // the cluster is the upstream, the URL still gives the request
+        assert_eq!("mesh-users", *ctx.upstream.borrow());
+        let headers = ctx.headers.take();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(Some("api.example.com:8443"), header(":authority"));
+        assert_eq!(Some("/users?active=1"), header(":path"));
+        assert_eq!(Some("https"), header(":scheme"));
+    }
+
+    #[test]
+    fn call_debug_request() {
+        let call = new_call();
+        assert_eq!(None, call.debug_request());
+
+        let headers = Payload::Json(json!({ "x-user": "ann" }));
+        let query = Payload::Json(json!({ "q": "1" }));
+        let input = Input {
+            data: &[
+                InputPort::Unconnected,
+                InputPort::Value(&headers),
+                InputPort::Value(&query),
+                InputPort::Unconnected,
+            ],
+            phase: Phase::HttpRequestHeaders,
+        };
+        assert!(matches!(call.run(&Mock::default(), &input), Waiting(1)));
+
+        assert_eq!(
+            Some(json!({
+                "method": "POST",
+                "url": "http://example.com/svc?q=1",
+                "upstream": "example.com",
+                "headers": {
+                    "x-user": "ann",
+                    ":method": "POST",
+                    ":path": "/svc?q=1",
+                    ":scheme": "http",
+                    ":authority": "example.com",
+                },
+            })),
+            call.debug_request()
+        );
+    }
+
+    #[test]
+    fn call_unresolved_reference() {
+        let bt = serde_json::from_value(json!({ "url": "{vault://env/upstream-url}" })).unwrap();
+        let err = CallFactory {}.new_config("c", &[], &[], &bt).err();
+        assert_eq!(
+            err.as_deref(),
+            Some("call: 'url' holds the unresolved vault reference {vault://env/upstream-url}")
+        );
+    }
+
+    #[test]
+    fn parse_timeout_values() {
+        let cases = vec![
+            (json!(0), Duration::ZERO),
+            (json!(60), Duration::from_secs(60)),
+            (json!("250ms"), Duration::from_millis(250)),
+            (json!("1.5s"), Duration::from_millis(1500)),
+            (json!(" 2 s "), Duration::from_secs(2)),
+            (json!("3"), Duration::from_secs(3)),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_timeout("call", &value), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn parse_timeout_invalid() {
+        for value in [
+            json!(-1),
+            json!(1.5),
+            json!("soon"),
+            json!("-5ms"),
+            json!(true),
+        ] {
+            let err = parse_timeout("call", &value).unwrap_err();
+            assert!(err.starts_with("call: invalid 'timeout' value"), "{err}");
         }
     }
 }
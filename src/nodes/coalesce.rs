@@ -0,0 +1,139 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::Payload;
+
+#[derive(Clone, Debug)]
+pub struct CoalesceConfig {
+    null_is_empty: bool,
+}
+
+impl NodeConfig for CoalesceConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn optional_inputs(&self) -> bool {
+        true
+    }
+}
+
+pub struct Coalesce {
+    config: CoalesceConfig,
+}
+
+impl Coalesce {
+    fn first_of<'a>(&self, inputs: &[Option<&'a Payload>]) -> Option<&'a Payload> {
+        inputs
+            .iter()
+            .flatten()
+            .copied()
+            .find(|payload| match payload {
+                Payload::Json(Value::Null) => !self.config.null_is_empty,
+                Payload::Error(_, _) => false,
+                _ => true,
+            })
+    }
+}
+
+impl Node for Coalesce {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        Done(vec![self.first_of(&input.payloads()).cloned()])
+    }
+}
+
+pub struct CoalesceFactory {}
+
+impl NodeFactory for CoalesceFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: None,
+            user_defined_ports: true,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        Ok(Box::new(CoalesceConfig {
+            null_is_empty: get_config_value(bt, "null_is_empty").unwrap_or(true),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<CoalesceConfig>() {
+            Some(cc) => Ok(Box::new(Coalesce { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::payload::ErrorKind;
+    use serde_json::json;
+
+    fn first_of(null_is_empty: bool, inputs: &[Option<Payload>]) -> Option<Payload> {
+        let node = Coalesce {
+            config: CoalesceConfig { null_is_empty },
+        };
+        let inputs: Vec<Option<&Payload>> = inputs.iter().map(Option::as_ref).collect();
+        node.first_of(&inputs).cloned()
+    }
+
+    #[test]
+    fn coalesce_first_present() {
+        let cached = Payload::Json(json!({ "hit": true }));
+        let upstream = Payload::Raw(b"fresh".to_vec());
+
+        assert_eq!(
+            Some(cached.clone()),
+            first_of(true, &[Some(cached.clone()), Some(upstream.clone())])
+        );
+        assert_eq!(
+            Some(upstream.clone()),
+            first_of(true, &[None, Some(upstream.clone())])
+        );
+        assert_eq!(None, first_of(true, &[None, None]));
+        assert_eq!(None, first_of(true, &[]));
+    }
+
+    #[test]
+    fn coalesce_null() {
+        let inputs = [
+            Some(Payload::Json(Value::Null)),
+            Some(Payload::Json(json!(0))),
+        ];
+        assert_eq!(Some(Payload::Json(json!(0))), first_of(true, &inputs));
+        assert_eq!(Some(Payload::Json(Value::Null)), first_of(false, &inputs));
+    }
+
+    #[test]
+    fn coalesce_skips_errors() {
+        let inputs = [
+            Some(Payload::Error(ErrorKind::ParseError, "boom".into())),
+            Some(Payload::Json(json!("default"))),
+        ];
+        assert_eq!(
+            Some(Payload::Json(json!("default"))),
+            first_of(true, &inputs)
+        );
+    }
+}
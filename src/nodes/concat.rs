@@ -0,0 +1,182 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Debug)]
+pub struct ConcatConfig {
+    separator: String,
+}
+
+impl NodeConfig for ConcatConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Concat {
+    config: ConcatConfig,
+}
+
+impl Concat {
+    fn concat(&self, payloads: &[&Payload]) -> Result<Payload, String> {
+        if payloads
+            .iter()
+            .all(|p| matches!(p, Payload::Json(Value::Array(_))))
+        {
+            let mut out = vec![];
+            for payload in payloads {
+                if let Payload::Json(Value::Array(vs)) = payload {
+                    out.extend(vs.iter().cloned());
+                }
+            }
+            return Ok(Payload::Json(Value::Array(out)));
+        }
+
+        let mut out = vec![];
+        for (i, payload) in payloads.iter().enumerate() {
+            if i > 0 {
+                out.extend_from_slice(self.config.separator.as_bytes());
+            }
+            out.extend(payload.to_bytes(None)?);
+        }
+        Ok(Payload::Raw(out))
+    }
+}
+
+impl Node for Concat {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        let payloads: Vec<&Payload> = input.data.iter().filter_map(|p| p.payload()).collect();
+
+        if payloads.is_empty() {
+            return Done(vec![None]);
+        }
+
+        match self.concat(&payloads) {
+            Ok(payload) => Done(vec![Some(payload)]),
+            Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+        }
+    }
+}
+
+pub struct ConcatFactory {}
+
+impl NodeFactory for ConcatFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: None,
+            user_defined_ports: true,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        Ok(Box::new(ConcatConfig {
+            separator: get_config_value(bt, "separator").unwrap_or_default(),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<ConcatConfig>() {
+            Some(cc) => Ok(Box::new(Concat { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn concat(separator: &str, payloads: &[Payload]) -> Result<Payload, String> {
+        let node = Concat {
+            config: ConcatConfig {
+                separator: separator.into(),
+            },
+        };
+        node.concat(&payloads.iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn concat_arrays() {
+        assert_eq!(
+            Ok(Payload::Json(json!([1, 2, "a", { "b": 3 }]))),
+            concat(
+                ", ",
+                &[
+                    Payload::Json(json!([1, 2])),
+                    Payload::Json(json!([])),
+                    Payload::Json(json!(["a", { "b": 3 }]))
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn concat_strings() {
+        assert_eq!(
+            Ok(Payload::Raw(b"<h1>hi</h1>\n<p>hello</p>".to_vec())),
+            concat(
+                "\n",
+                &[
+                    Payload::Raw(b"<h1>hi</h1>".to_vec()),
+                    Payload::Json(json!("<p>hello</p>"))
+                ]
+            )
+        );
+        assert_eq!(
+            Ok(Payload::Raw(b"ab".to_vec())),
+            concat(
+                "",
+                &[Payload::Raw(b"a".to_vec()), Payload::Raw(b"b".to_vec())]
+            )
+        );
+    }
+
+    #[test]
+    fn concat_mixed() {
+        assert_eq!(
+            Ok(Payload::Raw(br#"[1]-{"a":true}-x"#.to_vec())),
+            concat(
+                "-",
+                &[
+                    Payload::Json(json!([1])),
+                    Payload::Json(json!({ "a": true })),
+                    Payload::Raw(b"x".to_vec())
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn concat_error() {
+        assert_eq!(
+            Err("boom".to_string()),
+            concat(
+                "",
+                &[
+                    Payload::Raw(b"a".to_vec()),
+                    Payload::Error(ErrorKind::ParseError, "boom".into())
+                ]
+            )
+        );
+    }
+}
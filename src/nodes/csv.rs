@@ -0,0 +1,150 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{self, ErrorKind, Payload};
+
+#[derive(Clone, Debug)]
+pub struct CsvConfig {
+    delimiter: char,
+    header: bool,
+}
+
+impl NodeConfig for CsvConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Csv {
+    config: CsvConfig,
+}
+
+impl Csv {
+    /// Parse strings into records, and serialize arrays of records into strings.
+    fn convert(&self, input: &Payload) -> Result<Payload, String> {
+        let CsvConfig { delimiter, header } = self.config;
+
+        match input {
+            Payload::Raw(bytes) => {
+                payload::csv_bytes_to_json(bytes, delimiter, header).map(Payload::Json)
+            }
+            Payload::Json(Value::String(s)) => {
+                payload::csv_bytes_to_json(s.as_bytes(), delimiter, header).map(Payload::Json)
+            }
+            Payload::Json(value @ Value::Array(_)) => {
+                payload::json_to_csv_bytes(value, delimiter, header).map(Payload::Raw)
+            }
+            Payload::Json(_) => Err("csv: expected a string or an array of records".into()),
+            Payload::Error(_, e) => Err(e.clone()),
+        }
+    }
+}
+
+impl Node for Csv {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        match input.get(0) {
+            Some(payload) => match self.convert(payload) {
+                Ok(payload) => Done(vec![Some(payload)]),
+                Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+            },
+            None => Done(vec![None]),
+        }
+    }
+}
+
+pub struct CsvFactory {}
+
+impl NodeFactory for CsvFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let delimiter = match get_config_value::<String>(bt, "delimiter") {
+            None => ',',
+            Some(s) => {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c != '"' && c != '\r' && c != '\n' => c,
+                    _ => return Err("csv: 'delimiter' must be a single character".into()),
+                }
+            }
+        };
+
+        Ok(Box::new(CsvConfig {
+            delimiter,
+            header: get_config_value(bt, "header").unwrap_or(true),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<CsvConfig>() {
+            Some(cc) => Ok(Box::new(Csv { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn convert(delimiter: char, header: bool, payload: Payload) -> Result<Payload, String> {
+        let node = Csv {
+            config: CsvConfig { delimiter, header },
+        };
+        node.convert(&payload)
+    }
+
+    #[test]
+    fn csv_parse() {
+        assert_eq!(
+            Ok(Payload::Json(json!([{ "a": "1", "b": "x;y" }]))),
+            convert(';', true, Payload::Raw(b"a;b\n1;\"x;y\"\n".to_vec()))
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!([["a", "b"], ["1", "2"]]))),
+            convert('\t', false, Payload::Json(json!("a\tb\n1\t2")))
+        );
+    }
+
+    #[test]
+    fn csv_serialize() {
+        assert_eq!(
+            Ok(Payload::Raw(b"1;2\r\n".to_vec())),
+            convert(';', false, Payload::Json(json!([{ "a": 1, "b": 2 }])))
+        );
+        assert_eq!(
+            Ok(Payload::Raw(b"a|b\r\n1|\r\n".to_vec())),
+            convert('|', true, Payload::Json(json!([{ "a": 1, "b": null }])))
+        );
+    }
+
+    #[test]
+    fn csv_invalid() {
+        assert!(convert(',', true, Payload::Json(json!({ "a": 1 }))).is_err());
+        assert!(convert(',', true, Payload::Json(json!([1, 2]))).is_err());
+    }
+}
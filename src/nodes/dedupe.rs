@@ -0,0 +1,169 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::Payload;
+
+#[derive(Clone, Debug)]
+pub struct DedupeConfig {
+    by: Option<Vec<String>>,
+}
+
+impl NodeConfig for DedupeConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Dedupe {
+    config: DedupeConfig,
+}
+
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |v, key| v.get(key))
+}
+
+impl Dedupe {
+    fn dedupe(&self, value: &Value) -> Value {
+        // serde_json maps are sorted, so the serialized form
+        // is a stable key for comparing values
+        let mut seen = HashSet::new();
+
+        match value {
+            Value::Array(vs) => Value::Array(
+                vs.iter()
+                    .filter(|v| {
+                        let key = match &self.config.by {
+                            Some(path) => match lookup(v, path) {
+                                Some(k) => k,
+                                // entries without the key are kept as-is
+                                None => return true,
+                            },
+                            None => v,
+                        };
+                        seen.insert(key.to_string())
+                    })
+                    .cloned()
+                    .collect(),
+            ),
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .filter(|(_, v)| seen.insert(v.to_string()))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            ),
+            _ => value.clone(),
+        }
+    }
+}
+
+impl Node for Dedupe {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        match input.get(0) {
+            Some(Payload::Json(value)) => Done(vec![Some(Payload::Json(self.dedupe(value)))]),
+            Some(p @ Payload::Error(..)) => Fail(vec![Some((*p).clone())]),
+            Some(payload) => Done(vec![Some((*payload).clone())]),
+            None => Done(vec![None]),
+        }
+    }
+}
+
+pub struct DedupeFactory {}
+
+impl NodeFactory for DedupeFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        Ok(Box::new(DedupeConfig {
+            by: get_config_value::<String>(bt, "by")
+                .map(|by| by.split('.').map(str::to_owned).collect()),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<DedupeConfig>() {
+            Some(cc) => Ok(Box::new(Dedupe { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn dedupe(by: Option<&str>, value: Value) -> Value {
+        let node = Dedupe {
+            config: DedupeConfig {
+                by: by.map(|b| b.split('.').map(str::to_owned).collect()),
+            },
+        };
+        node.dedupe(&value)
+    }
+
+    #[test]
+    fn dedupe_array() {
+        assert_eq!(
+            json!([1, "a", { "x": 1 }, 2]),
+            dedupe(None, json!([1, "a", 1, { "x": 1 }, 2, "a", { "x": 1 }]))
+        );
+    }
+
+    #[test]
+    fn dedupe_array_by_key() {
+        assert_eq!(
+            json!([
+                { "user": { "id": 1 }, "v": "first" },
+                { "user": { "id": 2 }, "v": "second" },
+                { "other": true },
+                { "other": true },
+            ]),
+            dedupe(
+                Some("user.id"),
+                json!([
+                    { "user": { "id": 1 }, "v": "first" },
+                    { "user": { "id": 2 }, "v": "second" },
+                    { "user": { "id": 1 }, "v": "third" },
+                    { "other": true },
+                    { "other": true },
+                ])
+            )
+        );
+    }
+
+    #[test]
+    fn dedupe_object() {
+        assert_eq!(
+            json!({ "a": 1, "b": 2 }),
+            dedupe(None, json!({ "a": 1, "b": 2, "c": 1 }))
+        );
+    }
+
+    #[test]
+    fn dedupe_passthrough() {
+        assert_eq!(json!("a a a"), dedupe(None, json!("a a a")));
+    }
+}
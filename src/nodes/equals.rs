@@ -0,0 +1,182 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Debug)]
+pub struct EqualsConfig {
+    strict: bool,
+    constant_time: bool,
+}
+
+impl NodeConfig for EqualsConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Equals {
+    config: EqualsConfig,
+}
+
+/// Compare byte strings in a time that depends only on their lengths,
+/// and not on the position of the first difference.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+impl Equals {
+    fn compare(&self, a: &Payload, b: &Payload) -> Result<bool, String> {
+        let EqualsConfig {
+            strict,
+            constant_time,
+        } = self.config;
+
+        match (a, b) {
+            (Payload::Error(_, e), _) | (_, Payload::Error(_, e)) => return Err(e.clone()),
+            (Payload::Json(_), Payload::Raw(_)) | (Payload::Raw(_), Payload::Json(_)) if strict => {
+                return Ok(false)
+            }
+            (Payload::Json(x), Payload::Json(y)) if !constant_time => return Ok(x == y),
+            _ => {}
+        }
+
+        let x = a.to_bytes(None)?;
+        let y = b.to_bytes(None)?;
+        Ok(if constant_time {
+            constant_time_eq(&x, &y)
+        } else {
+            x == y
+        })
+    }
+}
+
+impl Node for Equals {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        let a = input.get(0);
+        let b = input.get(1);
+
+        let result = match (a, b) {
+            (Some(a), Some(b)) => self.compare(a, b),
+            // an input without a value is not equal to anything
+            _ => Ok(false),
+        };
+
+        match result {
+            Ok(equal) => Done(vec![Some(Payload::Json(equal.into()))]),
+            Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+        }
+    }
+}
+
+pub struct EqualsFactory {}
+
+impl NodeFactory for EqualsFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["a", "b"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        Ok(Box::new(EqualsConfig {
+            strict: get_config_value(bt, "strict").unwrap_or(false),
+            constant_time: get_config_value(bt, "constant_time").unwrap_or(false),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<EqualsConfig>() {
+            Some(ec) => Ok(Box::new(Equals { config: ec.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn compare(strict: bool, constant_time: bool, a: Payload, b: Payload) -> Result<bool, String> {
+        let node = Equals {
+            config: EqualsConfig {
+                strict,
+                constant_time,
+            },
+        };
+        node.compare(&a, &b)
+    }
+
+    #[test]
+    fn equals_json() {
+        let a = || Payload::Json(json!({ "a": [1, 2], "b": "x" }));
+        let b = || Payload::Json(json!({ "b": "x", "a": [1, 2] }));
+        for (strict, constant_time) in [(false, false), (true, false), (false, true)] {
+            assert_eq!(Ok(true), compare(strict, constant_time, a(), b()));
+            assert_eq!(
+                Ok(false),
+                compare(strict, constant_time, a(), Payload::Json(json!({})))
+            );
+        }
+    }
+
+    #[test]
+    fn equals_json_and_raw() {
+        let one = || Payload::Json(json!(1));
+        let raw = || Payload::Raw(b"1".to_vec());
+        assert_eq!(Ok(true), compare(false, false, one(), raw()));
+        assert_eq!(Ok(true), compare(false, true, raw(), one()));
+        assert_eq!(Ok(false), compare(true, false, one(), raw()));
+        assert_eq!(Ok(false), compare(true, true, one(), raw()));
+
+        let string = || Payload::Json(json!("abc"));
+        let raw = || Payload::Raw(b"abc".to_vec());
+        assert_eq!(Ok(true), compare(false, false, string(), raw()));
+        assert_eq!(Ok(false), compare(true, false, string(), raw()));
+    }
+
+    #[test]
+    fn equals_constant_time() {
+        assert!(constant_time_eq(b"signature", b"signature"));
+        assert!(!constant_time_eq(b"signature", b"signaturf"));
+        assert!(!constant_time_eq(b"signature", b"sig"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn equals_error() {
+        assert_eq!(
+            Err("boom".to_string()),
+            compare(
+                true,
+                false,
+                Payload::Json(json!(1)),
+                Payload::Error(ErrorKind::ParseError, "boom".into())
+            )
+        );
+    }
+}
@@ -6,15 +6,20 @@ use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::Relaxed;
 
 use crate::config::get_config_value;
-use crate::data::{Input, Phase, State, State::*};
-use crate::nodes::{Node, NodeConfig, NodeDefaultLink, NodeFactory, PortConfig};
+use crate::data::{Input, Phase, Response, State, State::*};
+use crate::nodes::{
+    replace_response_body, Node, NodeConfig, NodeDefaultLink, NodeFactory, PortConfig,
+};
 use crate::payload;
-use crate::payload::Payload;
+use crate::payload::{Compress, ErrorKind, Payload};
 
 #[derive(Debug)]
 pub struct ExitConfig {
     name: String,
     status: Option<u32>,
+    short_circuit: bool,
+    negotiate: bool,
+    compress: Compress,
     warn_headers_sent: AtomicBool,
 }
 
@@ -23,6 +28,9 @@ impl Clone for ExitConfig {
         ExitConfig {
             name: self.name.clone(),
             status: self.status,
+            short_circuit: self.short_circuit,
+            negotiate: self.negotiate,
+            compress: self.compress,
             warn_headers_sent: AtomicBool::new(self.warn_headers_sent.load(Relaxed)),
         }
     }
@@ -43,6 +51,8 @@ pub struct Exit {
     config: ExitConfig,
 }
 
+/// Trailers are not part of this warning: unlike the status and headers,
+/// they are sent after the body and can still be set at this point.
 fn warn_headers_sent(config: &ExitConfig, set_headers: bool) {
     let name = &config.name;
     let set_status = config.status.is_some();
@@ -64,39 +74,208 @@ fn warn_headers_sent(config: &ExitConfig, set_headers: bool) {
     config.warn_headers_sent.store(false, Relaxed);
 }
 
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Format a challenge such as `{ "scheme": "Bearer", "params": { "realm": "api" } }`
+/// into a `WWW-Authenticate` header value.
+fn format_challenge(challenge: &Value) -> Result<String, String> {
+    let scheme = match challenge.get("scheme").and_then(Value::as_str) {
+        Some(scheme) if is_token(scheme) => scheme,
+        _ => return Err("a challenge needs a `scheme`".into()),
+    };
+    let mut value = scheme.to_string();
+
+    let params: Vec<(&String, &Value)> = match challenge.get("params") {
+        Some(Value::Object(map)) => map.iter().collect(),
+        Some(_) => return Err("`params` must be an object".into()),
+        None => vec![],
+    };
+
+    if let Some(token68) = challenge.get("token68") {
+        match token68.as_str() {
+            Some(token68) if params.is_empty() => {
+                value.push(' ');
+                value.push_str(token68);
+                return Ok(value);
+            }
+            Some(_) => return Err("`token68` cannot be combined with `params`".into()),
+            None => return Err("`token68` must be a string".into()),
+        }
+    }
+
+    // the realm comes first, by convention
+    let (realm, others): (Vec<_>, Vec<_>) = params.into_iter().partition(|(k, _)| *k == "realm");
+    for (i, (name, param)) in realm.into_iter().chain(others).enumerate() {
+        if !is_token(name) {
+            return Err(format!("invalid parameter name `{name}`"));
+        }
+        let param = match param {
+            Value::String(s) => quote(s),
+            Value::Number(_) | Value::Bool(_) => quote(&param.to_string()),
+            _ => return Err(format!("parameter `{name}` must be a string")),
+        };
+        value.push_str(if i == 0 { " " } else { ", " });
+        value.push_str(&format!("{name}={param}"));
+    }
+
+    Ok(value)
+}
+
+/// The `WWW-Authenticate` header values for a challenge, an array of
+/// challenges, or a string which is used as is.
+fn www_authenticate(payload: &Payload) -> Result<Vec<String>, String> {
+    match payload.to_json()? {
+        Value::String(s) => Ok(vec![s]),
+        Value::Array(challenges) => challenges.iter().map(format_challenge).collect(),
+        challenge => Ok(vec![format_challenge(&challenge)?]),
+    }
+}
+
+/// Apply the status and headers to the response from the upstream, leaving
+/// its body untouched. Headers given several times are all kept.
+fn override_response_headers(ctx: &dyn HttpContext, status: Option<u32>, headers: &[(&str, &str)]) {
+    if let Some(status) = status {
+        ctx.set_http_response_header(":status", Some(&status.to_string()));
+    }
+
+    let mut seen: Vec<&str> = vec![];
+    for (name, value) in headers {
+        if seen.iter().any(|s| s.eq_ignore_ascii_case(name)) {
+            ctx.add_http_response_header(name, value);
+        } else {
+            ctx.set_http_response_header(name, Some(value));
+            seen.push(name);
+        }
+    }
+}
+
 impl Node for Exit {
     fn run(&self, ctx: &dyn HttpContext, input: &Input) -> State {
         let config = &self.config;
-        let body = input.data.first().unwrap_or(&None).as_deref();
-        let headers = input.data.get(1).unwrap_or(&None).as_deref();
+        let body = input.get(0);
+        let headers = input.get(1);
+        let trailers = input.get(2);
+        let challenge = input.get(3);
+
+        let challenges = match challenge.map(www_authenticate).transpose() {
+            Ok(values) => values.unwrap_or_default(),
+            Err(e) => {
+                return Fail(vec![Some(Payload::Error(
+                    ErrorKind::ParseError,
+                    format!("exit: invalid challenge: {e}"),
+                ))])
+            }
+        };
 
         let mut headers_vec = payload::to_pwm_headers(headers);
+        for value in &challenges {
+            headers_vec.push(("WWW-Authenticate", value));
+        }
 
-        if let Some(payload) = body {
-            if let Some(content_type) = payload.content_type() {
-                headers_vec.push(("Content-Type", content_type));
-            }
+        let negotiated = match body {
+            Some(payload) if config.negotiate => ctx
+                .get_http_request_header("Accept")
+                .and_then(|accept| payload.negotiate_content_type(&accept)),
+            _ => None,
+        };
+
+        if let Some(content_type) = negotiated.or_else(|| body.and_then(Payload::content_type)) {
+            headers_vec.push(("Content-Type", content_type));
         }
 
-        let body_slice = match payload::to_pwm_body(body) {
+        let body_slice = match negotiated {
+            Some(content_type) => body
+                .map(|payload| payload.to_bytes(Some(content_type)))
+                .transpose()
+                .map(|bytes| bytes.map(Vec::into_boxed_slice)),
+            None => payload::to_pwm_body(body),
+        };
+
+        let body_slice = match body_slice {
             Ok(slice) => slice,
-            Err(e) => return Fail(vec![Some(Payload::Error(e))]),
+            Err(e) => return Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
         };
 
         if input.phase == Phase::HttpResponseBody {
             if config.warn_headers_sent.load(Relaxed) {
-                warn_headers_sent(config, headers.is_some());
+                warn_headers_sent(config, headers.is_some() || challenge.is_some());
             }
 
             if let Some(b) = body_slice {
-                ctx.set_http_response_body(0, b.len(), &b);
+                // replace all of the upstream body, whatever its length
+                let body_size = ctx
+                    .get_http_response_body(0, usize::MAX)
+                    .map_or(0, |u| u.len());
+                replace_response_body(ctx, body_size, &b);
+            }
+
+            if trailers.is_some() {
+                ctx.set_http_response_trailers(payload::to_pwm_headers(trailers));
             }
         } else {
+            if trailers.is_some() {
+                log::warn!(
+                    "exit: node '{}' cannot set trailers on a response \
+                     produced before the response body phase",
+                    config.name
+                );
+            }
+
+            // without a `body` link, an exit while processing the response
+            // headers only adjusts the response from the upstream
+            if input.phase == Phase::HttpResponseHeaders && !input.is_connected(0) {
+                override_response_headers(ctx, config.status, &headers_vec);
+                return Done(vec![None, None]);
+            }
+
             let status = config.status.unwrap_or(200);
+
+            // the body can only be compressed along with the headers
+            let accept_encoding = match config.compress {
+                Compress::Auto => ctx.get_http_request_header("Accept-Encoding"),
+                _ => None,
+            };
+            let body_slice = match body_slice {
+                Some(b) if config.compress.applies(accept_encoding.as_deref()) => {
+                    match payload::gzip(&b) {
+                        Ok(compressed) => {
+                            headers_vec.push(("Content-Encoding", payload::GZIP_ENCODING));
+                            if config.compress == Compress::Auto {
+                                headers_vec.push(("Vary", "Accept-Encoding"));
+                            }
+                            Some(compressed.into_boxed_slice())
+                        }
+                        Err(e) => {
+                            return Fail(vec![Some(Payload::Error(ErrorKind::InternalError, e))])
+                        }
+                    }
+                }
+                body_slice => body_slice,
+            };
+
+            if config.short_circuit {
+                return ShortCircuit(Response {
+                    status,
+                    headers: headers_vec
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                    body: body_slice.map(Vec::from),
+                });
+            }
+
             ctx.send_http_response(status, headers_vec, body_slice.as_deref());
         }
 
-        Done(vec![None])
+        Done(vec![None, None])
     }
 }
 
@@ -105,7 +284,12 @@ pub struct ExitFactory {}
 impl NodeFactory for ExitFactory {
     fn default_input_ports(&self) -> PortConfig {
         PortConfig {
-            defaults: Some(PortConfig::names(&["body", "headers"])),
+            defaults: Some(PortConfig::names(&[
+                "body",
+                "headers",
+                "trailers",
+                "challenge",
+            ])),
             user_defined_ports: false,
         }
     }
@@ -124,19 +308,336 @@ impl NodeFactory for ExitFactory {
         _outputs: &[String],
         bt: &BTreeMap<String, Value>,
     ) -> Result<Box<dyn NodeConfig>, String> {
+        let compress = match get_config_value::<String>(bt, "compress") {
+            Some(value) => Compress::parse(&value)
+                .ok_or_else(|| format!("exit: invalid 'compress': {value}"))?,
+            None => Compress::default(),
+        };
+
         Ok(Box::new(ExitConfig {
             name: name.to_string(),
             status: get_config_value(bt, "status"),
+            short_circuit: get_config_value(bt, "short_circuit").unwrap_or(false),
+            negotiate: get_config_value(bt, "negotiate").unwrap_or(false),
+            compress,
             warn_headers_sent: AtomicBool::new(
                 get_config_value(bt, "warn_headers_sent").unwrap_or(true),
             ),
         }))
     }
 
-    fn new_node(&self, config: &dyn NodeConfig) -> Box<dyn Node> {
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
         match config.as_any().downcast_ref::<ExitConfig>() {
-            Some(cc) => Box::new(Exit { config: cc.clone() }),
-            None => panic!("incompatible NodeConfig"),
+            Some(cc) => Ok(Box::new(Exit { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::InputPort;
+    use mock_proxy_wasm::*;
+    use proxy_wasm::types::Bytes;
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Mock {
+        sent_status: RefCell<Option<u32>>,
+        accept_encoding: Option<String>,
+        response_headers: RefCell<Vec<(String, String)>>,
+        response_body: RefCell<Option<Vec<u8>>>,
+        upstream_body: Vec<u8>,
+    }
+
+    #[mock_proxy_wasm_context]
+    impl Context for Mock {}
+
+    #[mock_proxy_wasm_http_context]
+    impl HttpContext for Mock {
+        fn send_http_response(
+            &self,
+            status_code: u32,
+            _headers: Vec<(&str, &str)>,
+            _body: Option<&[u8]>,
+        ) {
+            *self.sent_status.borrow_mut() = Some(status_code);
+        }
+
+        fn set_http_response_header(&self, name: &str, value: Option<&str>) {
+            let mut headers = self.response_headers.borrow_mut();
+            headers.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+            if let Some(value) = value {
+                headers.push((name.into(), value.into()));
+            }
+        }
+
+        fn add_http_response_header(&self, name: &str, value: &str) {
+            let mut headers = self.response_headers.borrow_mut();
+            headers.push((name.into(), value.into()));
+        }
+
+        fn get_http_response_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
+            let body = self.upstream_body.iter().skip(start).take(max_size);
+            Some(body.copied().collect())
+        }
+
+        fn set_http_response_body(&self, start: usize, size: usize, value: &[u8]) {
+            // the host only replaces `size` bytes, leaving any others
+            let mut body = self.upstream_body.clone();
+            let end = (start + size).min(body.len());
+            body.splice(start..end, value.iter().copied());
+            *self.response_body.borrow_mut() = Some(body);
         }
+
+        fn get_http_request_header(&self, name: &str) -> Option<String> {
+            match name {
+                "Accept-Encoding" => self.accept_encoding.clone(),
+                _ => None,
+            }
+        }
+    }
+
+    fn exit(short_circuit: bool) -> Exit {
+        Exit {
+            config: ExitConfig {
+                name: "EXIT".into(),
+                status: Some(403),
+                short_circuit,
+                negotiate: false,
+                compress: Compress::Never,
+                warn_headers_sent: AtomicBool::new(true),
+            },
+        }
+    }
+
+    #[test]
+    fn exit_sends_response() {
+        let ctx = Mock::default();
+        let body = Payload::Raw(b"denied".to_vec());
+        let input = Input {
+            data: &[
+                InputPort::Value(&body),
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+            ],
+            phase: Phase::HttpRequestHeaders,
+        };
+
+        assert_eq!(Done(vec![None, None]), exit(false).run(&ctx, &input));
+        assert_eq!(Some(403), *ctx.sent_status.borrow());
+    }
+
+    #[test]
+    fn exit_short_circuit() {
+        let ctx = Mock::default();
+        let body = Payload::Json(json!({ "cached": true }));
+        let input = Input {
+            data: &[
+                InputPort::Value(&body),
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+            ],
+            phase: Phase::HttpRequestHeaders,
+        };
+
+        assert_eq!(
+            ShortCircuit(Response {
+                status: 403,
+                headers: vec![("Content-Type".into(), "application/json".into())],
+                body: Some(br#"{"cached":true}"#.to_vec()),
+            }),
+            exit(true).run(&ctx, &input)
+        );
+        // the response is sent by the filter, not by the node
+        assert_eq!(None, *ctx.sent_status.borrow());
+    }
+
+    #[test]
+    fn exit_preserves_upstream_body() {
+        let upstream = || {
+            let ctx = Mock::default();
+            ctx.set_http_response_header(":status", Some("200"));
+            ctx.set_http_response_header("X-Cache", Some("miss"));
+            ctx
+        };
+        let headers = Payload::Json(json!({ "x-cache": "hit", "x-tag": ["a", "b"] }));
+
+        // no `body` link: only the status and headers change
+        let ctx = upstream();
+        let input = Input {
+            data: &[
+                InputPort::Unconnected,
+                InputPort::Value(&headers),
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+            ],
+            phase: Phase::HttpResponseHeaders,
+        };
+        assert_eq!(Done(vec![None, None]), exit(true).run(&ctx, &input));
+        assert_eq!(None, *ctx.sent_status.borrow());
+        assert_eq!(None, *ctx.response_body.borrow());
+        assert_eq!(
+            vec![
+                (":status".to_string(), "403".to_string()),
+                ("x-cache".to_string(), "hit".to_string()),
+                ("x-tag".to_string(), "a".to_string()),
+                ("x-tag".to_string(), "b".to_string()),
+            ],
+            *ctx.response_headers.borrow()
+        );
+
+        let ctx = upstream();
+        let input = Input {
+            phase: Phase::HttpResponseBody,
+            ..input
+        };
+        exit(false).run(&ctx, &input);
+        assert_eq!(None, *ctx.response_body.borrow());
+
+        // an empty body still replaces the upstream one
+        let ctx = upstream();
+        let empty = Payload::Raw(vec![]);
+        let input = Input {
+            data: &[
+                InputPort::Value(&empty),
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+            ],
+            phase: Phase::HttpResponseBody,
+        };
+        exit(false).run(&ctx, &input);
+        assert_eq!(Some(vec![]), *ctx.response_body.borrow());
+    }
+
+    #[test]
+    fn exit_replaces_longer_upstream_body() {
+        let ctx = Mock {
+            upstream_body: b"a longer upstream body".to_vec(),
+            ..Default::default()
+        };
+        let body = Payload::Raw(b"short".to_vec());
+        let input = Input {
+            data: &[
+                InputPort::Value(&body),
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+            ],
+            phase: Phase::HttpResponseBody,
+        };
+
+        assert_eq!(Done(vec![None, None]), exit(false).run(&ctx, &input));
+        // no stale bytes of the upstream body are left behind
+        assert_eq!(Some(b"short".to_vec()), *ctx.response_body.borrow());
+    }
+
+    #[test]
+    fn exit_compress() {
+        let body = Payload::Json(json!({ "items": [1, 2, 3] }));
+        let input = Input {
+            data: &[
+                InputPort::Value(&body),
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+            ],
+            phase: Phase::HttpRequestHeaders,
+        };
+        let compressing = |compress| {
+            let mut node = exit(true);
+            node.config.compress = compress;
+            node
+        };
+        let run = |node: &Exit, accept_encoding: Option<&str>| {
+            let ctx = Mock {
+                accept_encoding: accept_encoding.map(String::from),
+                ..Mock::default()
+            };
+            let ShortCircuit(response) = node.run(&ctx, &input) else {
+                panic!("expected ShortCircuit(...)");
+            };
+            response
+        };
+        let has_header = |response: &Response, name: &str, value: &str| {
+            response
+                .headers
+                .iter()
+                .any(|(k, v)| k == name && v == value)
+        };
+
+        let response = run(&compressing(Compress::Auto), Some("br, gzip"));
+        assert!(has_header(&response, "Content-Encoding", "gzip"));
+        assert!(has_header(&response, "Vary", "Accept-Encoding"));
+        assert_eq!(payload::gzip(br#"{"items":[1,2,3]}"#).ok(), response.body);
+
+        let response = run(&compressing(Compress::Auto), Some("br"));
+        assert!(!has_header(&response, "Content-Encoding", "gzip"));
+        assert_eq!(Some(br#"{"items":[1,2,3]}"#.to_vec()), response.body);
+
+        let response = run(&compressing(Compress::Gzip), None);
+        assert!(has_header(&response, "Content-Encoding", "gzip"));
+        assert!(!has_header(&response, "Vary", "Accept-Encoding"));
+    }
+
+    #[test]
+    fn exit_challenge() {
+        let challenge = Payload::Json(json!([
+            { "scheme": "Bearer", "params": { "error": "invalid_token", "realm": "api" } },
+            { "scheme": "Basic", "params": { "realm": "say \"hi\"" } },
+            { "scheme": "Negotiate", "token68": "abc=" },
+        ]));
+        let input = Input {
+            data: &[
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+                InputPort::Unconnected,
+                InputPort::Value(&challenge),
+            ],
+            phase: Phase::HttpRequestHeaders,
+        };
+
+        let ShortCircuit(response) = exit(true).run(&Mock::default(), &input) else {
+            panic!("expected ShortCircuit(...)");
+        };
+        assert_eq!(
+            vec![
+                (
+                    "WWW-Authenticate".to_string(),
+                    r#"Bearer realm="api", error="invalid_token""#.to_string()
+                ),
+                (
+                    "WWW-Authenticate".to_string(),
+                    r#"Basic realm="say \"hi\"""#.to_string()
+                ),
+                ("WWW-Authenticate".to_string(), "Negotiate abc=".to_string()),
+            ],
+            response.headers
+        );
+    }
+
+    #[test]
+    fn exit_invalid_challenge() {
+        for challenge in [
+            json!({ "params": { "realm": "api" } }),
+            json!({ "scheme": "Bearer", "params": "realm=api" }),
+            json!({ "scheme": "Bearer", "params": { "realm": ["api"] } }),
+            json!({ "scheme": "Bearer", "params": { "a b": "c" } }),
+            json!({ "scheme": "Negotiate", "token68": "abc=", "params": { "a": "b" } }),
+        ] {
+            assert!(
+                www_authenticate(&Payload::Json(challenge.clone())).is_err(),
+                "{challenge}"
+            );
+        }
+        assert_eq!(
+            Ok(vec![r#"Basic realm="api""#.to_string()]),
+            www_authenticate(&Payload::Raw(br#"Basic realm="api""#.to_vec()))
+        );
     }
 }
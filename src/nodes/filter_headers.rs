@@ -0,0 +1,248 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{self, ErrorKind, Payload};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /// `filter_headers`, with `include` and `exclude` patterns.
+    Filter,
+    /// `remove_headers`, with the patterns of the `headers` to remove
+    /// and of those to `only_keep`.
+    Remove,
+}
+
+impl Mode {
+    fn node_type(self) -> &'static str {
+        match self {
+            Mode::Filter => "filter_headers",
+            Mode::Remove => "remove_headers",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FilterHeadersConfig {
+    mode: Mode,
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl NodeConfig for FilterHeadersConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct FilterHeaders {
+    config: FilterHeadersConfig,
+}
+
+impl Node for FilterHeaders {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        let config = &self.config;
+
+        match input.get(0) {
+            Some(headers) => {
+                match payload::filter_headers(headers, &config.include, &config.exclude) {
+                    Ok(payload) => Done(vec![Some(payload)]),
+                    Err(e) => Fail(vec![Some(Payload::Error(
+                        ErrorKind::ParseError,
+                        format!("{}: {e}", config.mode.node_type()),
+                    ))]),
+                }
+            }
+            None => Done(vec![None]),
+        }
+    }
+}
+
+fn get_patterns(
+    mode: Mode,
+    bt: &BTreeMap<String, Value>,
+    key: &str,
+) -> Result<Vec<String>, String> {
+    let node_type = mode.node_type();
+    match bt.get(key) {
+        None => Ok(vec![]),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(s) if !s.is_empty() => Ok(s.clone()),
+                _ => Err(format!("{node_type}: '{key}' must be a list of patterns")),
+            })
+            .collect(),
+        Some(_) => Err(format!("{node_type}: '{key}' must be a list of patterns")),
+    }
+}
+
+pub struct FilterHeadersFactory {
+    mode: Mode,
+}
+
+impl FilterHeadersFactory {
+    pub fn filter() -> Self {
+        FilterHeadersFactory { mode: Mode::Filter }
+    }
+
+    pub fn remove() -> Self {
+        FilterHeadersFactory { mode: Mode::Remove }
+    }
+}
+
+impl NodeFactory for FilterHeadersFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let mode = self.mode;
+        let (include, exclude) = match mode {
+            Mode::Filter => (
+                get_patterns(mode, bt, "include")?,
+                get_patterns(mode, bt, "exclude")?,
+            ),
+            Mode::Remove => {
+                let include = get_patterns(mode, bt, "only_keep")?;
+                let exclude = get_patterns(mode, bt, "headers")?;
+                if include.is_empty() && exclude.is_empty() {
+                    return Err(
+                        "remove_headers: one of 'headers' or 'only_keep' is required".into(),
+                    );
+                }
+                (include, exclude)
+            }
+        };
+
+        Ok(Box::new(FilterHeadersConfig {
+            mode,
+            include,
+            exclude,
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<FilterHeadersConfig>() {
+            Some(fc) => Ok(Box::new(FilterHeaders { config: fc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{InputPort, Phase};
+    use mock_proxy_wasm::*;
+    use proxy_wasm::types::Bytes;
+    use serde_json::json;
+
+    fn new_config_for(
+        factory: FilterHeadersFactory,
+        bt: Value,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let Value::Object(map) = bt else {
+            panic!("expected an object");
+        };
+        let bt = map.into_iter().collect();
+        factory.new_config("FILTER", &[], &[], &bt)
+    }
+
+    fn new_config(bt: Value) -> Result<Box<dyn NodeConfig>, String> {
+        new_config_for(FilterHeadersFactory::filter(), bt)
+    }
+
+    fn remove_headers(bt: Value, headers: Value) -> State {
+        let config = new_config_for(FilterHeadersFactory::remove(), bt).unwrap();
+        let node = FilterHeadersFactory::remove().new_node(&*config).unwrap();
+        let headers = Payload::Json(headers);
+        let input = Input {
+            data: &[InputPort::Value(&headers)],
+            phase: Phase::HttpRequestHeaders,
+        };
+        node.run(&Mock {}, &input)
+    }
+
+    struct Mock {}
+
+    #[mock_proxy_wasm_context]
+    impl Context for Mock {}
+
+    #[mock_proxy_wasm_http_context]
+    impl HttpContext for Mock {}
+
+    #[test]
+    fn filter_headers_config() {
+        assert!(new_config(json!({ "include": ["x-forwarded-*"], "exclude": ["*-host"] })).is_ok());
+        assert!(new_config(json!({})).is_ok());
+        assert!(new_config(json!({ "include": "x-forwarded-*" })).is_err());
+        assert!(new_config(json!({ "exclude": ["ok", 1] })).is_err());
+    }
+
+    #[test]
+    fn remove_headers_config() {
+        let remove = |bt| new_config_for(FilterHeadersFactory::remove(), bt);
+        assert!(remove(json!({ "headers": ["connection", "x-internal-*"] })).is_ok());
+        assert!(remove(json!({ "only_keep": ["accept*"] })).is_ok());
+        assert!(remove(json!({})).is_err());
+        assert!(remove(json!({ "headers": "connection" })).is_err());
+    }
+
+    #[test]
+    fn remove_headers_run() {
+        let headers = json!({
+            "Connection": "keep-alive",
+            "X-Internal-Token": "secret",
+            "Accept": "application/json",
+            "Accept-Language": "en",
+        });
+
+        assert_eq!(
+            Done(vec![Some(Payload::Json(json!({
+                "Accept": "application/json",
+                "Accept-Language": "en",
+            })))]),
+            remove_headers(
+                json!({ "headers": ["connection", "x-internal-*"] }),
+                headers.clone()
+            )
+        );
+        assert_eq!(
+            Done(vec![Some(Payload::Json(
+                json!({ "Accept": "application/json" })
+            ))]),
+            remove_headers(
+                json!({ "only_keep": ["accept*"], "headers": ["accept-language"] }),
+                headers
+            )
+        );
+
+        let Fail(payloads) = remove_headers(json!({ "headers": ["a"] }), json!("a: b")) else {
+            panic!("expected Fail(...)");
+        };
+        assert!(matches!(
+            payloads.first(),
+            Some(Some(Payload::Error(_, e))) if e.starts_with("remove_headers: ")
+        ));
+    }
+}
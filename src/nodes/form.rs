@@ -0,0 +1,184 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Parse,
+    Encode,
+}
+
+#[derive(Clone, Debug)]
+pub struct FormConfig {
+    action: Action,
+}
+
+impl NodeConfig for FormConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Form {
+    config: FormConfig,
+}
+
+/// Parse urlencoded data into an object. Unlike the implicit conversion of
+/// urlencoded payloads, which keeps the last value of a repeated key, the
+/// values of a repeated key are collected into an array, as `to_pwm_query`
+/// produces them.
+fn parse(input: &[u8]) -> Value {
+    let mut map = serde_json::Map::new();
+
+    for (k, v) in form_urlencoded::parse(input) {
+        match map.get_mut(k.as_ref()) {
+            Some(Value::Array(values)) => values.push(v.into()),
+            Some(value) => *value = Value::Array(vec![value.take(), v.into()]),
+            None => {
+                map.insert(k.into(), v.into());
+            }
+        }
+    }
+
+    map.into()
+}
+
+impl Form {
+    /// Convert between `application/x-www-form-urlencoded` strings and objects,
+    /// regardless of the content type of the input.
+    fn convert(&self, input: &Payload) -> Result<Payload, String> {
+        match (self.config.action, input) {
+            (_, Payload::Error(_, e)) => Err(e.clone()),
+            (Action::Parse, Payload::Raw(bytes)) => Ok(Payload::Json(parse(bytes))),
+            (Action::Parse, Payload::Json(Value::String(s))) => {
+                Ok(Payload::Json(parse(s.as_bytes())))
+            }
+            (Action::Parse, Payload::Json(_)) => Err("form: expected a string to parse".into()),
+            (Action::Encode, Payload::Json(Value::Object(_))) => {
+                Ok(Payload::Raw(input.to_pwm_query().into_bytes()))
+            }
+            (Action::Encode, _) => Err("form: expected an object to encode".into()),
+        }
+    }
+}
+
+impl Node for Form {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        match input.get(0) {
+            Some(payload) => match self.convert(payload) {
+                Ok(payload) => Done(vec![Some(payload)]),
+                Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+            },
+            None => Done(vec![None]),
+        }
+    }
+}
+
+pub struct FormFactory {}
+
+impl NodeFactory for FormFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let action = match get_config_value::<String>(bt, "action").as_deref() {
+            Some("parse") => Action::Parse,
+            Some("encode") => Action::Encode,
+            Some(other) => return Err(format!("form: invalid 'action': {other}")),
+            None => return Err("form: missing 'action'".into()),
+        };
+
+        Ok(Box::new(FormConfig { action }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<FormConfig>() {
+            Some(fc) => Ok(Box::new(Form { config: fc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn convert(action: Action, payload: Payload) -> Result<Payload, String> {
+        let node = Form {
+            config: FormConfig { action },
+        };
+        node.convert(&payload)
+    }
+
+    #[test]
+    fn form_parse() {
+        assert_eq!(
+            Ok(Payload::Json(
+                json!({ "user": "ann lee", "tag": ["a", "b"] })
+            )),
+            convert(
+                Action::Parse,
+                Payload::Raw(b"user=ann+lee&tag=a&tag=b".to_vec())
+            )
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!({ "q": "a&b" }))),
+            convert(Action::Parse, Payload::Json(json!("q=a%26b")))
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!({}))),
+            convert(Action::Parse, Payload::Raw(vec![]))
+        );
+    }
+
+    #[test]
+    fn form_encode() {
+        assert_eq!(
+            Ok(Payload::Raw(b"tag=a&tag=b&user=ann+lee".to_vec())),
+            convert(
+                Action::Encode,
+                Payload::Json(json!({ "user": "ann lee", "tag": ["a", "b"] }))
+            )
+        );
+    }
+
+    #[test]
+    fn form_invalid_input() {
+        assert!(convert(Action::Parse, Payload::Json(json!({ "a": 1 }))).is_err());
+        assert!(convert(Action::Encode, Payload::Json(json!("a=1"))).is_err());
+        assert!(convert(Action::Encode, Payload::Raw(b"a=1".to_vec())).is_err());
+    }
+
+    #[test]
+    fn form_invalid_action() {
+        let bt = BTreeMap::from([("action".to_string(), json!("decode"))]);
+        assert!(FormFactory {}.new_config("F", &[], &[], &bt).is_err());
+        assert!(FormFactory {}
+            .new_config("F", &[], &[], &BTreeMap::new())
+            .is_err());
+    }
+}
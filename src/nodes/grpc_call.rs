@@ -0,0 +1,280 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::config::get_config_value;
+use crate::data::{Input, Phase, State, State::*};
+use crate::nodes::call::parse_timeout;
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload;
+use crate::payload::{ErrorKind, Payload};
+
+/// gRPC status code for a successful call.
+const GRPC_STATUS_OK: u32 = 0;
+const GRPC_STATUS_DEADLINE_EXCEEDED: u32 = 4;
+
+#[derive(Clone, Debug)]
+pub struct GrpcCallConfig {
+    upstream: String,
+    service: String,
+    method: String,
+    timeout: Duration,
+}
+
+impl NodeConfig for GrpcCallConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct GrpcCall {
+    config: GrpcCallConfig,
+}
+
+fn fail(kind: ErrorKind, msg: String, status: Option<u32>) -> State {
+    Fail(vec![
+        Some(Payload::Error(kind, msg)),
+        status.map(|s| Payload::Json(s.into())),
+    ])
+}
+
+/// Messages are sent as they are: without a protobuf descriptor
+/// there is no way of encoding a JSON object as a protobuf message.
+fn message_bytes(message: Option<&Payload>) -> Result<Option<Vec<u8>>, String> {
+    match message {
+        None => Ok(None),
+        Some(Payload::Raw(bytes)) => Ok(Some(bytes.clone())),
+        Some(Payload::Json(Value::String(s))) => Ok(Some(s.clone().into_bytes())),
+        Some(Payload::Json(_)) => {
+            Err("grpc_call: 'message' must be an encoded protobuf message, not a JSON value".into())
+        }
+        Some(Payload::Error(_, e)) => Err(e.clone()),
+    }
+}
+
+impl Node for GrpcCall {
+    fn run(&self, ctx: &dyn HttpContext, input: &Input) -> State {
+        let message = input.get(0);
+        let metadata = input.get(1);
+
+        let message = match message_bytes(message) {
+            Ok(bytes) => bytes,
+            Err(e) => return fail(ErrorKind::ParseError, e, None),
+        };
+
+        let metadata_vec = payload::to_pwm_headers(metadata)
+            .into_iter()
+            .map(|(k, v)| (k, v.as_bytes()))
+            .collect();
+
+        let config = &self.config;
+        let result = ctx.dispatch_grpc_call(
+            &config.upstream,
+            &config.service,
+            &config.method,
+            metadata_vec,
+            message.as_deref(),
+            config.timeout,
+        );
+
+        match result {
+            Ok(id) => {
+                log::debug!("grpc_call: dispatch call id: {:?}", id);
+                Waiting(id)
+            }
+            Err(status) => {
+                log::debug!("grpc_call: dispatch call failed: {:?}", status);
+                fail(
+                    ErrorKind::UpstreamError,
+                    format!("grpc_call error: {:?}", status),
+                    None,
+                )
+            }
+        }
+    }
+
+    fn resume(&self, ctx: &dyn HttpContext, input: &Input) -> State {
+        let Phase::GrpcCallResponse(status) = input.phase else {
+            return fail(
+                ErrorKind::UpstreamError,
+                "grpc_call: resumed without a gRPC response".into(),
+                None,
+            );
+        };
+
+        if status != GRPC_STATUS_OK {
+            let kind = match status {
+                GRPC_STATUS_DEADLINE_EXCEEDED => ErrorKind::Timeout,
+                _ => ErrorKind::UpstreamError,
+            };
+            return fail(
+                kind,
+                format!("grpc_call: call failed with gRPC status {status}"),
+                Some(status),
+            );
+        }
+
+        let message = ctx
+            .get_grpc_call_response_body(0, usize::MAX)
+            .map(Payload::Raw);
+
+        Done(vec![message, Some(Payload::Json(status.into()))])
+    }
+}
+
+pub struct GrpcCallFactory {}
+
+impl NodeFactory for GrpcCallFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["message", "metadata"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["message", "status"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let required = |key: &str| {
+            get_config_value::<String>(bt, key)
+                .ok_or_else(|| format!("grpc_call: '{key}' is a required attribute"))
+        };
+
+        Ok(Box::new(GrpcCallConfig {
+            upstream: required("upstream")?,
+            service: required("service")?,
+            method: required("method")?,
+            timeout: match bt.get("timeout") {
+                Some(value) => parse_timeout("grpc_call", value)?,
+                None => Duration::from_secs(60),
+            },
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<GrpcCallConfig>() {
+            Some(cc) => Ok(Box::new(GrpcCall { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::InputPort;
+    use mock_proxy_wasm::*;
+    use proxy_wasm::types::{Bytes, Status};
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Mock {
+        dispatched: RefCell<Vec<(String, Option<Vec<u8>>)>>,
+    }
+
+    #[mock_proxy_wasm_context]
+    impl Context for Mock {
+        fn dispatch_grpc_call(
+            &self,
+            _upstream_name: &str,
+            service_name: &str,
+            method_name: &str,
+            initial_metadata: Vec<(&str, &[u8])>,
+            message: Option<&[u8]>,
+            _timeout: Duration,
+        ) -> Result<u32, Status> {
+            assert_eq!(vec![("x-id", b"1".as_slice())], initial_metadata);
+            let path = format!("{service_name}/{method_name}");
+            let mut dispatched = self.dispatched.borrow_mut();
+            dispatched.push((path, message.map(<[u8]>::to_vec)));
+            Ok(dispatched.len() as u32)
+        }
+
+        fn get_grpc_call_response_body(&self, _start: usize, _max_size: usize) -> Option<Bytes> {
+            Some(b"\x08\x01".to_vec())
+        }
+    }
+
+    #[mock_proxy_wasm_http_context]
+    impl HttpContext for Mock {}
+
+    fn node() -> GrpcCall {
+        GrpcCall {
+            config: GrpcCallConfig {
+                upstream: "grpc_cluster".into(),
+                service: "pkg.Greeter".into(),
+                method: "SayHello".into(),
+                timeout: Duration::from_secs(1),
+            },
+        }
+    }
+
+    #[test]
+    fn grpc_call_lifecycle() {
+        let ctx = Mock::default();
+        let node = node();
+        let message = Payload::Raw(b"\x0a\x02hi".to_vec());
+        let metadata = Payload::Json(serde_json::json!({ "x-id": "1" }));
+
+        let input = Input {
+            data: &[InputPort::Value(&message), InputPort::Value(&metadata)],
+            phase: Phase::HttpRequestHeaders,
+        };
+        assert_eq!(Waiting(1), node.run(&ctx as &dyn HttpContext, &input));
+        assert_eq!(
+            vec![(
+                "pkg.Greeter/SayHello".to_string(),
+                Some(b"\x0a\x02hi".to_vec())
+            )],
+            *ctx.dispatched.borrow()
+        );
+
+        let input = Input {
+            data: &[InputPort::Value(&message), InputPort::Value(&metadata)],
+            phase: Phase::GrpcCallResponse(GRPC_STATUS_OK),
+        };
+        assert_eq!(
+            Done(vec![
+                Some(Payload::Raw(b"\x08\x01".to_vec())),
+                Some(Payload::Json(0.into()))
+            ]),
+            node.resume(&ctx as &dyn HttpContext, &input)
+        );
+    }
+
+    #[test]
+    fn grpc_call_failed_status() {
+        let ctx = Mock::default();
+        let input = Input {
+            data: &[],
+            phase: Phase::GrpcCallResponse(14),
+        };
+        assert_eq!(
+            fail(
+                ErrorKind::UpstreamError,
+                "grpc_call: call failed with gRPC status 14".into(),
+                Some(14)
+            ),
+            node().resume(&ctx as &dyn HttpContext, &input)
+        );
+    }
+
+    #[test]
+    fn grpc_call_json_message() {
+        let message = Payload::Json(serde_json::json!({ "name": "hi" }));
+        assert!(message_bytes(Some(&message)).is_err());
+    }
+}
@@ -1,19 +1,22 @@
-use handlebars::Handlebars;
+use handlebars::{
+    handlebars_helper, Context, Handlebars, Helper, HelperResult, Output, RenderContext,
+};
 use proxy_wasm::traits::*;
 use serde_json::Value;
 use std::any::Any;
 use std::collections::BTreeMap;
 
 use crate::config::get_config_value;
-use crate::data::{Input, State};
+use crate::data::{Input, InputPort, State};
 use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
-use crate::payload::Payload;
+use crate::payload::{ErrorKind, Payload};
 
 #[derive(Clone, Debug)]
 pub struct HandlebarsConfig {
     template: String,
     content_type: String,
     inputs: Vec<String>,
+    helpers: Vec<String>,
 }
 
 impl NodeConfig for HandlebarsConfig {
@@ -28,10 +31,44 @@ pub struct HandlebarsNode<'a> {
     handlebars: Handlebars<'a>,
 }
 
+/// Embed a value as JSON. The output is not HTML-escaped,
+/// so that `{{json x}}` can be used within JSON templates.
+fn json_helper(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = h.param(0).map_or(&Value::Null, |p| p.value());
+    out.write(&value.to_string())?;
+    Ok(())
+}
+
+handlebars_helper!(default_helper: |value: Json, fallback: Json| {
+    if value.is_null() {
+        fallback.clone()
+    } else {
+        value.clone()
+    }
+});
+
+/// Helpers provided in addition to the Handlebars built-in ones
+/// (such as `eq`, `and` or `lookup`), enabled by default.
+const HELPERS: &[&str] = &["json", "default"];
+
 impl HandlebarsNode<'_> {
     fn new(config: HandlebarsConfig) -> Self {
         let mut handlebars = Handlebars::new();
 
+        for helper in &config.helpers {
+            match helper.as_str() {
+                "json" => handlebars.register_helper("json", Box::new(json_helper)),
+                "default" => handlebars.register_helper("default", Box::new(default_helper)),
+                _ => {}
+            }
+        }
+
         match handlebars.register_template_string("template", &config.template) {
             Ok(()) => {}
             Err(err) => {
@@ -55,10 +92,10 @@ impl Node for HandlebarsNode<'_> {
         for (input_name, input) in self.config.inputs.iter().zip(input.data.iter()) {
             let var = sanitize_handlebars_variable(input_name);
             match input {
-                Some(Payload::Json(value)) => {
+                InputPort::Value(Payload::Json(value)) => {
                     data.insert(var, value);
                 }
-                Some(Payload::Raw(vec_bytes)) => {
+                InputPort::Value(Payload::Raw(vec_bytes)) => {
                     match std::str::from_utf8(vec_bytes) {
                         Ok(s) => {
                             let v = serde_json::to_value::<String>(s.into())
@@ -70,10 +107,10 @@ impl Node for HandlebarsNode<'_> {
                         }
                     };
                 }
-                Some(Payload::Error(error)) => {
+                InputPort::Value(Payload::Error(_, error)) => {
                     vs.push((var, serde_json::json!(error)));
                 }
-                None => {}
+                InputPort::Unconnected | InputPort::NotReady => {}
             }
         }
 
@@ -85,13 +122,14 @@ impl Node for HandlebarsNode<'_> {
             Ok(output) => {
                 log::debug!("output: {output}");
                 match Payload::from_bytes(output.into(), Some(&self.config.content_type)) {
-                    p @ Some(Payload::Error(_)) => State::Fail(vec![p]),
+                    p @ Some(Payload::Error(..)) => State::Fail(vec![p]),
                     p => State::Done(vec![p]),
                 }
             }
-            Err(err) => State::Fail(vec![Some(Payload::Error(format!(
-                "handlebars: error rendering template: {err}"
-            )))]),
+            Err(err) => State::Fail(vec![Some(Payload::Error(
+                ErrorKind::ConfigError,
+                format!("handlebars: error rendering template: {err}"),
+            ))]),
         }
     }
 }
@@ -120,18 +158,104 @@ impl NodeFactory for HandlebarsFactory {
         _outputs: &[String],
         bt: &BTreeMap<String, Value>,
     ) -> Result<Box<dyn NodeConfig>, String> {
+        let helpers = match bt.get("helpers") {
+            None => HELPERS.iter().map(|h| h.to_string()).collect(),
+            Some(value) => {
+                let helpers: Vec<String> = serde_json::from_value(value.clone())
+                    .map_err(|_| "handlebars: 'helpers' must be a list of helper names")?;
+                if let Some(h) = helpers.iter().find(|h| !HELPERS.contains(&h.as_str())) {
+                    return Err(format!(
+                        "handlebars: unknown helper '{h}', expected one of: {}",
+                        HELPERS.join(", ")
+                    ));
+                }
+                helpers
+            }
+        };
+
         Ok(Box::new(HandlebarsConfig {
             inputs: inputs.to_vec(),
+            helpers,
             template: get_config_value(bt, "template").unwrap_or_else(|| String::from("")),
             content_type: get_config_value(bt, "content_type")
                 .unwrap_or_else(|| String::from("text/plain")),
         }))
     }
 
-    fn new_node(&self, config: &dyn NodeConfig) -> Box<dyn Node> {
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
         match config.as_any().downcast_ref::<HandlebarsConfig>() {
-            Some(cc) => Box::new(HandlebarsNode::new(cc.clone())),
-            None => panic!("incompatible NodeConfig"),
+            Some(cc) => Ok(Box::new(HandlebarsNode::new(cc.clone()))),
+            None => Err("incompatible NodeConfig".into()),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn render(template: &str, helpers: &[&str], data: Value) -> Result<String, String> {
+        let node = HandlebarsNode::new(HandlebarsConfig {
+            template: template.into(),
+            content_type: "text/plain".into(),
+            inputs: vec![],
+            helpers: helpers.iter().map(|h| h.to_string()).collect(),
+        });
+        node.handlebars
+            .render("template", &data)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn helper_json() {
+        let data = json!({ "user": { "name": "a \"b\"", "ids": [1, 2] } });
+        assert_eq!(
+            Ok(r#"{"user": {"ids":[1,2],"name":"a \"b\""}}"#.to_string()),
+            render(r#"{"user": {{json user}}}"#, HELPERS, data)
+        );
+        assert_eq!(
+            Ok("null".to_string()),
+            render("{{json missing}}", HELPERS, json!({}))
+        );
+    }
+
+    #[test]
+    fn helper_default() {
+        let template = "{{default name \"anonymous\"}}";
+        assert_eq!(
+            Ok("anonymous".to_string()),
+            render(template, HELPERS, json!({}))
+        );
+        assert_eq!(
+            Ok("alice".to_string()),
+            render(template, HELPERS, json!({ "name": "alice" }))
+        );
+        assert_eq!(
+            Ok("{\"id\":1}".to_string()),
+            render(
+                "{{json (default user fallback)}}",
+                HELPERS,
+                json!({ "fallback": { "id": 1 } })
+            )
+        );
+    }
+
+    #[test]
+    fn helper_eq() {
+        let template = "{{#if (eq method \"GET\")}}read{{else}}write{{/if}}";
+        assert_eq!(
+            Ok("read".to_string()),
+            render(template, &[], json!({ "method": "GET" }))
+        );
+        assert_eq!(
+            Ok("write".to_string()),
+            render(template, &[], json!({ "method": "POST" }))
+        );
+    }
+
+    #[test]
+    fn helpers_disabled() {
+        assert!(render("{{json x}}", &["default"], json!({ "x": 1 })).is_err());
+    }
+}
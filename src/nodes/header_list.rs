@@ -0,0 +1,223 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{self, ErrorKind, Payload};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Direction {
+    Split,
+    Join,
+}
+
+impl Direction {
+    fn node_type(self) -> &'static str {
+        match self {
+            Direction::Split => "split_header",
+            Direction::Join => "join_header",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HeaderListConfig {
+    direction: Direction,
+    delimiter: char,
+}
+
+impl NodeConfig for HeaderListConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct HeaderList {
+    config: HeaderListConfig,
+}
+
+impl HeaderList {
+    fn split(&self, payload: &Payload) -> Result<Payload, String> {
+        let delimiter = self.config.delimiter;
+        let items = match payload {
+            Payload::Json(Value::String(s)) => payload::split_header_value(s, delimiter),
+            // multi-value headers, as produced by the implicit headers ports
+            Payload::Json(Value::Array(vs)) => {
+                let mut items = vec![];
+                for v in vs {
+                    match v {
+                        Value::String(s) => items.extend(payload::split_header_value(s, delimiter)),
+                        _ => return Err(format!("split_header: invalid list item: {v}")),
+                    }
+                }
+                items
+            }
+            Payload::Raw(bytes) => match std::str::from_utf8(bytes) {
+                Ok(s) => payload::split_header_value(s, delimiter),
+                Err(e) => return Err(format!("split_header: {e}")),
+            },
+            Payload::Json(v) => return Err(format!("split_header: not a header value: {v}")),
+            Payload::Error(_, e) => return Err(e.clone()),
+        };
+
+        Ok(Payload::Json(items.into()))
+    }
+
+    fn join(&self, payload: &Payload) -> Result<Payload, String> {
+        match payload {
+            Payload::Json(Value::Array(vs)) => {
+                let mut items = vec![];
+                for v in vs {
+                    match v {
+                        Value::String(s) => items.push(s.as_str()),
+                        _ => return Err(format!("join_header: invalid list item: {v}")),
+                    }
+                }
+                let value = payload::join_header_value(&items, self.config.delimiter);
+                Ok(Payload::Json(value.into()))
+            }
+            // already a single value
+            Payload::Json(Value::String(_)) | Payload::Raw(_) => Ok(payload.clone()),
+            Payload::Json(v) => Err(format!("join_header: not a list: {v}")),
+            Payload::Error(_, e) => Err(e.clone()),
+        }
+    }
+}
+
+impl Node for HeaderList {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        let Some(payload) = input.get(0) else {
+            return Done(vec![None]);
+        };
+
+        let result = match self.config.direction {
+            Direction::Split => self.split(payload),
+            Direction::Join => self.join(payload),
+        };
+
+        match result {
+            Ok(payload) => Done(vec![Some(payload)]),
+            Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+        }
+    }
+}
+
+pub struct HeaderListFactory {
+    direction: Direction,
+}
+
+impl HeaderListFactory {
+    pub fn split() -> Self {
+        HeaderListFactory {
+            direction: Direction::Split,
+        }
+    }
+
+    pub fn join() -> Self {
+        HeaderListFactory {
+            direction: Direction::Join,
+        }
+    }
+}
+
+impl NodeFactory for HeaderListFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let delimiter = match get_config_value::<String>(bt, "delimiter") {
+            None => ',',
+            Some(d) => {
+                let mut chars = d.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c != '"' && !c.is_whitespace() => c,
+                    _ => {
+                        return Err(format!(
+                            "{}: invalid 'delimiter' {d:?}, expected a single non-space character",
+                            self.direction.node_type()
+                        ))
+                    }
+                }
+            }
+        };
+
+        Ok(Box::new(HeaderListConfig {
+            direction: self.direction,
+            delimiter,
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<HeaderListConfig>() {
+            Some(cc) => Ok(Box::new(HeaderList { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn node(direction: Direction) -> HeaderList {
+        HeaderList {
+            config: HeaderListConfig {
+                direction,
+                delimiter: ',',
+            },
+        }
+    }
+
+    #[test]
+    fn split_values() {
+        let split = node(Direction::Split);
+        assert_eq!(
+            Ok(Payload::Json(json!(["gzip", "br"]))),
+            split.split(&Payload::Json(json!("gzip, br")))
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!(["1.1 a", "1.1 b", "1.0 c"]))),
+            split.split(&Payload::Json(json!(["1.1 a, 1.1 b", "1.0 c"])))
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!(["x", "y"]))),
+            split.split(&Payload::Raw(b"x,y".to_vec()))
+        );
+        assert!(split.split(&Payload::Json(json!({ "a": "b" }))).is_err());
+    }
+
+    #[test]
+    fn join_values() {
+        let join = node(Direction::Join);
+        assert_eq!(
+            Ok(Payload::Json(json!("gzip, br"))),
+            join.join(&Payload::Json(json!(["gzip", "br"])))
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!("gzip"))),
+            join.join(&Payload::Json(json!("gzip")))
+        );
+        assert!(join.join(&Payload::Json(json!([1]))).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use proxy_wasm::traits::*;
+use serde_json::{Map, Value};
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum JoinMode {
+    /// Only entries of `left` with a match in `right`.
+    Inner,
+    /// All entries of `left`, joined when they have a match in `right`.
+    Left,
+}
+
+#[derive(Clone, Debug)]
+pub struct JoinConfig {
+    on: String,
+    mode: JoinMode,
+}
+
+impl NodeConfig for JoinConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Join {
+    config: JoinConfig,
+}
+
+fn objects<'a>(port: &str, payload: &'a Payload) -> Result<Vec<&'a Map<String, Value>>, String> {
+    let error = || format!("join: `{port}` must be an array of objects");
+    match payload {
+        Payload::Error(_, e) => Err(e.clone()),
+        Payload::Json(Value::Array(values)) => values
+            .iter()
+            .map(|value| value.as_object().ok_or_else(error))
+            .collect(),
+        _ => Err(error()),
+    }
+}
+
+impl Join {
+    fn join(&self, left: &Payload, right: Option<&Payload>) -> Result<Value, String> {
+        let on = &self.config.on;
+        let left = objects("left", left)?;
+        let right = match right {
+            Some(payload) => objects("right", payload)?,
+            None => vec![],
+        };
+
+        // entries of `right` by their key, serialized since
+        // JSON values cannot be compared otherwise
+        let mut index: BTreeMap<String, Vec<&Map<String, Value>>> = BTreeMap::new();
+        for entry in right {
+            if let Some(key) = entry.get(on) {
+                index.entry(key.to_string()).or_default().push(entry);
+            }
+        }
+
+        let mut joined = vec![];
+        for entry in left {
+            let matches = entry.get(on).and_then(|key| index.get(&key.to_string()));
+            match matches {
+                Some(matches) => {
+                    for other in matches {
+                        // the fields of `left` take precedence
+                        let mut object = (*other).clone();
+                        object.extend(entry.iter().map(|(k, v)| (k.clone(), v.clone())));
+                        joined.push(Value::Object(object));
+                    }
+                }
+                None if self.config.mode == JoinMode::Left => {
+                    joined.push(Value::Object(entry.clone()));
+                }
+                None => {}
+            }
+        }
+
+        Ok(Value::Array(joined))
+    }
+}
+
+impl Node for Join {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        let Some(left) = input.get(0) else {
+            return Done(vec![None]);
+        };
+
+        match self.join(left, input.get(1)) {
+            Ok(value) => Done(vec![Some(Payload::Json(value))]),
+            Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+        }
+    }
+}
+
+pub struct JoinFactory {}
+
+impl NodeFactory for JoinFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["left", "right"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let Some(on) = get_config_value::<String>(bt, "on").filter(|on| !on.is_empty()) else {
+            return Err("join: missing 'on'".into());
+        };
+
+        let mode = match get_config_value::<String>(bt, "mode").as_deref() {
+            None | Some("inner") => JoinMode::Inner,
+            Some("left") => JoinMode::Left,
+            Some(other) => return Err(format!("join: invalid 'mode': {other}")),
+        };
+
+        Ok(Box::new(JoinConfig { on, mode }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<JoinConfig>() {
+            Some(jc) => Ok(Box::new(Join { config: jc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn join(mode: JoinMode, left: Value, right: Value) -> Result<Value, String> {
+        let node = Join {
+            config: JoinConfig {
+                on: "customer_id".into(),
+                mode,
+            },
+        };
+        node.join(&Payload::Json(left), Some(&Payload::Json(right)))
+    }
+
+    fn orders() -> Value {
+        json!([
+            { "id": 1, "customer_id": "a" },
+            { "id": 2, "customer_id": "b" },
+            { "id": 3 },
+        ])
+    }
+
+    fn customers() -> Value {
+        json!([
+            { "id": "c1", "customer_id": "a", "name": "Ann" },
+            { "id": "c2", "customer_id": "c", "name": "Cid" },
+        ])
+    }
+
+    #[test]
+    fn join_inner() {
+        assert_eq!(
+            Ok(json!([{ "id": 1, "customer_id": "a", "name": "Ann" }])),
+            join(JoinMode::Inner, orders(), customers())
+        );
+    }
+
+    #[test]
+    fn join_left() {
+        assert_eq!(
+            Ok(json!([
+                { "id": 1, "customer_id": "a", "name": "Ann" },
+                { "id": 2, "customer_id": "b" },
+                { "id": 3 },
+            ])),
+            join(JoinMode::Left, orders(), customers())
+        );
+    }
+
+    #[test]
+    fn join_multiple_matches() {
+        let right = json!([
+            { "customer_id": 1, "tag": "x" },
+            { "customer_id": 1, "tag": "y" },
+            { "customer_id": "1", "tag": "z" },
+        ]);
+        assert_eq!(
+            Ok(json!([
+                { "customer_id": 1, "tag": "x" },
+                { "customer_id": 1, "tag": "y" },
+            ])),
+            join(JoinMode::Inner, json!([{ "customer_id": 1 }]), right)
+        );
+    }
+
+    #[test]
+    fn join_invalid_input() {
+        assert_eq!(
+            Err("join: `left` must be an array of objects".to_string()),
+            join(JoinMode::Inner, json!({ "customer_id": "a" }), customers())
+        );
+        assert_eq!(
+            Err("join: `right` must be an array of objects".to_string()),
+            join(JoinMode::Inner, orders(), json!([1, 2]))
+        );
+    }
+
+    #[test]
+    fn join_invalid_config() {
+        let config = |bt: Value| {
+            let Value::Object(map) = bt else {
+                panic!("expected an object");
+            };
+            JoinFactory {}
+                .new_config("J", &[], &[], &map.into_iter().collect())
+                .map(|_| ())
+        };
+        assert!(config(json!({ "on": "id" })).is_ok());
+        assert!(config(json!({})).is_err());
+        assert!(config(json!({ "on": "id", "mode": "outer" })).is_err());
+    }
+}
@@ -8,13 +8,26 @@ use std::collections::BTreeMap;
 use std::rc::Rc;
 
 use crate::config::get_config_value;
-use crate::data::{Input, State};
+use crate::data::{Input, Phase, State};
 use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
-use crate::payload::Payload;
+use crate::payload::{ErrorKind, Payload};
+
+/// The default number of results after which a program is stopped.
+const DEFAULT_MAX_RESULTS: usize = 10000;
 
 #[derive(Clone)]
 pub struct Jq {
     inputs: Vec<String>,
+    outputs: Vec<String>,
+    args: Vec<JsonValue>,
+    /// Whether `$phase` is bound, which is the case unless
+    /// an input port or an argument has that name.
+    binds_phase: bool,
+    /// Programs producing more results are stopped and fail. This only
+    /// caps the results: the work done between two of them is unbounded.
+    max_results: usize,
+    /// Whether results are emitted as raw strings, like `jq -r`.
+    raw_output: bool,
     filter: Filter,
 }
 
@@ -56,18 +69,79 @@ impl Errors {
 
 impl From<Errors> for State {
     fn from(val: Errors) -> Self {
-        State::Fail(vec![Some(Payload::Error(if val.is_empty() {
+        let msg = if val.is_empty() {
             // should be unreachable
             "unknown jq error".to_string()
         } else {
             val.0.join(", ")
-        }))])
+        };
+        State::Fail(vec![Some(Payload::Error(ErrorKind::ConfigError, msg))])
+    }
+}
+
+/// Add the definitions of the `jq_prelude` to those available to programs.
+fn insert_prelude(defs: &mut ParseCtx, prelude: &str) -> Result<(), String> {
+    let (parsed, errs) = jaq_parse::parse(prelude, jaq_parse::defs());
+    if !errs.is_empty() {
+        let errs: Vec<String> = errs.iter().map(|err| err.to_string()).collect();
+        return Err(format!("invalid jq_prelude: {}", errs.join(", ")));
+    }
+
+    defs.insert_defs(parsed.unwrap_or_default());
+    if !defs.errs.is_empty() {
+        let errs: Vec<String> = defs.errs.iter().map(|(err, _)| err.to_string()).collect();
+        return Err(format!(
+            "jq_prelude compilation failed: {}",
+            errs.join(", ")
+        ));
     }
+
+    Ok(())
+}
+
+/// Check the definitions of a `jq_prelude` once for all jq nodes.
+/// They are compiled without any variables in scope, so they cannot
+/// refer to the input ports of the nodes using them.
+pub fn check_prelude(prelude: &str) -> Result<(), String> {
+    let mut defs = ParseCtx::new(vec![]);
+    defs.insert_natives(jaq_core::core());
+    defs.insert_defs(jaq_std::std());
+    insert_prelude(&mut defs, prelude)
 }
 
 impl Jq {
-    fn new(jq: &str, inputs: Vec<String>) -> Result<Self, String> {
-        let mut defs = ParseCtx::new(inputs.clone());
+    fn new(
+        jq: &str,
+        inputs: Vec<String>,
+        args: BTreeMap<String, JsonValue>,
+    ) -> Result<Self, String> {
+        Self::with_prelude(jq, "", inputs, args)
+    }
+
+    pub(crate) fn with_prelude(
+        jq: &str,
+        prelude: &str,
+        inputs: Vec<String>,
+        args: BTreeMap<String, JsonValue>,
+    ) -> Result<Self, String> {
+        // constant arguments are bound as variables after the input ports
+        let mut vars = inputs.clone();
+        for name in args.keys() {
+            if inputs.contains(name) {
+                return Err(format!(
+                    "argument `${name}` collides with an input port variable"
+                ));
+            }
+            vars.push(name.clone());
+        }
+        let args = args.into_values().collect();
+
+        let binds_phase = !vars.iter().any(|var| var == "phase");
+        if binds_phase {
+            vars.push("phase".into());
+        }
+
+        let mut defs = ParseCtx::new(vars);
 
         defs.insert_natives(jaq_core::core());
         defs.insert_defs(jaq_std::std());
@@ -79,6 +153,12 @@ impl Jq {
             return Err("failed parsing filter inputs".to_string());
         }
 
+        // `check_prelude` made sure that the prelude does not refer to
+        // any variables, so it cannot capture those of the inputs
+        if !prelude.is_empty() {
+            insert_prelude(&mut defs, prelude)?;
+        }
+
         let (parsed, errs) = jaq_parse::parse(jq, jaq_parse::main());
         if !errs.is_empty() {
             for err in errs {
@@ -100,10 +180,49 @@ impl Jq {
             return Err("filter compilation failed".to_string());
         }
 
-        Ok(Jq { inputs, filter })
+        Ok(Jq {
+            inputs,
+            outputs: vec![],
+            args,
+            binds_phase,
+            max_results: DEFAULT_MAX_RESULTS,
+            raw_output: false,
+            filter,
+        })
+    }
+
+    /// Run the program, for other nodes built on jq.
+    /// A condition over the values of the given input ports,
+    /// available as variables named after the ports.
+    pub(crate) fn predicate(jq: &str, prelude: &str, inputs: &[String]) -> Result<Self, String> {
+        Self::with_prelude(jq, prelude, sanitize_jq_inputs(inputs), BTreeMap::new())
+    }
+
+    pub(crate) fn eval(
+        &self,
+        inputs: &[Option<&Payload>],
+        phase: Option<Phase>,
+    ) -> Result<Vec<JsonValue>, String> {
+        self.exec(inputs, phase).map_err(|errs| errs.0.join(", "))
     }
 
-    fn exec(&self, inputs: &[Option<&Payload>]) -> Result<Vec<JsonValue>, Errors> {
+    /// Whether the first result is neither `null` nor `false`.
+    pub(crate) fn holds(
+        &self,
+        inputs: &[Option<&Payload>],
+        phase: Option<Phase>,
+    ) -> Result<bool, String> {
+        Ok(matches!(
+            self.eval(inputs, phase)?.first(),
+            Some(result) if !matches!(result, JsonValue::Null | JsonValue::Bool(false))
+        ))
+    }
+
+    fn exec(
+        &self,
+        inputs: &[Option<&Payload>],
+        phase: Option<Phase>,
+    ) -> Result<Vec<JsonValue>, Errors> {
         if inputs.len() != self.inputs.len() {
             return Err(Errors::from(format!(
                 "invalid number of inputs, expected: {}, got: {}",
@@ -129,7 +248,12 @@ impl Jq {
                     },
                     None => Val::Null,
                 }
-            });
+            })
+            .chain(self.args.iter().map(|arg| arg.clone().into()))
+            .chain(self.binds_phase.then(|| match phase {
+                Some(phase) => JsonValue::from(phase.name()).into(),
+                None => Val::Null,
+            }));
 
         let input_iter = {
             let iter = std::iter::empty::<Result<Val, String>>();
@@ -140,17 +264,23 @@ impl Jq {
 
         let ctx = Ctx::new(vars_iter, &input_iter);
 
-        let results: Vec<JsonValue> = self
-            .filter
-            .run((ctx, input))
-            .map(|item| match item {
+        let mut results: Vec<JsonValue> = vec![];
+        for item in self.filter.run((ctx, input)) {
+            if results.len() == self.max_results {
+                errs.push(format!(
+                    "jq: program stopped after {} results",
+                    self.max_results
+                ));
+                break;
+            }
+            results.push(match item {
                 Ok(v) => v.into(),
                 Err(e) => {
                     errs.push(e.to_string());
                     JsonValue::Null
                 }
-            })
-            .collect();
+            });
+        }
 
         if !errs.is_empty() {
             return Err(errs);
@@ -158,25 +288,54 @@ impl Jq {
 
         Ok(results)
     }
+
+    /// Whether the results are a single object whose keys are all names
+    /// of output ports, in which case each value goes to its port.
+    fn routes_by_name(&self, results: &[JsonValue]) -> bool {
+        match results {
+            [JsonValue::Object(map)] if self.outputs.len() > 1 && !map.is_empty() => {
+                map.keys().all(|key| self.outputs.contains(key))
+            }
+            _ => false,
+        }
+    }
+
+    /// Strings are emitted as their contents and other values
+    /// as their serialization when `raw_output` is set.
+    fn to_payload(&self, value: JsonValue) -> Payload {
+        match value {
+            JsonValue::String(s) if self.raw_output => Payload::Raw(s.into_bytes()),
+            value if self.raw_output => Payload::Raw(value.to_string().into_bytes()),
+            value => Payload::Json(value),
+        }
+    }
+
+    fn route(&self, results: Vec<JsonValue>) -> Vec<Option<Payload>> {
+        if self.routes_by_name(&results) {
+            let Some(JsonValue::Object(mut map)) = results.into_iter().next() else {
+                unreachable!("checked by routes_by_name");
+            };
+            return self
+                .outputs
+                .iter()
+                .map(|name| map.remove(name).map(|value| self.to_payload(value)))
+                .collect();
+        }
+
+        // one value per output port, in order: ports without
+        // a value produce no data, and extra values are dropped
+        let mut results = results.into_iter().map(|item| Some(self.to_payload(item)));
+        self.outputs
+            .iter()
+            .map(|_| results.next().flatten())
+            .collect()
+    }
 }
 
 impl Node for Rc<Jq> {
     fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
-        match self.exec(input.data) {
-            Ok(results) => {
-                match results.len() {
-                    // empty
-                    0 => State::Done(vec![None]),
-
-                    // one or more
-                    _ => State::Done(
-                        results
-                            .into_iter()
-                            .map(|item| Some(Payload::Json(item)))
-                            .collect(),
-                    ),
-                }
-            }
+        match self.exec(&input.payloads(), Some(input.phase)) {
+            Ok(results) => State::Done(self.route(results)),
             Err(errs) => errs.into(),
         }
     }
@@ -212,20 +371,36 @@ impl NodeFactory for JqFactory {
         &self,
         _name: &str,
         inputs: &[String],
-        _outputs: &[String],
+        outputs: &[String],
         bt: &BTreeMap<String, JsonValue>,
     ) -> Result<Box<dyn NodeConfig>, String> {
         let filter = get_config_value(bt, "jq").unwrap_or(".".to_string());
         let inputs = sanitize_jq_inputs(inputs);
-        let jq = Jq::new(&filter, inputs)?;
+        let args = match bt.get("args") {
+            Some(JsonValue::Object(map)) => map
+                .iter()
+                .map(|(k, v)| (k.trim_start_matches('$').to_string(), v.clone()))
+                .collect(),
+            Some(_) => return Err("'args' must be an object".into()),
+            None => BTreeMap::new(),
+        };
+        let prelude: String = get_config_value(bt, "jq_prelude").unwrap_or_default();
+        let mut jq = Jq::with_prelude(&filter, &prelude, inputs, args)?;
+        jq.outputs = outputs.to_vec();
+        jq.max_results = match get_config_value::<usize>(bt, "max_results") {
+            Some(0) => return Err("'max_results' must be a positive integer".into()),
+            Some(n) => n,
+            None => DEFAULT_MAX_RESULTS,
+        };
+        jq.raw_output = get_config_value(bt, "raw_output").unwrap_or(false);
 
         Ok(Box::new(Rc::new(jq)))
     }
 
-    fn new_node(&self, config: &dyn NodeConfig) -> Box<dyn Node> {
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
         match config.as_any().downcast_ref::<Rc<Jq>>() {
-            Some(jq) => Box::new(jq.clone()),
-            None => panic!("incompatible NodeConfig"),
+            Some(jq) => Ok(Box::new(jq.clone())),
+            None => Err("incompatible NodeConfig".into()),
         }
     }
 }
@@ -237,7 +412,11 @@ mod test {
 
     #[test]
     fn filter_sanity() {
-        let jq = Jq::new("{ a: $a, b: $b }", vec!["a".to_string(), "b".to_string()]);
+        let jq = Jq::new(
+            "{ a: $a, b: $b }",
+            vec!["a".to_string(), "b".to_string()],
+            BTreeMap::new(),
+        );
 
         let Ok(jq) = jq else {
             panic!("jq error");
@@ -252,7 +431,7 @@ mod test {
 
         let inputs = vec![Some(&a), Some(&b)];
 
-        let res = jq.exec(inputs.as_slice());
+        let res = jq.exec(inputs.as_slice(), None);
 
         let Ok(results) = res else {
             panic!("unexpected jq error");
@@ -272,7 +451,7 @@ mod test {
 
     #[test]
     fn invalid_filter_text() {
-        let jq = Jq::new("nope!", Vec::new());
+        let jq = Jq::new("nope!", Vec::new(), BTreeMap::new());
 
         let Err(e) = jq else {
             panic!("expected invalid filter to result in an error");
@@ -283,7 +462,7 @@ mod test {
 
     #[test]
     fn empty_filter() {
-        let jq = Jq::new("", vec![]);
+        let jq = Jq::new("", vec![], BTreeMap::new());
 
         let Err(e) = jq else {
             panic!("expected invalid filter to result in an error");
@@ -294,9 +473,9 @@ mod test {
 
     #[test]
     fn filter_errors() {
-        let jq = Jq::new("error(\"woops\")", vec![]).unwrap();
+        let jq = Jq::new("error(\"woops\")", vec![], BTreeMap::new()).unwrap();
 
-        let res = jq.exec(&[]);
+        let res = jq.exec(&[], None);
         let Err(errs) = res else {
             panic!("expected a failure");
         };
@@ -306,9 +485,9 @@ mod test {
 
     #[test]
     fn invalid_number_of_inputs() {
-        let jq = Jq::new("$foo", vec!["foo".to_string()]).unwrap();
+        let jq = Jq::new("$foo", vec!["foo".to_string()], BTreeMap::new()).unwrap();
 
-        let res = jq.exec(&[]);
+        let res = jq.exec(&[], None);
         let Err(errs) = res else {
             panic!("expected a failure");
         };
@@ -318,4 +497,210 @@ mod test {
             vec!["invalid number of inputs, expected: 1, got: 0"]
         );
     }
+
+    #[test]
+    fn filter_args() {
+        let args = BTreeMap::from([
+            ("flag".to_string(), json!(true)),
+            ("limits".to_string(), json!({ "max": 10 })),
+        ]);
+        let jq = Jq::new(
+            "{ a: $a, flag: $flag, max: $limits.max }",
+            vec!["a".to_string()],
+            args,
+        )
+        .unwrap();
+
+        let a = Payload::Json(json!("hello"));
+        let results = jq.exec(&[Some(&a)], None).ok().unwrap();
+
+        assert_eq!(
+            results,
+            vec![json!({ "a": "hello", "flag": true, "max": 10 })]
+        );
+    }
+
+    #[test]
+    fn filter_number_precision() {
+        let jq = Jq::new(
+            "{ id: $a.id, amount: $a.amount }",
+            vec!["a".into()],
+            BTreeMap::new(),
+        )
+        .unwrap();
+
+        let body = br#"{"amount":12.345678901234567890,"id":9007199254740993}"#;
+        let a = Payload::from_bytes(body.to_vec(), Some("application/json")).unwrap();
+        let results = jq.exec(&[Some(&a)], None).ok().unwrap();
+
+        let output = serde_json::to_vec(&results[0]).unwrap();
+        assert_eq!(body.to_vec(), output);
+    }
+
+    #[test]
+    fn filter_phase() {
+        let jq = Jq::new("$phase", vec![], BTreeMap::new()).unwrap();
+        let results = jq.exec(&[], Some(Phase::HttpResponseBody)).ok().unwrap();
+        assert_eq!(results, vec![json!("response_body")]);
+
+        let results = jq.exec(&[], None).ok().unwrap();
+        assert_eq!(results, vec![JsonValue::Null]);
+
+        // an input port named `phase` takes precedence
+        let jq = Jq::new("$phase", vec!["phase".to_string()], BTreeMap::new()).unwrap();
+        let a = Payload::Json(json!("mine"));
+        let results = jq.exec(&[Some(&a)], Some(Phase::Tick)).ok().unwrap();
+        assert_eq!(results, vec![json!("mine")]);
+    }
+
+    #[test]
+    fn filter_max_results() {
+        let bt = BTreeMap::from([
+            ("jq".to_string(), json!("range(1000000000)")),
+            ("max_results".to_string(), json!(100)),
+        ]);
+        let config = JqFactory {}.new_config("JQ", &[], &[], &bt).unwrap();
+        let jq = config.as_any().downcast_ref::<Rc<Jq>>().unwrap();
+
+        let Err(errs) = jq.exec(&[], None) else {
+            panic!("expected a failure");
+        };
+        assert_eq!(
+            errs.into_inner(),
+            vec!["jq: program stopped after 100 results"]
+        );
+
+        let jq = Jq::new("range(100)", vec![], BTreeMap::new()).unwrap();
+        assert_eq!(100, jq.exec(&[], None).ok().unwrap().len());
+
+        let bt = BTreeMap::from([("max_results".to_string(), json!(0))]);
+        assert!(JqFactory {}.new_config("JQ", &[], &[], &bt).is_err());
+    }
+
+    #[test]
+    fn route_named_outputs() {
+        let mut jq = Jq::new(".", vec![], BTreeMap::new()).unwrap();
+        jq.outputs = vec!["user".to_string(), "count".to_string(), "rest".to_string()];
+
+        assert_eq!(
+            vec![
+                Some(Payload::Json(json!({ "id": 1 }))),
+                Some(Payload::Json(json!(2))),
+                None
+            ],
+            jq.route(vec![json!({ "count": 2, "user": { "id": 1 } })])
+        );
+
+        // not all keys are port names: the object goes to the first port
+        assert_eq!(
+            vec![
+                Some(Payload::Json(json!({ "user": 1, "other": 2 }))),
+                None,
+                None
+            ],
+            jq.route(vec![json!({ "user": 1, "other": 2 })])
+        );
+
+        // multiple results are still routed by position
+        assert_eq!(
+            vec![
+                Some(Payload::Json(json!(1))),
+                Some(Payload::Json(json!(2))),
+                None
+            ],
+            jq.route(vec![json!(1), json!(2)])
+        );
+    }
+
+    #[test]
+    fn route_raw_output() {
+        let mut jq = Jq::new(".", vec![], BTreeMap::new()).unwrap();
+        jq.outputs = vec!["text".to_string(), "other".to_string()];
+        jq.raw_output = true;
+
+        assert_eq!(
+            vec![
+                Some(Payload::Raw(b"hello \"world\"".to_vec())),
+                Some(Payload::Raw(b"42".to_vec()))
+            ],
+            jq.route(vec![json!("hello \"world\""), json!(42)])
+        );
+        assert_eq!(
+            vec![
+                Some(Payload::Raw(br#"{"a":[1,null]}"#.to_vec())),
+                Some(Payload::Raw(b"true".to_vec()))
+            ],
+            jq.route(vec![json!({ "a": [1, null] }), json!(true)])
+        );
+
+        // values routed by name are raw as well
+        assert_eq!(
+            vec![Some(Payload::Raw(b"hi".to_vec())), None],
+            jq.route(vec![json!({ "text": "hi" })])
+        );
+    }
+
+    #[test]
+    fn route_single_output() {
+        let mut jq = Jq::new(".", vec![], BTreeMap::new()).unwrap();
+        jq.outputs = vec!["user".to_string()];
+
+        assert_eq!(
+            vec![Some(Payload::Json(json!({ "user": 1 })))],
+            jq.route(vec![json!({ "user": 1 })])
+        );
+        assert_eq!(vec![None], jq.route(vec![]));
+
+        // extra values have no port to go to
+        assert_eq!(
+            vec![Some(Payload::Json(json!(1)))],
+            jq.route(vec![json!(1), json!(2)])
+        );
+    }
+
+    #[test]
+    fn filter_prelude() {
+        let prelude = "def double: . * 2; def greet($name): \"hello \\($name)\";";
+        assert!(check_prelude(prelude).is_ok());
+
+        let jq = Jq::with_prelude(
+            "[($n | double), greet($who)]",
+            prelude,
+            vec!["n".to_string(), "who".to_string()],
+            BTreeMap::new(),
+        )
+        .unwrap();
+
+        let n = Payload::Json(json!(21));
+        let who = Payload::Json(json!("ann"));
+        let Ok(results) = jq.exec(&[Some(&n), Some(&who)], None) else {
+            panic!("unexpected jq error");
+        };
+        assert_eq!(results, vec![json!([42, "hello ann"])]);
+    }
+
+    #[test]
+    fn filter_prelude_invalid() {
+        assert!(check_prelude("def broken: ;")
+            .unwrap_err()
+            .starts_with("invalid jq_prelude: "));
+        assert!(check_prelude("def f: nope;")
+            .unwrap_err()
+            .starts_with("jq_prelude compilation failed: "));
+
+        // input port variables are not in scope for the prelude
+        assert!(check_prelude("def user: $user;").is_err());
+    }
+
+    #[test]
+    fn filter_args_collision() {
+        let args = BTreeMap::from([("a".to_string(), json!(1))]);
+        let jq = Jq::new("$a", vec!["a".to_string()], args);
+
+        let Err(e) = jq else {
+            panic!("expected colliding names to result in an error");
+        };
+
+        assert_eq!("argument `$a` collides with an input port variable", e);
+    }
 }
@@ -0,0 +1,187 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use serde_json_path::JsonPath as Path;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Debug)]
+pub struct JsonPathConfig {
+    path: Path,
+    required: bool,
+}
+
+impl NodeConfig for JsonPathConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct JsonPath {
+    config: JsonPathConfig,
+}
+
+impl JsonPath {
+    fn extract(&self, input: &Payload) -> Result<Payload, String> {
+        let parsed;
+        let value = match input {
+            Payload::Json(value) => value,
+            Payload::Raw(bytes) => {
+                parsed = serde_json::from_slice(bytes)
+                    .map_err(|e| format!("jsonpath: input is not JSON: {e}"))?;
+                &parsed
+            }
+            Payload::Error(_, e) => return Err(e.clone()),
+        };
+
+        let mut matches = self.config.path.query(value).all();
+        let output = match matches.len() {
+            0 if self.config.required => return Err("jsonpath: no match for path".into()),
+            0 => Value::Null,
+            1 => matches.remove(0).clone(),
+            _ => Value::Array(matches.into_iter().cloned().collect()),
+        };
+
+        Ok(Payload::Json(output))
+    }
+}
+
+impl Node for JsonPath {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        match input.get(0) {
+            Some(payload) => match self.extract(payload) {
+                Ok(payload) => Done(vec![Some(payload)]),
+                Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+            },
+            None => Done(vec![None]),
+        }
+    }
+}
+
+pub struct JsonPathFactory {}
+
+impl NodeFactory for JsonPathFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let Some(path) = get_config_value::<String>(bt, "path") else {
+            return Err("jsonpath: missing 'path'".into());
+        };
+        let path = Path::parse(&path).map_err(|e| format!("jsonpath: invalid 'path': {e}"))?;
+
+        Ok(Box::new(JsonPathConfig {
+            path,
+            required: get_config_value(bt, "required").unwrap_or(false),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<JsonPathConfig>() {
+            Some(jc) => Ok(Box::new(JsonPath { config: jc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn extract(path: &str, required: bool, input: Payload) -> Result<Payload, String> {
+        let node = JsonPath {
+            config: JsonPathConfig {
+                path: Path::parse(path).unwrap(),
+                required,
+            },
+        };
+        node.extract(&input)
+    }
+
+    fn store() -> Payload {
+        Payload::Json(json!({
+            "books": [
+                { "title": "Dune", "price": 10 },
+                { "title": "Emma", "price": 8 }
+            ],
+            "owner": { "name": "Ann" }
+        }))
+    }
+
+    #[test]
+    fn jsonpath_single_match() {
+        assert_eq!(
+            Ok(Payload::Json(json!("Ann"))),
+            extract("$.owner.name", false, store())
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!({ "title": "Emma", "price": 8 }))),
+            extract("$.books[1]", false, store())
+        );
+    }
+
+    #[test]
+    fn jsonpath_multiple_matches() {
+        assert_eq!(
+            Ok(Payload::Json(json!(["Dune", "Emma"]))),
+            extract("$.books[*].title", false, store())
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!([10, 8]))),
+            extract("$..price", false, store())
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!("Emma"))),
+            extract("$.books[?@.price < 9].title", false, store())
+        );
+    }
+
+    #[test]
+    fn jsonpath_no_match() {
+        assert_eq!(
+            Ok(Payload::Json(Value::Null)),
+            extract("$.missing", false, store())
+        );
+        assert_eq!(
+            Err("jsonpath: no match for path".to_string()),
+            extract("$.missing", true, store())
+        );
+    }
+
+    #[test]
+    fn jsonpath_raw_input() {
+        assert_eq!(
+            Ok(Payload::Json(json!(1))),
+            extract("$.a", false, Payload::Raw(br#"{"a": 1}"#.to_vec()))
+        );
+        assert!(extract("$.a", false, Payload::Raw(b"nope".to_vec())).is_err());
+    }
+
+    #[test]
+    fn jsonpath_invalid_path() {
+        let bt = BTreeMap::from([("path".to_string(), json!("$.["))]);
+        assert!(JsonPathFactory {}.new_config("J", &[], &[], &bt).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::Payload;
+
+const ELLIPSIS: &str = "...";
+
+#[derive(Clone, Debug)]
+pub struct LimitConfig {
+    max: usize,
+    ellipsis: bool,
+}
+
+impl NodeConfig for LimitConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Limit {
+    config: LimitConfig,
+}
+
+impl Limit {
+    fn truncate_str(&self, s: &str) -> String {
+        match s.char_indices().nth(self.config.max) {
+            Some((end, _)) if self.config.ellipsis => s[..end].to_string() + ELLIPSIS,
+            Some((end, _)) => s[..end].to_string(),
+            None => s.to_string(),
+        }
+    }
+
+    fn truncate(&self, payload: &Payload) -> Payload {
+        let max = self.config.max;
+
+        match payload {
+            Payload::Json(Value::Array(vs)) => {
+                Payload::Json(Value::Array(vs.iter().take(max).cloned().collect()))
+            }
+            Payload::Json(Value::String(s)) => Payload::Json(Value::String(self.truncate_str(s))),
+            Payload::Raw(bytes) if bytes.len() > max => {
+                let mut bytes = bytes[..max].to_vec();
+                if self.config.ellipsis {
+                    bytes.extend_from_slice(ELLIPSIS.as_bytes());
+                }
+                Payload::Raw(bytes)
+            }
+            _ => payload.clone(),
+        }
+    }
+}
+
+impl Node for Limit {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        match input.get(0) {
+            Some(p @ Payload::Error(..)) => Fail(vec![Some((*p).clone())]),
+            Some(payload) => Done(vec![Some(self.truncate(payload))]),
+            None => Done(vec![None]),
+        }
+    }
+}
+
+pub struct LimitFactory {}
+
+impl NodeFactory for LimitFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let Some(max) = get_config_value::<usize>(bt, "max") else {
+            return Err("limit: 'max' is a required non-negative integer attribute".into());
+        };
+
+        Ok(Box::new(LimitConfig {
+            max,
+            ellipsis: get_config_value(bt, "ellipsis").unwrap_or(false),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<LimitConfig>() {
+            Some(cc) => Ok(Box::new(Limit { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn limit(max: usize, ellipsis: bool, payload: Payload) -> Payload {
+        let node = Limit {
+            config: LimitConfig { max, ellipsis },
+        };
+        node.truncate(&payload)
+    }
+
+    #[test]
+    fn limit_array() {
+        assert_eq!(
+            Payload::Json(json!([1, 2])),
+            limit(2, true, Payload::Json(json!([1, 2, 3, 4])))
+        );
+        assert_eq!(
+            Payload::Json(json!([1])),
+            limit(5, false, Payload::Json(json!([1])))
+        );
+    }
+
+    #[test]
+    fn limit_string() {
+        assert_eq!(
+            Payload::Json(json!("héll")),
+            limit(4, false, Payload::Json(json!("héllo")))
+        );
+        assert_eq!(
+            Payload::Json(json!("hé...")),
+            limit(2, true, Payload::Json(json!("héllo")))
+        );
+        assert_eq!(
+            Payload::Json(json!("héllo")),
+            limit(5, true, Payload::Json(json!("héllo")))
+        );
+    }
+
+    #[test]
+    fn limit_raw() {
+        assert_eq!(
+            Payload::Raw(b"abc...".to_vec()),
+            limit(3, true, Payload::Raw(b"abcdef".to_vec()))
+        );
+        assert_eq!(
+            Payload::Raw(b"ab".to_vec()),
+            limit(3, false, Payload::Raw(b"ab".to_vec()))
+        );
+    }
+
+    #[test]
+    fn limit_passthrough() {
+        let obj = Payload::Json(json!({ "a": 1, "b": 2 }));
+        assert_eq!(obj, limit(1, false, obj.clone()));
+    }
+}
@@ -0,0 +1,186 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PatchFormat {
+    /// RFC 6902
+    JsonPatch,
+    /// RFC 7386
+    MergePatch,
+}
+
+#[derive(Clone, Debug)]
+pub struct PatchConfig {
+    format: PatchFormat,
+    patch: Option<Value>,
+}
+
+impl NodeConfig for PatchConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Patch {
+    config: PatchConfig,
+}
+
+fn fail(kind: ErrorKind, msg: String) -> State {
+    Fail(vec![Some(Payload::Error(kind, msg))])
+}
+
+fn apply(format: PatchFormat, doc: &mut Value, patch: &Value) -> Result<(), String> {
+    match format {
+        PatchFormat::JsonPatch => {
+            let ops: json_patch::Patch = serde_json::from_value(patch.clone())
+                .map_err(|e| format!("patch: invalid JSON Patch document: {e}"))?;
+
+            json_patch::patch(doc, &ops).map_err(|e| {
+                format!(
+                    "patch: operation {} at '{}' failed: {}",
+                    e.operation, e.path, e.kind
+                )
+            })
+        }
+        PatchFormat::MergePatch => {
+            json_patch::merge(doc, patch);
+            Ok(())
+        }
+    }
+}
+
+impl Node for Patch {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        let doc = input.get(0);
+        let patch = input.get(1);
+
+        let mut doc = match doc {
+            Some(payload) => match payload.to_json() {
+                Ok(value) => value,
+                Err(e) => return fail(ErrorKind::ParseError, e),
+            },
+            None => Value::Null,
+        };
+
+        let patch = match (patch, &self.config.patch) {
+            (Some(payload), _) => match payload.to_json() {
+                Ok(value) => value,
+                Err(e) => return fail(ErrorKind::ParseError, e),
+            },
+            (None, Some(value)) => value.clone(),
+            (None, None) => {
+                return fail(
+                    ErrorKind::ConfigError,
+                    "patch: no patch document given".into(),
+                )
+            }
+        };
+
+        match apply(self.config.format, &mut doc, &patch) {
+            Ok(()) => Done(vec![Some(Payload::Json(doc))]),
+            Err(e) => fail(ErrorKind::ConfigError, e),
+        }
+    }
+}
+
+pub struct PatchFactory {}
+
+impl NodeFactory for PatchFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value", "patch"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let format = match get_config_value::<String>(bt, "format").as_deref() {
+            None | Some("json_patch") => PatchFormat::JsonPatch,
+            Some("merge_patch") => PatchFormat::MergePatch,
+            Some(other) => {
+                return Err(format!(
+                    "patch: invalid 'format' {other}, expected json_patch or merge_patch"
+                ))
+            }
+        };
+
+        let patch = bt.get("patch").cloned();
+        if let (PatchFormat::JsonPatch, Some(value)) = (format, &patch) {
+            if let Err(e) = serde_json::from_value::<json_patch::Patch>(value.clone()) {
+                return Err(format!("patch: invalid JSON Patch document: {e}"));
+            }
+        }
+
+        Ok(Box::new(PatchConfig { format, patch }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<PatchConfig>() {
+            Some(cc) => Ok(Box::new(Patch { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn json_patch() {
+        let mut doc = json!({ "a": 1, "b": [1, 2] });
+        let patch = json!([
+            { "op": "replace", "path": "/a", "value": 2 },
+            { "op": "add", "path": "/b/-", "value": 3 },
+            { "op": "remove", "path": "/b/0" },
+        ]);
+
+        apply(PatchFormat::JsonPatch, &mut doc, &patch).unwrap();
+        assert_eq!(json!({ "a": 2, "b": [2, 3] }), doc);
+    }
+
+    #[test]
+    fn json_patch_failed_test() {
+        let mut doc = json!({ "a": 1 });
+        let patch = json!([
+            { "op": "add", "path": "/b", "value": 2 },
+            { "op": "test", "path": "/a", "value": 3 },
+        ]);
+
+        let err = apply(PatchFormat::JsonPatch, &mut doc, &patch).unwrap_err();
+        assert!(
+            err.starts_with("patch: operation 1 at '/a' failed"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn merge_patch() {
+        let mut doc = json!({ "a": 1, "b": { "c": 2, "d": 3 } });
+        let patch = json!({ "a": null, "b": { "c": 4 } });
+
+        apply(PatchFormat::MergePatch, &mut doc, &patch).unwrap();
+        assert_eq!(json!({ "b": { "c": 4, "d": 3 } }), doc);
+    }
+}
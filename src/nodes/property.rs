@@ -6,11 +6,66 @@ use std::collections::BTreeMap;
 use crate::config::get_config_value;
 use crate::data::{Input, State, State::*};
 use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
-use crate::payload::Payload;
+use crate::payload::{ErrorKind, Payload};
+
+/// Friendly names for commonly used properties,
+/// which can be used in place of their full paths.
+const PRESETS: &[(&str, &[&str])] = &[
+    ("route_id", &["kong", "route_id"]),
+    ("route_name", &["kong", "route_name"]),
+    ("service_id", &["kong", "service_id"]),
+    ("service_name", &["kong", "service_name"]),
+    ("request_id", &["ngx", "kong_request_id"]),
+    ("client_ip", &["ngx", "remote_addr"]),
+    ("service_target", &["kong", "service", "target"]),
+    ("service_upstream", &["kong", "service", "upstream"]),
+    ("service_scheme", &["kong", "service", "request", "scheme"]),
+];
+
+/// Properties known to be read-only, along with everything under them.
+/// Properties not listed here are not checked.
+const READ_ONLY: &[&[&str]] = &[
+    &["kong", "route_id"],
+    &["kong", "route_name"],
+    &["kong", "service_id"],
+    &["kong", "service_name"],
+    &["kong", "version"],
+    &["kong", "node", "id"],
+    &["kong", "client", "protocol"],
+    &["kong", "nginx", "subsystem"],
+    &["kong", "configuration"],
+    &["ngx", "kong_request_id"],
+    &["ngx", "remote_addr"],
+];
+
+fn resolve_path(name: &str) -> Vec<String> {
+    match PRESETS.iter().find(|(preset, _)| *preset == name) {
+        Some((_, path)) => path.iter().map(|s| s.to_string()).collect(),
+        None => name.split('.').map(|s| s.to_string()).collect(),
+    }
+}
+
+fn is_read_only(path: &[String]) -> bool {
+    READ_ONLY.iter().any(|read_only| {
+        path.len() >= read_only.len() && path.iter().zip(read_only.iter()).all(|(a, b)| a == b)
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PropertyMode {
+    /// Set if an input value is given, get otherwise.
+    Auto,
+    Get,
+    Set,
+    Delete,
+}
 
 #[derive(Clone, Debug)]
 pub struct PropertyConfig {
+    mode: PropertyMode,
     path: Vec<String>,
+    /// Output keys and paths, when reading multiple properties at once.
+    properties: BTreeMap<String, Vec<String>>,
     content_type: Option<String>,
 }
 
@@ -21,11 +76,32 @@ impl PropertyConfig {
         Option<CT>: Into<Option<String>>,
     {
         Self {
-            path: name.as_ref().split('.').map(|s| s.to_string()).collect(),
+            mode: PropertyMode::Auto,
+            path: resolve_path(name.as_ref()),
+            properties: BTreeMap::new(),
             content_type: ct.into(),
         }
     }
 
+    fn new_multiple<CT>(properties: BTreeMap<String, String>, ct: Option<CT>) -> Self
+    where
+        Option<CT>: Into<Option<String>>,
+    {
+        Self {
+            mode: PropertyMode::Auto,
+            path: vec![],
+            properties: properties
+                .into_iter()
+                .map(|(key, name)| (key, resolve_path(&name)))
+                .collect(),
+            content_type: ct.into(),
+        }
+    }
+
+    fn with_mode(self, mode: PropertyMode) -> Self {
+        Self { mode, ..self }
+    }
+
     fn to_path(&self) -> Vec<&str> {
         self.path.iter().map(String::as_str).collect()
     }
@@ -35,6 +111,10 @@ impl NodeConfig for PropertyConfig {
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn sink_outputs(&self) -> bool {
+        matches!(self.mode, PropertyMode::Set | PropertyMode::Delete)
+    }
 }
 
 #[derive(Debug)]
@@ -64,12 +144,20 @@ impl Property {
         match payload.to_bytes(content_type) {
             Ok(bytes) => {
                 ctx.set_property(self.config.to_path(), Some(bytes.as_slice()));
-                Done(vec![None])
+                Done(vec![None, None])
             }
-            Err(e) => Fail(vec![Some(Payload::Error(e))]),
+            Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
         }
     }
 
+    fn delete(&self, ctx: &dyn HttpContext) -> State {
+        #[cfg(debug_assertions)]
+        log::debug!("DELETE property {:?}", self.config.path);
+
+        ctx.set_property(self.config.to_path(), None);
+        Done(vec![None, None])
+    }
+
     fn get(&self, ctx: &dyn HttpContext) -> State {
         let content_type = self.config.content_type.as_deref();
 
@@ -80,26 +168,86 @@ impl Property {
                 #[cfg(debug_assertions)]
                 log::debug!("GET property {:?} => {:?}", &self.config.path, payload);
 
-                vec![payload]
+                vec![payload, Some(Payload::Json(true.into()))]
             }
             None => {
                 #[cfg(debug_assertions)]
                 log::debug!("GET property {:?} => None", &self.config.path);
 
-                vec![Some(Payload::json_null())]
+                vec![
+                    Some(Payload::json_null()),
+                    Some(Payload::Json(false.into())),
+                ]
             }
         })
     }
+
+    fn get_multiple(&self, ctx: &dyn HttpContext) -> State {
+        let content_type = self.config.content_type.as_deref();
+
+        let mut map = serde_json::Map::new();
+        let mut all_exist = true;
+
+        for (key, path) in &self.config.properties {
+            let path: Vec<&str> = path.iter().map(String::as_str).collect();
+
+            let value = match ctx.get_property(path) {
+                Some(bytes) => match Payload::from_bytes(bytes, content_type) {
+                    Some(payload) => match payload.to_json() {
+                        Ok(value) => value,
+                        Err(e) => {
+                            let e = format!("property: error reading '{key}': {e}");
+                            return Done(vec![
+                                Some(Payload::Error(ErrorKind::ParseError, e)),
+                                None,
+                            ]);
+                        }
+                    },
+                    None => Value::Null,
+                },
+                None => {
+                    all_exist = false;
+                    Value::Null
+                }
+            };
+
+            map.insert(key.clone(), value);
+        }
+
+        #[cfg(debug_assertions)]
+        log::debug!("GET properties {:?} => {:?}", &self.config.properties, map);
+
+        Done(vec![
+            Some(Payload::Json(Value::Object(map))),
+            Some(Payload::Json(all_exist.into())),
+        ])
+    }
 }
 
 impl Node for Property {
     fn run(&self, ctx: &dyn HttpContext, input: &Input) -> State {
-        // set the property if we have an input
-        if let Some(Some(payload)) = input.data.first() {
-            return self.set(ctx, payload);
+        let multiple = !self.config.properties.is_empty();
+        let payload = input.get(0);
+
+        match (self.config.mode, payload) {
+            (PropertyMode::Delete, _) => self.delete(ctx),
+            // nothing to set
+            (PropertyMode::Set, None) => Done(vec![None, None]),
+            (PropertyMode::Set | PropertyMode::Auto, Some(payload)) => {
+                if multiple {
+                    let e = "property: cannot set multiple properties at once".to_string();
+                    return Fail(vec![Some(Payload::Error(ErrorKind::ConfigError, e))]);
+                }
+                if is_read_only(&self.config.path) {
+                    let path = self.config.path.join(".");
+                    let e = format!("property: `{path}` is read-only");
+                    return Fail(vec![Some(Payload::Error(ErrorKind::ConfigError, e))]);
+                }
+                self.set(ctx, payload)
+            }
+            _ if multiple => self.get_multiple(ctx),
+            _ => self.get(ctx),
         }
-
-        self.get(ctx)
     }
 }
 
@@ -114,7 +262,7 @@ impl NodeFactory for PropertyFactory {
     }
     fn default_output_ports(&self) -> PortConfig {
         PortConfig {
-            defaults: Some(PortConfig::names(&["value"])),
+            defaults: Some(PortConfig::names(&["value", "exists"])),
             user_defined_ports: false,
         }
     }
@@ -126,23 +274,60 @@ impl NodeFactory for PropertyFactory {
         _outputs: &[String],
         bt: &BTreeMap<String, Value>,
     ) -> Result<Box<dyn NodeConfig>, String> {
-        Ok(Box::new(PropertyConfig::new(
-            get_config_value::<String>(bt, "property")
-                .ok_or_else(|| "Missing `property` attribute".to_owned())?,
-            get_config_value::<String>(bt, "content_type"),
-        )))
+        let content_type = get_config_value::<String>(bt, "content_type");
+
+        let mode = match get_config_value::<String>(bt, "mode").as_deref() {
+            None => PropertyMode::Auto,
+            Some("get") => PropertyMode::Get,
+            Some("set") => PropertyMode::Set,
+            Some("delete") => PropertyMode::Delete,
+            Some(other) => {
+                return Err(format!(
+                    "invalid `mode` {other}, expected get, set or delete"
+                ))
+            }
+        };
+
+        if bt.contains_key("properties") {
+            if bt.contains_key("property") {
+                return Err("`property` and `properties` attributes are mutually exclusive".into());
+            }
+            if matches!(mode, PropertyMode::Set | PropertyMode::Delete) {
+                return Err("`properties` can only be used to get properties".into());
+            }
+
+            let properties = get_config_value::<BTreeMap<String, String>>(bt, "properties")
+                .filter(|properties| !properties.is_empty())
+                .ok_or_else(|| "`properties` must be a non-empty map of properties".to_owned())?;
+
+            return Ok(Box::new(
+                PropertyConfig::new_multiple(properties, content_type).with_mode(mode),
+            ));
+        }
+
+        let name = get_config_value::<String>(bt, "property")
+            .ok_or_else(|| "Missing `property` attribute".to_owned())?;
+        let config = PropertyConfig::new(&name, content_type).with_mode(mode);
+
+        if matches!(mode, PropertyMode::Set | PropertyMode::Delete) && is_read_only(&config.path) {
+            let path = config.path.join(".");
+            return Err(format!("property `{name}` ({path}) is read-only"));
+        }
+
+        Ok(Box::new(config))
     }
 
-    fn new_node(&self, config: &dyn NodeConfig) -> Box<dyn Node> {
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
         match config.as_any().downcast_ref::<PropertyConfig>() {
-            Some(cc) => Box::new(Property::from(cc)),
-            None => panic!("incompatible NodeConfig"),
+            Some(cc) => Ok(Box::new(Property::from(cc))),
+            None => Err("incompatible NodeConfig".into()),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::data::InputPort;
     use crate::payload::JSON_CONTENT_TYPE;
     use mock_proxy_wasm::*;
     use proxy_wasm::types::Bytes;
@@ -203,7 +388,7 @@ mod test {
     macro_rules! input {
         ($v:expr) => {
             Input {
-                data: &[$v],
+                data: &[$v.map_or(InputPort::Unconnected, InputPort::Value)],
                 phase: crate::data::Phase::HttpRequestHeaders,
             }
         };
@@ -216,11 +401,11 @@ mod test {
     }
 
     macro_rules! done {
-        ($v:expr) => {
-            State::Done(vec![$v])
+        ($v:expr, $exists:expr) => {
+            State::Done(vec![$v, Some(Payload::Json($exists.into()))])
         };
         () => {
-            State::Done(vec![None])
+            State::Done(vec![None, None])
         };
     }
 
@@ -240,6 +425,9 @@ mod test {
         ($name:expr) => {
             Property::from(PropertyConfig::new($name, None as Option<String>))
         };
+        ($name:expr, mode = $mode:expr) => {
+            Property::from(PropertyConfig::new($name, None as Option<String>).with_mode($mode))
+        };
         ($name:expr, $ct:expr) => {
             Property::from(PropertyConfig::new($name, Some($ct.into())))
         };
@@ -257,7 +445,20 @@ mod test {
         let input = input!();
 
         let state = run!(&node, &ctx, &input);
-        assert_eq!(done!(Some(Payload::Raw(value.into()))), state);
+        assert_eq!(done!(Some(Payload::from(value)), true), state);
+    }
+
+    #[test]
+    fn get_property_preset() {
+        let value = "my-route";
+
+        let ctx = Mock::new();
+        ctx.set("kong.route_name", value);
+
+        let node = node!("route_name");
+
+        let state = run!(&node, &ctx, &input!());
+        assert_eq!(done!(Some(Payload::from(value)), true), state);
     }
 
     #[test]
@@ -267,7 +468,18 @@ mod test {
         let node = node!("test.property");
 
         let state = run!(&node, &ctx, &input!());
-        assert_eq!(done!(Some(Payload::json_null())), state);
+        assert_eq!(done!(Some(Payload::json_null()), false), state);
+    }
+
+    #[test]
+    fn get_property_null() {
+        let ctx = Mock::new();
+        ctx.set("test.property", "null");
+
+        let node = node!("test.property", JSON_CONTENT_TYPE);
+
+        let state = run!(&node, &ctx, &input!());
+        assert_eq!(done!(Some(Payload::json_null()), true), state);
     }
 
     #[test]
@@ -284,7 +496,7 @@ mod test {
         let node = node!(property, JSON_CONTENT_TYPE);
 
         let state = run!(&node, &ctx, &input!());
-        assert_eq!(done!(Some(payload)), state);
+        assert_eq!(done!(Some(payload), true), state);
     }
 
     #[test]
@@ -302,13 +514,55 @@ mod test {
             panic!("expected State::Done(...)");
         };
 
-        assert_eq!(1, payloads.len());
+        assert_eq!(2, payloads.len());
 
-        let Some(&Some(Payload::Error(_))) = payloads.first() else {
+        let Some(&Some(Payload::Error(..))) = payloads.first() else {
             panic!("expected Payload::Error(...)");
         };
     }
 
+    #[test]
+    fn get_properties() {
+        let ctx = Mock::new();
+        ctx.set("request.method", "GET");
+        ctx.set("ngx.remote_addr", "127.0.0.1");
+
+        let properties = BTreeMap::from([
+            ("method".to_string(), "request.method".to_string()),
+            ("ip".to_string(), "client_ip".to_string()),
+            ("latency".to_string(), "kong.latency".to_string()),
+        ]);
+        let node = Property::from(PropertyConfig::new_multiple(
+            properties,
+            None as Option<String>,
+        ));
+
+        let state = run!(&node, &ctx, &input!());
+        let expected = serde_json::json!({
+            "method": "GET",
+            "ip": "127.0.0.1",
+            "latency": null,
+        });
+        assert_eq!(done!(Some(expected.into()), false), state);
+    }
+
+    #[test]
+    fn set_properties() {
+        let ctx = Mock::new();
+
+        let properties = BTreeMap::from([("a".to_string(), "test.a".to_string())]);
+        let node = Property::from(PropertyConfig::new_multiple(
+            properties,
+            None as Option<String>,
+        ));
+
+        let payload = Payload::from("value");
+        let state = run!(&node, &ctx, &input!(Some(&payload)));
+
+        assert!(matches!(state, State::Fail(_)));
+        assert_eq!(None, ctx.get("test.a"));
+    }
+
     #[test]
     fn set_property() {
         let property = "test.property";
@@ -316,7 +570,7 @@ mod test {
         let ctx = Mock::new();
 
         let node = node!(property);
-        let payload = Payload::Raw(value.into());
+        let payload = Payload::from(value);
         let input = input!(Some(&payload));
 
         let state = run!(&node, &ctx, &input);
@@ -356,9 +610,9 @@ mod test {
             panic!("expected State::Done(...)");
         };
 
-        assert_eq!(1, payloads.len());
+        assert_eq!(2, payloads.len());
 
-        let Some(&Some(Payload::Error(_))) = payloads.first() else {
+        let Some(&Some(Payload::Error(..))) = payloads.first() else {
             panic!("expected Payload::Error(...)");
         };
     }
@@ -447,7 +701,7 @@ mod test {
         let ctx = Mock::new();
         ctx.set(property, old);
 
-        let payload = Payload::Raw(new.into());
+        let payload = Payload::from(new);
 
         let node = node!(property);
         let input = input!(Some(&payload));
@@ -457,6 +711,90 @@ mod test {
         assert_eq!(Some(new.into()), ctx.get(property));
     }
 
+    #[test]
+    fn get_mode_ignores_input() {
+        let property = "test.property";
+
+        let ctx = Mock::new();
+        ctx.set(property, "old value");
+
+        let node = node!(property, mode = PropertyMode::Get);
+        let payload = Payload::from("new value");
+
+        let state = run!(&node, &ctx, &input!(Some(&payload)));
+        assert_eq!(done!(Some(Payload::from("old value")), true), state);
+        assert_eq!(Some("old value".into()), ctx.get(property));
+    }
+
+    #[test]
+    fn set_mode_without_input() {
+        let property = "test.property";
+
+        let ctx = Mock::new();
+        ctx.set(property, "old value");
+
+        let node = node!(property, mode = PropertyMode::Set);
+
+        let state = run!(&node, &ctx, &input!(None));
+        assert_eq!(done!(), state);
+        assert_eq!(Some("old value".into()), ctx.get(property));
+    }
+
+    #[test]
+    fn delete_property() {
+        let property = "test.property";
+
+        let ctx = Mock::new();
+        ctx.set(property, "old value");
+
+        let node = node!(property, mode = PropertyMode::Delete);
+
+        let state = run!(&node, &ctx, &input!());
+        assert_eq!(done!(), state);
+        assert_eq!(None, ctx.get(property));
+    }
+
+    #[test]
+    fn set_read_only_property() {
+        let ctx = Mock::new();
+        ctx.set("kong.route_name", "my-route");
+
+        let node = node!("route_name");
+        let payload = Payload::from("other-route");
+
+        let state = run!(&node, &ctx, &input!(Some(&payload)));
+        assert_eq!(
+            fail!(Some(Payload::Error(
+                ErrorKind::ConfigError,
+                "property: `kong.route_name` is read-only".into()
+            ))),
+            state
+        );
+        assert_eq!(Some("my-route".into()), ctx.get("kong.route_name"));
+    }
+
+    #[test]
+    fn reject_read_only_property() {
+        let new_config = |property: &str, mode: &str| {
+            let bt = BTreeMap::from([
+                ("property".to_string(), Value::from(property)),
+                ("mode".to_string(), Value::from(mode)),
+            ]);
+            PropertyFactory {}
+                .new_config("P", &[], &[], &bt)
+                .map(|_| ())
+        };
+
+        assert_eq!(
+            Err("property `client_ip` (ngx.remote_addr) is read-only".into()),
+            new_config("client_ip", "set")
+        );
+        assert!(new_config("kong.configuration.log_level", "delete").is_err());
+        assert!(new_config("kong.route_id", "get").is_ok());
+        assert!(new_config("service_target", "set").is_ok());
+        assert!(new_config("my.property", "set").is_ok());
+    }
+
     #[test]
     fn set_property_from_error() {
         let property = "test.property";
@@ -464,7 +802,7 @@ mod test {
 
         let ctx = Mock::new();
 
-        let payload = Payload::Error(err.into());
+        let payload = Payload::Error(ErrorKind::ParseError, err.into());
 
         let node = node!(property);
         let state = run!(&node, &ctx, &input!(Some(&payload)));
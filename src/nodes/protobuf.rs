@@ -0,0 +1,203 @@
+use base64::Engine;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+/// Size of the prefix of a gRPC message frame:
+/// a compression flag followed by a 4-byte big-endian length.
+const GRPC_FRAME_PREFIX_LEN: usize = 5;
+
+#[derive(Clone, Debug)]
+pub struct ProtobufConfig {
+    message: MessageDescriptor,
+    framed: bool,
+}
+
+impl NodeConfig for ProtobufConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Protobuf {
+    config: ProtobufConfig,
+}
+
+/// Strip the gRPC frame of a unary message, as found in the body
+/// of an HTTP/2 gRPC response.
+fn unframe(bytes: &[u8]) -> Result<&[u8], String> {
+    if bytes.len() < GRPC_FRAME_PREFIX_LEN {
+        return Err("protobuf: incomplete gRPC frame".into());
+    }
+    if bytes[0] != 0 {
+        return Err("protobuf: compressed gRPC messages are not supported".into());
+    }
+
+    let len = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]) as usize;
+    let message = &bytes[GRPC_FRAME_PREFIX_LEN..];
+    if message.len() != len {
+        return Err(
+            "protobuf: gRPC frame length mismatch (only unary messages are supported)".into(),
+        );
+    }
+    Ok(message)
+}
+
+impl Protobuf {
+    fn decode(&self, input: &Payload) -> Result<Payload, String> {
+        let bytes = match input {
+            Payload::Raw(bytes) => bytes.as_slice(),
+            Payload::Json(_) => {
+                return Err(
+                    "protobuf: expected an encoded protobuf message, not a JSON value".into(),
+                )
+            }
+            Payload::Error(_, e) => return Err(e.clone()),
+        };
+
+        let bytes = if self.config.framed {
+            unframe(bytes)?
+        } else {
+            bytes
+        };
+
+        let message = DynamicMessage::decode(self.config.message.clone(), bytes)
+            .map_err(|e| format!("protobuf: failed decoding message: {e}"))?;
+
+        serde_json::to_value(&message)
+            .map(Payload::Json)
+            .map_err(|e| format!("protobuf: failed converting message to JSON: {e}"))
+    }
+}
+
+impl Node for Protobuf {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        match input.get(0) {
+            Some(payload) => match self.decode(payload) {
+                Ok(payload) => Done(vec![Some(payload)]),
+                Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+            },
+            None => Done(vec![None]),
+        }
+    }
+}
+
+/// Load the message type of the given name from a
+/// base64-encoded, compiled `FileDescriptorSet`.
+fn load_message(descriptor_set: &str, name: &str) -> Result<MessageDescriptor, String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(descriptor_set.trim())
+        .map_err(|e| format!("protobuf: 'descriptor_set' is not valid base64: {e}"))?;
+
+    let pool = DescriptorPool::decode(bytes.as_slice())
+        .map_err(|e| format!("protobuf: invalid 'descriptor_set': {e}"))?;
+
+    pool.get_message_by_name(name.trim_start_matches('.'))
+        .ok_or_else(|| format!("protobuf: message type '{name}' not found in 'descriptor_set'"))
+}
+
+pub struct ProtobufFactory {}
+
+impl NodeFactory for ProtobufFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let Some(descriptor_set) = get_config_value::<String>(bt, "descriptor_set") else {
+            return Err("protobuf: missing 'descriptor_set'".into());
+        };
+        let Some(message) = get_config_value::<String>(bt, "message") else {
+            return Err("protobuf: missing 'message'".into());
+        };
+
+        Ok(Box::new(ProtobufConfig {
+            message: load_message(&descriptor_set, &message)?,
+            framed: get_config_value(bt, "framed").unwrap_or(false),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<ProtobufConfig>() {
+            Some(pc) => Ok(Box::new(Protobuf { config: pc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    // file `greeting.proto`:
+    //   syntax = "proto3";
+    //   package test;
+    //   message Greeting { string name = 1; int32 count = 2; }
+    const DESCRIPTOR_SET: &str = "ClQKDmdyZWV0aW5nLnByb3RvEgR0ZXN0IjQKCEdyZWV0aW5nEhIKBG5hbWUYASABKAlSBG5hbWUSFAoFY291bnQYAiABKAVSBWNvdW50YgZwcm90bzM=";
+
+    // Greeting { name: "hi", count: 3 }
+    const GREETING: &[u8] = &[0x0a, 0x02, b'h', b'i', 0x10, 0x03];
+
+    fn node(framed: bool) -> Protobuf {
+        Protobuf {
+            config: ProtobufConfig {
+                message: load_message(DESCRIPTOR_SET, "test.Greeting").unwrap(),
+                framed,
+            },
+        }
+    }
+
+    #[test]
+    fn protobuf_decode() {
+        assert_eq!(
+            Ok(Payload::Json(json!({ "name": "hi", "count": 3 }))),
+            node(false).decode(&Payload::Raw(GREETING.to_vec()))
+        );
+    }
+
+    #[test]
+    fn protobuf_decode_framed() {
+        let mut framed = vec![0, 0, 0, 0, GREETING.len() as u8];
+        framed.extend_from_slice(GREETING);
+        assert_eq!(
+            Ok(Payload::Json(json!({ "name": "hi", "count": 3 }))),
+            node(true).decode(&Payload::Raw(framed))
+        );
+
+        assert!(node(true).decode(&Payload::Raw(GREETING.to_vec())).is_err());
+    }
+
+    #[test]
+    fn protobuf_invalid() {
+        assert!(node(false).decode(&Payload::Raw(vec![0xff])).is_err());
+        assert!(node(false).decode(&Payload::Json(json!({}))).is_err());
+        assert_eq!(
+            Err("protobuf: message type 'test.Nope' not found in 'descriptor_set'".to_string()),
+            load_message(DESCRIPTOR_SET, "test.Nope").map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use proxy_wasm::traits::*;
+use proxy_wasm::types::Status;
+use serde_json::{json, Value};
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::time::UNIX_EPOCH;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+/// How many times to retry updating a counter that
+/// was concurrently updated by another worker.
+const MAX_CAS_RETRIES: usize = 10;
+
+/// The value of a counter created before any hit is counted into it,
+/// which counts as zero in any window.
+const NEW_COUNTER: &[u8] = b"0 0";
+
+#[derive(Clone, Debug)]
+pub struct RateLimitConfig {
+    limit: u64,
+    window_seconds: u64,
+    namespace: String,
+    slots: Option<u64>,
+}
+
+impl NodeConfig for RateLimitConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct RateLimit {
+    config: RateLimitConfig,
+}
+
+/// Counters are stored as "<window> <count>", where the window is the
+/// number of the current window since the epoch. A counter from an earlier
+/// window is stale and counts as zero.
+fn parse_counter(bytes: &[u8], window: u64) -> u64 {
+    let Ok(s) = std::str::from_utf8(bytes) else {
+        return 0;
+    };
+    match s.split_once(' ') {
+        Some((w, count)) if w.parse() == Ok(window) => count.parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn key_to_string(payload: Option<&Payload>) -> Result<String, String> {
+    match payload {
+        None => Ok(String::new()),
+        Some(Payload::Json(Value::String(s))) => Ok(s.clone()),
+        Some(Payload::Json(value)) => Ok(value.to_string()),
+        Some(Payload::Raw(bytes)) => Ok(String::from_utf8_lossy(bytes).into_owned()),
+        Some(Payload::Error(_, e)) => Err(e.clone()),
+    }
+}
+
+impl RateLimit {
+    /// Each key has its own counter, unless `slots` is set: shared data
+    /// cannot be removed, so keys are then hashed into a fixed number of
+    /// slots for the counters to take bounded memory. Keys falling into
+    /// the same slot share a counter.
+    fn shared_data_key(&self, key: &str) -> String {
+        let namespace = &self.config.namespace;
+        match self.config.slots {
+            Some(slots) => {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                let slot = hasher.finish() % slots;
+                format!("datakit:rate_limit:{namespace}:{slot}")
+            }
+            None => format!("datakit:rate_limit:{namespace}:{key}"),
+        }
+    }
+
+    /// Shared data has no create-if-absent: a write without a CAS value
+    /// replaces whatever is there. So a missing counter is first created
+    /// empty, and hits are only ever counted through CAS writes. Workers
+    /// creating the same counter at once then all count their hits; only
+    /// a creation landing after another worker already counted into the
+    /// counter can still drop that count.
+    fn create_counter(&self, ctx: &dyn HttpContext, key: &str) -> Result<(), String> {
+        if ctx.get_shared_data(key).0.is_some() {
+            return Ok(());
+        }
+        match ctx.set_shared_data(key, Some(NEW_COUNTER), None) {
+            Ok(()) | Err(Status::CasMismatch) => Ok(()),
+            Err(status) => Err(format!("rate_limit: failed creating counter: {status:?}")),
+        }
+    }
+
+    /// Count a hit for the key in the current window,
+    /// returning the count including this hit, or None if
+    /// the limit had already been reached.
+    fn hit(&self, ctx: &dyn HttpContext, key: &str, window: u64) -> Result<Option<u64>, String> {
+        let key = self.shared_data_key(key);
+        self.create_counter(ctx, &key)?;
+
+        for _ in 0..MAX_CAS_RETRIES {
+            let (data, cas) = ctx.get_shared_data(&key);
+            let count = data.map_or(0, |bytes| parse_counter(&bytes, window));
+            if count >= self.config.limit {
+                return Ok(None);
+            }
+
+            let value = format!("{window} {}", count + 1);
+            match ctx.set_shared_data(&key, Some(value.as_bytes()), cas) {
+                Ok(()) => return Ok(Some(count + 1)),
+                Err(Status::CasMismatch) => continue,
+                Err(status) => {
+                    return Err(format!("rate_limit: failed updating counter: {status:?}"))
+                }
+            }
+        }
+
+        Err(format!(
+            "rate_limit: failed updating counter after {MAX_CAS_RETRIES} attempts"
+        ))
+    }
+}
+
+impl Node for RateLimit {
+    fn run(&self, ctx: &dyn HttpContext, input: &Input) -> State {
+        let key = match key_to_string(input.get(0)) {
+            Ok(key) => key,
+            Err(e) => return Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e)), None]),
+        };
+
+        let now = ctx
+            .get_current_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let window_seconds = self.config.window_seconds;
+        let window = now / window_seconds;
+        let reset = window_seconds - now % window_seconds;
+
+        match self.hit(ctx, &key, window) {
+            Ok(Some(count)) => {
+                let info = json!({
+                    "limit": self.config.limit,
+                    "remaining": self.config.limit - count,
+                    "reset": reset,
+                });
+                Done(vec![Some(Payload::Json(info)), None])
+            }
+            Ok(None) => {
+                let info = json!({
+                    "limit": self.config.limit,
+                    "remaining": 0,
+                    "reset": reset,
+                });
+                Done(vec![None, Some(Payload::Json(info))])
+            }
+            Err(e) => Fail(vec![
+                Some(Payload::Error(ErrorKind::InternalError, e)),
+                None,
+            ]),
+        }
+    }
+}
+
+pub struct RateLimitFactory {}
+
+impl NodeFactory for RateLimitFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["key"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["allowed", "limited"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let limit = match get_config_value::<u64>(bt, "limit") {
+            Some(n) if n > 0 => n,
+            _ => return Err("rate_limit: 'limit' is a required positive integer attribute".into()),
+        };
+
+        let window_seconds = match get_config_value::<u64>(bt, "window_seconds") {
+            None => 60,
+            Some(n) if n > 0 => n,
+            Some(_) => return Err("rate_limit: 'window_seconds' must be positive".into()),
+        };
+
+        let slots = match get_config_value::<u64>(bt, "slots") {
+            None => None,
+            Some(n) if n > 0 => Some(n),
+            Some(_) => return Err("rate_limit: 'slots' must be positive".into()),
+        };
+
+        Ok(Box::new(RateLimitConfig {
+            limit,
+            window_seconds,
+            namespace: get_config_value(bt, "namespace").unwrap_or_else(|| name.to_string()),
+            slots,
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<RateLimitConfig>() {
+            Some(cc) => Ok(Box::new(RateLimit { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{InputPort, Phase};
+    use mock_proxy_wasm::*;
+    use proxy_wasm::types::Bytes;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    #[derive(Default)]
+    struct Mock {
+        now: u64,
+        shared: RefCell<HashMap<String, (Bytes, u32)>>,
+        // the CAS values of the writes
+        writes: RefCell<Vec<Option<u32>>>,
+        // number of upcoming writes that fail as if
+        // another worker had updated the counter first
+        conflicts: Cell<usize>,
+    }
+
+    #[mock_proxy_wasm_context]
+    impl Context for Mock {
+        fn get_current_time(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(self.now)
+        }
+
+        fn get_shared_data(&self, key: &str) -> (Option<Bytes>, Option<u32>) {
+            match self.shared.borrow().get(key) {
+                Some((bytes, cas)) => (Some(bytes.clone()), Some(*cas)),
+                None => (None, None),
+            }
+        }
+
+        fn set_shared_data(
+            &self,
+            key: &str,
+            value: Option<&[u8]>,
+            cas: Option<u32>,
+        ) -> Result<(), Status> {
+            self.writes.borrow_mut().push(cas);
+            let mut shared = self.shared.borrow_mut();
+            let current = shared.get(key).map(|(_, c)| *c);
+            if self.conflicts.get() > 0 {
+                self.conflicts.set(self.conflicts.get() - 1);
+                return Err(Status::CasMismatch);
+            }
+            if cas.is_some() && cas != current {
+                return Err(Status::CasMismatch);
+            }
+            let bytes = value.unwrap_or_default().to_vec();
+            shared.insert(key.to_string(), (bytes, current.unwrap_or(0) + 1));
+            Ok(())
+        }
+    }
+
+    #[mock_proxy_wasm_http_context]
+    impl HttpContext for Mock {}
+
+    fn node(limit: u64, window_seconds: u64) -> RateLimit {
+        RateLimit {
+            config: RateLimitConfig {
+                limit,
+                window_seconds,
+                namespace: "test".into(),
+                slots: None,
+            },
+        }
+    }
+
+    fn run(node: &RateLimit, ctx: &Mock, key: &str) -> State {
+        let key = Payload::Json(json!(key));
+        let input = Input {
+            data: &[InputPort::Value(&key)],
+            phase: Phase::HttpRequestHeaders,
+        };
+        node.run(ctx as &dyn HttpContext, &input)
+    }
+
+    fn allowed(remaining: u64, reset: u64) -> State {
+        let info = json!({ "limit": 2, "remaining": remaining, "reset": reset });
+        Done(vec![Some(Payload::Json(info)), None])
+    }
+
+    fn limited(reset: u64) -> State {
+        let info = json!({ "limit": 2, "remaining": 0, "reset": reset });
+        Done(vec![None, Some(Payload::Json(info))])
+    }
+
+    #[test]
+    fn rate_limit_window() {
+        let node = node(2, 10);
+        let mut ctx = Mock {
+            now: 100,
+            ..Default::default()
+        };
+
+        assert_eq!(allowed(1, 10), run(&node, &ctx, "a"));
+        assert_eq!(allowed(0, 10), run(&node, &ctx, "a"));
+        assert_eq!(limited(10), run(&node, &ctx, "a"));
+        assert_eq!(allowed(1, 10), run(&node, &ctx, "b"));
+
+        ctx.now = 109;
+        assert_eq!(limited(1), run(&node, &ctx, "a"));
+
+        // next window
+        ctx.now = 110;
+        assert_eq!(allowed(1, 10), run(&node, &ctx, "a"));
+    }
+
+    #[test]
+    fn rate_limit_key_counters() {
+        let node = node(2, 10);
+        let ctx = Mock::default();
+
+        for i in 0..100 {
+            assert_eq!(allowed(1, 10), run(&node, &ctx, &format!("client-{i}")));
+        }
+        assert_eq!(100, ctx.shared.borrow().len());
+        assert!(ctx
+            .shared
+            .borrow()
+            .contains_key("datakit:rate_limit:test:client-0"));
+    }
+
+    #[test]
+    fn rate_limit_bounded_slots() {
+        let mut node = node(2, 10);
+        node.config.slots = Some(4);
+        let ctx = Mock::default();
+
+        for i in 0..100 {
+            run(&node, &ctx, &format!("client-{i}"));
+        }
+        assert!(ctx.shared.borrow().len() <= 4);
+    }
+
+    #[test]
+    fn rate_limit_counts_through_cas() {
+        let node = node(2, 10);
+        let ctx = Mock::default();
+
+        // the counter is created, then counted into with its CAS value
+        assert_eq!(allowed(1, 10), run(&node, &ctx, "a"));
+        assert_eq!(vec![None, Some(1)], *ctx.writes.borrow());
+
+        ctx.writes.borrow_mut().clear();
+        assert_eq!(allowed(0, 10), run(&node, &ctx, "a"));
+        assert_eq!(vec![Some(2)], *ctx.writes.borrow());
+    }
+
+    #[test]
+    fn rate_limit_cas_retry() {
+        let node = node(2, 10);
+        let ctx = Mock::default();
+
+        ctx.conflicts.set(MAX_CAS_RETRIES - 1);
+        assert_eq!(allowed(1, 10), run(&node, &ctx, "a"));
+
+        ctx.conflicts.set(MAX_CAS_RETRIES);
+        assert!(matches!(run(&node, &ctx, "a"), Fail(_)));
+    }
+}
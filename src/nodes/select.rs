@@ -0,0 +1,180 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Debug)]
+pub struct SelectConfig {
+    /// The names of the candidate ports, which follow `selector`.
+    candidates: Vec<String>,
+    /// The candidate used when the selector has no value.
+    default: Option<usize>,
+}
+
+impl NodeConfig for SelectConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn optional_inputs(&self) -> bool {
+        true
+    }
+}
+
+pub struct Select {
+    config: SelectConfig,
+}
+
+impl Select {
+    /// The index of the chosen candidate, from the name of its port
+    /// or its position among the candidates.
+    fn choose(&self, selector: Option<&Payload>) -> Result<Option<usize>, String> {
+        let candidates = &self.config.candidates;
+        let found = match selector {
+            None | Some(Payload::Json(Value::Null)) => return Ok(self.config.default),
+            Some(Payload::Error(_, e)) => return Err(e.clone()),
+            Some(Payload::Json(Value::Number(n))) => n
+                .as_u64()
+                .map(|i| i as usize)
+                .filter(|&i| i < candidates.len())
+                .ok_or_else(|| n.to_string()),
+            Some(payload) => {
+                let name = match payload {
+                    Payload::Json(Value::String(s)) => s.clone(),
+                    _ => String::from_utf8_lossy(&payload.to_bytes(None)?).into_owned(),
+                };
+                candidates.iter().position(|c| *c == name).ok_or(name)
+            }
+        };
+
+        match found {
+            Ok(i) => Ok(Some(i)),
+            Err(_) if self.config.default.is_some() => Ok(self.config.default),
+            Err(name) => Err(format!("select: no input port matches selector '{name}'")),
+        }
+    }
+}
+
+impl Node for Select {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        match self.choose(input.get(0)) {
+            // candidates come after the selector port
+            Ok(chosen) => Done(vec![chosen.and_then(|i| input.get(i + 1)).cloned()]),
+            Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+        }
+    }
+}
+
+pub struct SelectFactory {}
+
+impl NodeFactory for SelectFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["selector"])),
+            user_defined_ports: true,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let candidates = inputs.get(1..).unwrap_or_default().to_vec();
+        if candidates.is_empty() {
+            return Err("select: at least one input port besides 'selector' is needed".into());
+        }
+
+        let default = match get_config_value::<String>(bt, "default") {
+            Some(name) => match candidates.iter().position(|c| *c == name) {
+                Some(i) => Some(i),
+                None => return Err(format!("select: 'default' is not an input port: {name}")),
+            },
+            None => None,
+        };
+
+        Ok(Box::new(SelectConfig {
+            candidates,
+            default,
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<SelectConfig>() {
+            Some(sc) => Ok(Box::new(Select { config: sc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn select(default: Option<&str>) -> Result<Select, String> {
+        let inputs = ["selector", "json", "html"].map(String::from);
+        let bt = match default {
+            Some(name) => BTreeMap::from([("default".to_string(), json!(name))]),
+            None => BTreeMap::new(),
+        };
+        let config = SelectFactory {}.new_config("S", &inputs, &[], &bt)?;
+        let config = config.as_any().downcast_ref::<SelectConfig>().unwrap();
+        Ok(Select {
+            config: config.clone(),
+        })
+    }
+
+    #[test]
+    fn select_by_name_or_index() {
+        let node = select(None).unwrap();
+        let choose = |payload: Payload| node.choose(Some(&payload));
+        assert_eq!(Ok(Some(1)), choose(Payload::Json(json!("html"))));
+        assert_eq!(Ok(Some(1)), choose(Payload::Raw(b"html".to_vec())));
+        assert_eq!(Ok(Some(0)), choose(Payload::Json(json!(0))));
+        assert_eq!(Ok(None), node.choose(None));
+
+        assert_eq!(
+            Err("select: no input port matches selector 'xml'".to_string()),
+            choose(Payload::Json(json!("xml")))
+        );
+        assert!(choose(Payload::Json(json!(2))).is_err());
+        assert!(choose(Payload::Json(json!(-1))).is_err());
+        assert!(choose(Payload::Error(ErrorKind::ParseError, "boom".into())).is_err());
+    }
+
+    #[test]
+    fn select_default() {
+        let node = select(Some("json")).unwrap();
+        assert_eq!(Ok(Some(0)), node.choose(None));
+        assert_eq!(Ok(Some(0)), node.choose(Some(&Payload::Json(Value::Null))));
+        assert_eq!(Ok(Some(0)), node.choose(Some(&Payload::Json(json!("xml")))));
+        assert_eq!(
+            Ok(Some(1)),
+            node.choose(Some(&Payload::Json(json!("html"))))
+        );
+    }
+
+    #[test]
+    fn select_invalid_config() {
+        assert!(select(Some("xml")).is_err());
+        let inputs = ["selector"].map(String::from);
+        assert!(SelectFactory {}
+            .new_config("S", &inputs, &[], &BTreeMap::new())
+            .is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use percent_encoding::percent_decode_str;
+use proxy_wasm::traits::*;
+use serde_json::{Map, Value};
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// A `{name}` segment, capturing the path segment at its position.
+    Param(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct SplitPathConfig {
+    segments: Vec<Segment>,
+    required: bool,
+}
+
+impl NodeConfig for SplitPathConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct SplitPath {
+    config: SplitPathConfig,
+}
+
+/// The segments of a path, without its query string and trailing slash.
+fn path_segments(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.split_once('?').map_or(path, |(p, _)| p);
+    let path = path.strip_suffix('/').unwrap_or(path);
+    path.strip_prefix('/').unwrap_or(path).split('/')
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment>, String> {
+    if !pattern.starts_with('/') {
+        return Err("split_path: 'pattern' must start with '/'".into());
+    }
+
+    let mut segments = vec![];
+    for segment in path_segments(pattern) {
+        let segment = match segment.strip_prefix('{') {
+            Some(rest) => {
+                let Some(name) = rest.strip_suffix('}').filter(|name| !name.is_empty()) else {
+                    return Err(format!(
+                        "split_path: invalid segment '{segment}' in 'pattern'"
+                    ));
+                };
+                if segments.contains(&Segment::Param(name.into())) {
+                    return Err(format!("split_path: duplicate parameter '{name}'"));
+                }
+                Segment::Param(name.into())
+            }
+            None => Segment::Literal(segment.into()),
+        };
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+impl SplitPath {
+    /// The captured parameters, or `None` if the path does not match.
+    fn capture(&self, path: &str) -> Option<Map<String, Value>> {
+        let mut params = Map::new();
+        let mut parts = path_segments(path);
+
+        for segment in &self.config.segments {
+            let part = parts.next()?;
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(_) if part.is_empty() => return None,
+                Segment::Param(name) => {
+                    let value = percent_decode_str(part).decode_utf8_lossy();
+                    params.insert(name.clone(), value.into());
+                }
+            }
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+
+    fn split(&self, path: &str) -> State {
+        match self.capture(path) {
+            Some(params) => Done(vec![Some(Payload::Json(params.into()))]),
+            None if self.config.required => Fail(vec![Some(Payload::Error(
+                ErrorKind::ParseError,
+                format!("split_path: path '{path}' does not match the pattern"),
+            ))]),
+            None => Done(vec![Some(Payload::json_null())]),
+        }
+    }
+}
+
+impl Node for SplitPath {
+    fn run(&self, ctx: &dyn HttpContext, _input: &Input) -> State {
+        let path = ctx.get_http_request_header(":path").unwrap_or_default();
+        self.split(&path)
+    }
+}
+
+pub struct SplitPathFactory {}
+
+impl NodeFactory for SplitPathFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: None,
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let Some(pattern) = get_config_value::<String>(bt, "pattern") else {
+            return Err("split_path: missing 'pattern'".into());
+        };
+
+        Ok(Box::new(SplitPathConfig {
+            segments: parse_pattern(&pattern)?,
+            required: get_config_value(bt, "required").unwrap_or(false),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<SplitPathConfig>() {
+            Some(sc) => Ok(Box::new(SplitPath { config: sc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn split(pattern: &str, required: bool, path: &str) -> State {
+        let node = SplitPath {
+            config: SplitPathConfig {
+                segments: parse_pattern(pattern).unwrap(),
+                required,
+            },
+        };
+        node.split(path)
+    }
+
+    fn params(value: Value) -> State {
+        Done(vec![Some(Payload::Json(value))])
+    }
+
+    #[test]
+    fn split_path_params() {
+        let pattern = "/users/{id}/orders/{order}";
+        assert_eq!(
+            params(json!({ "id": "42", "order": "a b" })),
+            split(pattern, false, "/users/42/orders/a%20b")
+        );
+        assert_eq!(
+            params(json!({ "id": "42", "order": "7" })),
+            split(pattern, false, "/users/42/orders/7/?expand=items")
+        );
+        assert_eq!(params(json!({})), split("/", false, "/"));
+    }
+
+    #[test]
+    fn split_path_no_match() {
+        let pattern = "/users/{id}";
+        for path in ["/users", "/users/", "/users/42/orders", "/items/42"] {
+            assert_eq!(params(Value::Null), split(pattern, false, path), "{path}");
+        }
+
+        let State::Fail(payloads) = split(pattern, true, "/items/42") else {
+            panic!("expected State::Fail(...)");
+        };
+        assert!(matches!(
+            payloads.first(),
+            Some(Some(Payload::Error(ErrorKind::ParseError, _)))
+        ));
+    }
+
+    #[test]
+    fn split_path_invalid_pattern() {
+        assert!(parse_pattern("users/{id}").is_err());
+        assert!(parse_pattern("/users/{}").is_err());
+        assert!(parse_pattern("/users/{id").is_err());
+        assert!(parse_pattern("/users/{id}/{id}").is_err());
+    }
+}
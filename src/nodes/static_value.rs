@@ -0,0 +1,142 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::{check_resolved, get_config_value};
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{Payload, JSON_CONTENT_TYPE};
+
+#[derive(Clone, Debug)]
+pub struct StaticConfig {
+    payload: Payload,
+}
+
+impl NodeConfig for StaticConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct Static {
+    config: StaticConfig,
+}
+
+impl Node for Static {
+    fn run(&self, _ctx: &dyn HttpContext, _input: &Input) -> State {
+        Done(vec![Some(self.config.payload.clone())])
+    }
+}
+
+/// JSON values are emitted as they are; string values with another
+/// content type are converted as if they had been received with it.
+fn to_payload(value: Value, content_type: Option<&str>) -> Result<Payload, String> {
+    match (value, content_type) {
+        (value, None) => Ok(Payload::Json(value)),
+        (value, Some(ct)) if ct.contains(JSON_CONTENT_TYPE) => Ok(Payload::Json(value)),
+        (Value::String(s), Some(ct)) => match Payload::from_bytes(s.into_bytes(), Some(ct)) {
+            Some(Payload::Error(_, e)) => Err(format!("static: invalid 'value': {e}")),
+            Some(payload) => Ok(payload),
+            None => Err("static: invalid 'value'".into()),
+        },
+        (_, Some(ct)) => Err(format!(
+            "static: 'value' must be a string when 'content_type' is {ct}"
+        )),
+    }
+}
+
+pub struct StaticFactory {}
+
+impl NodeFactory for StaticFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: None,
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let Some(value) = bt.get("value").cloned() else {
+            return Err("static: 'value' is a required attribute".into());
+        };
+        if let Value::String(s) = &value {
+            check_resolved("static", "value", s)?;
+        }
+
+        let content_type = get_config_value::<String>(bt, "content_type");
+
+        Ok(Box::new(StaticConfig {
+            payload: to_payload(value, content_type.as_deref())?,
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<StaticConfig>() {
+            Some(cc) => Ok(Box::new(Static { config: cc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn static_payloads() {
+        let value = json!({ "a": [1, 2] });
+        assert_eq!(
+            Ok(Payload::Json(value.clone())),
+            to_payload(value.clone(), None)
+        );
+        assert_eq!(
+            Ok(Payload::Json(value.clone())),
+            to_payload(value, Some(JSON_CONTENT_TYPE))
+        );
+        assert_eq!(
+            Ok(Payload::Raw(b"hello".to_vec())),
+            to_payload(json!("hello"), Some("text/plain"))
+        );
+        assert_eq!(
+            Ok(Payload::Json(json!({ "a": "1" }))),
+            to_payload(json!("a=1"), Some("application/x-www-form-urlencoded"))
+        );
+    }
+
+    #[test]
+    fn static_invalid() {
+        assert_eq!(
+            Err("static: 'value' must be a string when 'content_type' is text/plain".into()),
+            to_payload(json!(1), Some("text/plain"))
+        );
+    }
+
+    #[test]
+    fn static_unresolved_reference() {
+        let bt = serde_json::from_value(json!({ "value": "{vault://env/token}" })).unwrap();
+        let err = StaticFactory {}.new_config("s", &[], &[], &bt).err();
+        assert_eq!(
+            err.as_deref(),
+            Some("static: 'value' holds the unresolved vault reference {vault://env/token}")
+        );
+
+        // only a reference that is the whole string is one
+        let bt = serde_json::from_value(json!({ "value": "see {vault://env/token}" })).unwrap();
+        assert!(StaticFactory {}.new_config("s", &[], &[], &bt).is_ok());
+    }
+}
@@ -0,0 +1,217 @@
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    /// The index of the input port whose value is inserted.
+    Input(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct SubstituteConfig {
+    segments: Vec<Segment>,
+    default: String,
+}
+
+impl NodeConfig for SubstituteConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn optional_inputs(&self) -> bool {
+        true
+    }
+}
+
+pub struct Substitute {
+    config: SubstituteConfig,
+}
+
+/// Split a template into text and `${name}` placeholders, resolving each
+/// name to an input port. `$${` produces a literal `${`.
+fn parse_template(template: &str, inputs: &[String]) -> Result<Vec<Segment>, String> {
+    let mut segments = vec![];
+    let mut text = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            text.push_str(&rest[..start - 1]);
+            text.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find('}') else {
+            return Err("substitute: unterminated placeholder in 'template'".into());
+        };
+
+        let name = after[..end].trim();
+        let Some(port) = inputs.iter().position(|input| input == name) else {
+            return Err(format!(
+                "substitute: placeholder '{name}' does not match any input port"
+            ));
+        };
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(std::mem::take(&mut text)));
+        }
+        segments.push(Segment::Input(port));
+        rest = &after[end + 1..];
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+
+    Ok(segments)
+}
+
+impl Substitute {
+    fn fill(&self, inputs: &[Option<&Payload>]) -> Result<Vec<u8>, String> {
+        let mut out = vec![];
+        for segment in &self.config.segments {
+            match segment {
+                Segment::Text(text) => out.extend_from_slice(text.as_bytes()),
+                Segment::Input(port) => match inputs.get(*port).copied().flatten() {
+                    Some(payload) => out.extend(payload.to_bytes(None)?),
+                    None => out.extend_from_slice(self.config.default.as_bytes()),
+                },
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl Node for Substitute {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        match self.fill(&input.payloads()) {
+            Ok(bytes) => Done(vec![Some(Payload::Raw(bytes))]),
+            Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+        }
+    }
+}
+
+pub struct SubstituteFactory {}
+
+impl NodeFactory for SubstituteFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: None,
+            user_defined_ports: true,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let Some(template) = get_config_value::<String>(bt, "template") else {
+            return Err("substitute: missing 'template'".into());
+        };
+
+        Ok(Box::new(SubstituteConfig {
+            segments: parse_template(&template, inputs)?,
+            default: get_config_value(bt, "default").unwrap_or_default(),
+        }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<SubstituteConfig>() {
+            Some(sc) => Ok(Box::new(Substitute { config: sc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn fill(template: &str, default: &str, inputs: &[Option<Payload>]) -> Result<String, String> {
+        let names = ["user", "id"].map(String::from);
+        let node = Substitute {
+            config: SubstituteConfig {
+                segments: parse_template(template, &names)?,
+                default: default.into(),
+            },
+        };
+        let inputs: Vec<Option<&Payload>> = inputs.iter().map(Option::as_ref).collect();
+        node.fill(&inputs)
+            .map(|bytes| String::from_utf8(bytes).unwrap())
+    }
+
+    #[test]
+    fn substitute_inputs() {
+        let inputs = [
+            Some(Payload::Raw(b"ann".to_vec())),
+            Some(Payload::Json(json!(42))),
+        ];
+        assert_eq!(
+            Ok("/users/ann/items/42?u=ann".to_string()),
+            fill("/users/${user}/items/${ id }?u=${user}", "", &inputs)
+        );
+
+        let inputs = [Some(Payload::Json(json!("ann"))), None];
+        assert_eq!(
+            Ok("ann-none".to_string()),
+            fill("${user}-${id}", "none", &inputs)
+        );
+        assert_eq!(Ok("ann-".to_string()), fill("${user}-${id}", "", &inputs));
+    }
+
+    #[test]
+    fn substitute_escape() {
+        assert_eq!(
+            Ok("cost: $5, ${user} is ann".to_string()),
+            fill(
+                "cost: $5, $${user} is ${user}",
+                "",
+                &[Some(Payload::Raw(b"ann".to_vec())), None]
+            )
+        );
+    }
+
+    #[test]
+    fn substitute_invalid_template() {
+        assert_eq!(
+            Err("substitute: placeholder 'name' does not match any input port".to_string()),
+            fill("hello ${name}", "", &[])
+        );
+        assert_eq!(
+            Err("substitute: unterminated placeholder in 'template'".to_string()),
+            fill("hello ${user", "", &[])
+        );
+    }
+
+    #[test]
+    fn substitute_error_input() {
+        let inputs = [
+            Some(Payload::Error(ErrorKind::ParseError, "boom".into())),
+            None,
+        ];
+        assert_eq!(Err("boom".to_string()), fill("${user}", "", &inputs));
+    }
+}
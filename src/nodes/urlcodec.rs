@@ -0,0 +1,247 @@
+use percent_encoding::{percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
+use proxy_wasm::traits::*;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::BTreeMap;
+
+use crate::config::get_config_value;
+use crate::data::{Input, State, State::*};
+use crate::nodes::{Node, NodeConfig, NodeFactory, PortConfig};
+use crate::payload::{ErrorKind, Payload};
+
+/// Everything but the unreserved characters of RFC 3986.
+const COMPONENT_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The path percent-encode set of the WHATWG URL standard, plus `%` itself.
+/// It keeps `/`, so that a whole path can be encoded at once.
+const PATH_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'%');
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Encode,
+    Decode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EncodeSet {
+    /// A query string key or value, as in `application/x-www-form-urlencoded`.
+    Query,
+    Path,
+    Component,
+}
+
+#[derive(Clone, Debug)]
+pub struct UrlCodecConfig {
+    action: Action,
+    component: EncodeSet,
+}
+
+impl NodeConfig for UrlCodecConfig {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub struct UrlCodec {
+    config: UrlCodecConfig,
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Decode percent-encoded bytes, reporting the position
+/// of the first invalid `%` sequence.
+fn percent_decode_strict(input: &[u8], plus_as_space: bool) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'%' => {
+                let hi = input.get(i + 1).copied().and_then(hex_value);
+                let lo = input.get(i + 2).copied().and_then(hex_value);
+                match (hi, lo) {
+                    (Some(hi), Some(lo)) => out.push((hi << 4) | lo),
+                    _ => {
+                        return Err(format!(
+                            "urlcodec: invalid percent-encoding at position {i}"
+                        ))
+                    }
+                }
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+impl UrlCodec {
+    fn convert(&self, input: &Payload) -> Result<Payload, String> {
+        let bytes = match input {
+            Payload::Json(Value::Object(_)) | Payload::Json(Value::Array(_)) => {
+                return Err("urlcodec: expected a string".into())
+            }
+            _ => input.to_bytes(None)?,
+        };
+
+        let UrlCodecConfig { action, component } = self.config;
+        let output = match (action, component) {
+            (Action::Encode, EncodeSet::Query) => form_urlencoded::byte_serialize(&bytes).collect(),
+            (Action::Encode, EncodeSet::Path) => percent_encode(&bytes, PATH_SET).to_string(),
+            (Action::Encode, EncodeSet::Component) => {
+                percent_encode(&bytes, COMPONENT_SET).to_string()
+            }
+            (Action::Decode, _) => {
+                let decoded = percent_decode_strict(&bytes, component == EncodeSet::Query)?;
+                String::from_utf8(decoded)
+                    .map_err(|_| "urlcodec: decoded value is not valid UTF-8".to_string())?
+            }
+        };
+
+        Ok(Payload::Json(Value::String(output)))
+    }
+}
+
+impl Node for UrlCodec {
+    fn run(&self, _ctx: &dyn HttpContext, input: &Input) -> State {
+        match input.get(0) {
+            Some(payload) => match self.convert(payload) {
+                Ok(payload) => Done(vec![Some(payload)]),
+                Err(e) => Fail(vec![Some(Payload::Error(ErrorKind::ParseError, e))]),
+            },
+            None => Done(vec![None]),
+        }
+    }
+}
+
+pub struct UrlCodecFactory {}
+
+impl NodeFactory for UrlCodecFactory {
+    fn default_input_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn default_output_ports(&self) -> PortConfig {
+        PortConfig {
+            defaults: Some(PortConfig::names(&["value"])),
+            user_defined_ports: false,
+        }
+    }
+
+    fn new_config(
+        &self,
+        _name: &str,
+        _inputs: &[String],
+        _outputs: &[String],
+        bt: &BTreeMap<String, Value>,
+    ) -> Result<Box<dyn NodeConfig>, String> {
+        let action = match get_config_value::<String>(bt, "action").as_deref() {
+            Some("encode") => Action::Encode,
+            Some("decode") => Action::Decode,
+            _ => return Err("urlcodec: 'action' must be either 'encode' or 'decode'".into()),
+        };
+
+        let component = match get_config_value::<String>(bt, "component").as_deref() {
+            None | Some("component") => EncodeSet::Component,
+            Some("query") => EncodeSet::Query,
+            Some("path") => EncodeSet::Path,
+            Some(other) => return Err(format!("urlcodec: invalid 'component': {other}")),
+        };
+
+        Ok(Box::new(UrlCodecConfig { action, component }))
+    }
+
+    fn new_node(&self, config: &dyn NodeConfig) -> Result<Box<dyn Node>, String> {
+        match config.as_any().downcast_ref::<UrlCodecConfig>() {
+            Some(uc) => Ok(Box::new(UrlCodec { config: uc.clone() })),
+            None => Err("incompatible NodeConfig".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn convert(action: Action, component: EncodeSet, input: &str) -> Result<Payload, String> {
+        let node = UrlCodec {
+            config: UrlCodecConfig { action, component },
+        };
+        node.convert(&Payload::Json(json!(input)))
+    }
+
+    fn string(s: &str) -> Result<Payload, String> {
+        Ok(Payload::Json(json!(s)))
+    }
+
+    #[test]
+    fn urlcodec_encode() {
+        let input = "a b/c?d=e&f~ü";
+        assert_eq!(
+            string("a+b%2Fc%3Fd%3De%26f%7E%C3%BC"),
+            convert(Action::Encode, EncodeSet::Query, input)
+        );
+        assert_eq!(
+            string("a%20b/c%3Fd=e&f~%C3%BC"),
+            convert(Action::Encode, EncodeSet::Path, input)
+        );
+        assert_eq!(
+            string("a%20b%2Fc%3Fd%3De%26f~%C3%BC"),
+            convert(Action::Encode, EncodeSet::Component, input)
+        );
+    }
+
+    #[test]
+    fn urlcodec_decode() {
+        assert_eq!(
+            string("a b/c ü"),
+            convert(Action::Decode, EncodeSet::Query, "a+b%2Fc%20%C3%BC")
+        );
+        assert_eq!(
+            string("a+b/c ü"),
+            convert(Action::Decode, EncodeSet::Component, "a+b%2Fc%20%C3%BC")
+        );
+    }
+
+    #[test]
+    fn urlcodec_decode_invalid() {
+        assert_eq!(
+            Err("urlcodec: invalid percent-encoding at position 3".to_string()),
+            convert(Action::Decode, EncodeSet::Path, "abc%zz")
+        );
+        assert_eq!(
+            Err("urlcodec: invalid percent-encoding at position 2".to_string()),
+            convert(Action::Decode, EncodeSet::Path, "ab%4")
+        );
+        assert_eq!(
+            Err("urlcodec: decoded value is not valid UTF-8".to_string()),
+            convert(Action::Decode, EncodeSet::Path, "%ff")
+        );
+    }
+}
@@ -1,16 +1,149 @@
+use encoding_rs::{Encoding, UTF_8};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as Json;
 use std::collections::BTreeMap;
+use std::io::Write;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Payload {
     Raw(Vec<u8>),
     Json(Json),
-    Error(String),
+    Error(ErrorKind, String),
+}
+
+/// The category of a failure, so that error handling
+/// can tell problems with the data apart from system ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Data that could not be decoded or converted, such as invalid JSON.
+    ParseError,
+    /// A failed or invalid response from an upstream service.
+    UpstreamError,
+    /// A node configuration that cannot be applied to the given data,
+    /// such as a failing jq script or template.
+    ConfigError,
+    /// An operation that did not complete in time.
+    Timeout,
+    /// A failure of the proxy or of DataKit itself.
+    InternalError,
+    /// An invariant checked by an `assert` node that does not hold.
+    AssertionFailed,
 }
 
 pub const JSON_CONTENT_TYPE: &str = "application/json";
 pub const URLENCODED_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+pub const CSV_CONTENT_TYPES: &[&str] = &["text/csv", "application/csv"];
+
+pub const GZIP_ENCODING: &str = "gzip";
+
+fn is_csv(content_type: &str) -> bool {
+    CSV_CONTENT_TYPES.iter().any(|ct| content_type.contains(ct))
+}
+
+/// The items of a header such as `Accept` or `Accept-Encoding` with their
+/// quality, best first. Items of the same quality keep the client order.
+fn quality_list(header: &str) -> Vec<(&str, f32)> {
+    let mut items: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let value = params.next()?.trim();
+            let q = params
+                .filter_map(|param| param.split_once('='))
+                .find(|(key, _)| key.trim() == "q")
+                .map_or(Some(1.0), |(_, q)| q.trim().parse().ok())?;
+            (!value.is_empty()).then_some((value, q))
+        })
+        .collect();
+    // stable, so that items of the same quality keep the client order
+    items.sort_by(|a, b| b.1.total_cmp(&a.1));
+    items
+}
+
+/// When to compress a response body.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compress {
+    /// Gzip if the `Accept-Encoding` header of the client allows it.
+    Auto,
+    /// Always gzip.
+    Gzip,
+    #[default]
+    Never,
+}
+
+impl Compress {
+    pub fn parse(value: &str) -> Option<Compress> {
+        match value {
+            "auto" => Some(Compress::Auto),
+            "gzip" => Some(Compress::Gzip),
+            "none" => Some(Compress::Never),
+            _ => None,
+        }
+    }
+
+    /// Whether to gzip a body, given the `Accept-Encoding` request header.
+    pub fn applies(self, accept_encoding: Option<&str>) -> bool {
+        match self {
+            Compress::Auto => accept_encoding.is_some_and(accepts_gzip),
+            Compress::Gzip => true,
+            Compress::Never => false,
+        }
+    }
+}
+
+/// Whether an `Accept-Encoding` header allows gzip, either explicitly
+/// or through `*`.
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    let items = quality_list(accept_encoding);
+    let quality = |coding: &str| {
+        items
+            .iter()
+            .find(|(value, _)| value.eq_ignore_ascii_case(coding))
+            .map(|(_, q)| *q)
+    };
+    quality(GZIP_ENCODING)
+        .or_else(|| quality("x-gzip"))
+        .or_else(|| quality("*"))
+        .is_some_and(|q| q > 0.0)
+}
+
+pub fn gzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(bytes)
+        .and_then(|()| encoder.finish())
+        .map_err(|e| format!("gzip: {e}"))
+}
+
+/// The character encoding given by the `charset` parameter of a content type.
+#[derive(Debug, PartialEq)]
+enum Charset {
+    /// UTF-8, which is also assumed when no charset is given.
+    Utf8,
+    Other(&'static Encoding),
+    Unknown,
+}
+
+fn charset(content_type: &str) -> Charset {
+    for param in content_type.split(';').skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+        if key.trim().eq_ignore_ascii_case("charset") {
+            let label = value.trim().trim_matches('"');
+            return match Encoding::for_label(label.as_bytes()) {
+                Some(encoding) if encoding == UTF_8 => Charset::Utf8,
+                Some(encoding) => Charset::Other(encoding),
+                None => Charset::Unknown,
+            };
+        }
+    }
+    Charset::Utf8
+}
 
 impl Payload {
     pub fn content_type(&self) -> Option<&str> {
@@ -25,13 +158,33 @@ impl Payload {
     pub fn from_bytes(bytes: Vec<u8>, content_type: Option<&str>) -> Option<Payload> {
         match content_type {
             Some(ct) => {
+                // all processing happens in UTF-8; the data of an unknown
+                // charset is kept as is, since it cannot be interpreted
+                let bytes = match charset(ct) {
+                    Charset::Utf8 => bytes,
+                    Charset::Other(encoding) => {
+                        let (text, _) = encoding.decode_without_bom_handling(&bytes);
+                        text.into_owned().into_bytes()
+                    }
+                    Charset::Unknown => return Some(Payload::Raw(bytes)),
+                };
+
                 if ct.contains(JSON_CONTENT_TYPE) {
+                    // such as the body of a 204 response
+                    if bytes.is_empty() {
+                        return Some(Payload::json_null());
+                    }
                     match serde_json::from_slice(&bytes) {
                         Ok(v) => Some(Payload::Json(v)),
-                        Err(e) => Some(Payload::Error(e.to_string())),
+                        Err(e) => Some(Payload::Error(ErrorKind::ParseError, e.to_string())),
                     }
                 } else if ct.contains(URLENCODED_CONTENT_TYPE) {
                     Some(Payload::Json(urlencoded_bytes_to_map(&bytes).into()))
+                } else if is_csv(ct) {
+                    match csv_bytes_to_json(&bytes, ',', true) {
+                        Ok(v) => Some(Payload::Json(v)),
+                        Err(e) => Some(Payload::Error(ErrorKind::ParseError, e)),
+                    }
                 } else {
                     Some(Payload::Raw(bytes))
                 }
@@ -40,6 +193,23 @@ impl Payload {
         }
     }
 
+    /// A body without a content type: JSON if it is an object or an array
+    /// that parses as such, raw otherwise.
+    pub fn sniff(bytes: Vec<u8>) -> Payload {
+        let looks_like_json = matches!(
+            bytes.iter().find(|b| !b.is_ascii_whitespace()),
+            Some(b'{' | b'[')
+        );
+        if !looks_like_json {
+            return Payload::Raw(bytes);
+        }
+
+        match serde_json::from_slice(&bytes) {
+            Ok(value) => Payload::Json(value),
+            Err(_) => Payload::Raw(bytes),
+        }
+    }
+
     pub fn to_json(&self) -> Result<Json, String> {
         match &self {
             Payload::Json(value) => Ok(value.clone()),
@@ -47,32 +217,105 @@ impl Payload {
                 Ok(s) => serde_json::to_value(s).map_err(|e| e.to_string()),
                 Err(e) => Err(e.to_string()),
             },
-            Payload::Error(e) => Err(e.clone()),
+            Payload::Error(_, e) => Err(e.clone()),
         }
     }
 
     pub fn to_bytes(&self, content_type: Option<&str>) -> Result<Vec<u8>, String> {
         let to_json = content_type.is_some_and(|ct| ct.contains(JSON_CONTENT_TYPE));
+        let to_csv = content_type.is_some_and(is_csv);
+        let to_urlencoded = content_type.is_some_and(|ct| ct.contains(URLENCODED_CONTENT_TYPE));
 
-        match &self {
+        let bytes = match &self {
+            Payload::Json(value @ Json::Array(_)) if to_csv => json_to_csv_bytes(value, ',', true)?,
+            Payload::Json(Json::Object(_)) if to_urlencoded => self.to_pwm_query().into_bytes(),
             Payload::Json(Json::String(string)) if !to_json => {
                 // do not serialize a JSON string unless explicitly asked
-                Ok(string.clone().into_bytes())
+                string.clone().into_bytes()
             }
-            Payload::Json(value) => Ok(value.to_string().into_bytes()),
-            Payload::Raw(s) => Ok(s.clone()), // it would be nice to be able to avoid this copy
-            Payload::Error(e) => Err(e.clone()),
+            Payload::Json(value) => value.to_string().into_bytes(),
+            Payload::Raw(s) => s.clone(), // it would be nice to be able to avoid this copy
+            Payload::Error(_, e) => return Err(e.clone()),
+        };
+
+        // transcode back into the charset of the content type; raw data
+        // which is not UTF-8 is assumed to be in that charset already
+        match content_type.map(charset) {
+            Some(Charset::Other(encoding)) => match std::str::from_utf8(&bytes) {
+                Ok(text) => Ok(encoding.encode(text).0.into_owned()),
+                Err(_) => Ok(bytes),
+            },
+            _ => Ok(bytes),
         }
     }
 
-    pub fn len(&self) -> Option<usize> {
+    /// The number of bytes `to_bytes` produces for the same content type,
+    /// or None if the payload cannot be converted to bytes.
+    pub fn len(&self, content_type: Option<&str>) -> Option<usize> {
         match &self {
-            Payload::Json(_) => None,
+            Payload::Json(_) => self.to_bytes(content_type).ok().map(|b| b.len()),
+            Payload::Raw(_) if content_type.is_some_and(|ct| charset(ct) != Charset::Utf8) => {
+                self.to_bytes(content_type).ok().map(|b| b.len())
+            }
             Payload::Raw(s) => Some(s.len()),
-            Payload::Error(e) => Some(e.len()),
+            Payload::Error(..) => None,
         }
     }
 
+    /// The content type that best matches an `Accept` header among those
+    /// `to_bytes` can produce for this payload, or None if none is
+    /// acceptable. Only JSON payloads can be converted, and only arrays
+    /// of records to CSV.
+    pub fn negotiate_content_type(&self, accept: &str) -> Option<&'static str> {
+        let candidates: &[&'static str] = match self {
+            Payload::Json(Json::Array(rows))
+                if rows.iter().all(|row| row.is_object() || row.is_array()) =>
+            {
+                &[JSON_CONTENT_TYPE, CSV_CONTENT_TYPES[0]]
+            }
+            Payload::Json(Json::Object(_)) => &[JSON_CONTENT_TYPE, URLENCODED_CONTENT_TYPE],
+            Payload::Json(_) => &[JSON_CONTENT_TYPE],
+            _ => return None,
+        };
+
+        let ranges = quality_list(accept);
+
+        // A candidate takes the quality of the most specific range that
+        // matches it, so that `application/json;q=0` excludes JSON even
+        // with `*/*` in the list, and the position of that range.
+        let quality = |candidate: &str| {
+            let mut best: Option<(u8, usize, f32)> = None;
+            for (pos, (range, q)) in ranges.iter().enumerate() {
+                let specificity = if range.eq_ignore_ascii_case(candidate) {
+                    2
+                } else if range
+                    .strip_suffix("/*")
+                    .and_then(|main| candidate.strip_prefix(main))
+                    .is_some_and(|sub| sub.starts_with('/'))
+                {
+                    1
+                } else if *range == "*/*" {
+                    0
+                } else {
+                    continue;
+                };
+                if best.is_none_or(|(s, _, _)| specificity > s) {
+                    best = Some((specificity, pos, *q));
+                }
+            }
+            best.map(|(_, pos, q)| (pos, q))
+        };
+
+        // the highest quality wins, then the client order of the ranges,
+        // then the order of the candidates
+        candidates
+            .iter()
+            .filter_map(|&candidate| quality(candidate).map(|(pos, q)| (candidate, pos, q)))
+            .filter(|(_, _, q)| *q > 0.0)
+            .min_by(|a, b| b.2.total_cmp(&a.2).then(a.1.cmp(&b.1)))
+            .map(|(candidate, _, _)| candidate)
+    }
+
     pub fn to_pwm_headers(&self) -> Vec<(&str, &str)> {
         match &self {
             Payload::Json(value) => {
@@ -149,7 +392,7 @@ impl Payload {
             Payload::Raw(s) => form_urlencoded::byte_serialize(s)
                 .collect::<Vec<_>>()
                 .join(""),
-            Payload::Error(err) => {
+            Payload::Error(_, err) => {
                 // FIXME what is the best behavior here?
                 log::debug!("attempting to produce query from an error value: {err}");
                 "".into()
@@ -171,6 +414,28 @@ impl Payload {
     pub fn json_null() -> Self {
         Self::Json(Json::Null)
     }
+
+    pub fn from_json_value(value: Json) -> Self {
+        Self::Json(value)
+    }
+}
+
+impl From<Json> for Payload {
+    fn from(value: Json) -> Self {
+        Self::from_json_value(value)
+    }
+}
+
+impl From<&str> for Payload {
+    fn from(text: &str) -> Self {
+        Self::Raw(text.into())
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Raw(bytes)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -207,6 +472,268 @@ pub fn to_pwm_headers(payload: Option<&Payload>) -> Vec<(&str, &str)> {
     payload.map_or_else(Vec::new, |p| p.to_pwm_headers())
 }
 
+/// Split a header value carrying a list, such as `Accept` or `Via`, into its
+/// items. Whitespace around items is trimmed and empty items are skipped.
+/// Delimiters inside double-quoted strings do not split.
+pub fn split_header_value(value: &str, delimiter: char) -> Vec<String> {
+    let mut items = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => {
+                items.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    items.push(&value[start..]);
+
+    items
+        .into_iter()
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Case-insensitive glob matching of a header name, in which `*` matches
+/// any sequence of characters and `?` matches any single character.
+pub fn header_name_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` seen, and of the name when it was seen
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the last `*` match one more character
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Keep the headers whose names match any of the `include` patterns
+/// (or all of them, if no patterns are given) and none of the `exclude`
+/// patterns. Names are kept as they are, and so are their values.
+pub fn filter_headers(
+    payload: &Payload,
+    include: &[String],
+    exclude: &[String],
+) -> Result<Payload, String> {
+    let Payload::Json(Json::Object(map)) = payload else {
+        return Err("headers must be a JSON object".into());
+    };
+
+    let matches_any =
+        |patterns: &[String], name: &str| patterns.iter().any(|p| header_name_matches(p, name));
+
+    let filtered = map
+        .iter()
+        .filter(|(name, _)| include.is_empty() || matches_any(include, name))
+        .filter(|(name, _)| !matches_any(exclude, name))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    Ok(Payload::Json(Json::Object(filtered)))
+}
+
+/// The reverse of `split_header_value`: join items into a single header
+/// value, separating them with the delimiter followed by a space.
+pub fn join_header_value<S: AsRef<str>>(items: &[S], delimiter: char) -> String {
+    let separator = format!("{delimiter} ");
+    items
+        .iter()
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(&separator)
+}
+
+/// Parse all `Cookie` headers from a headers vector into a map
+/// from cookie names to their values. If a cookie name is repeated,
+/// the first occurrence wins.
+pub fn from_pwm_cookies(vec: &[(String, String)]) -> Payload {
+    let mut map = serde_json::Map::new();
+    for (k, v) in vec {
+        if !k.eq_ignore_ascii_case("cookie") {
+            continue;
+        }
+        for pair in v.split(';') {
+            let pair = pair.trim();
+            if pair.is_empty() {
+                continue;
+            }
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = value.trim().trim_matches('"');
+            map.entry(name.trim())
+                .or_insert_with(|| Json::String(value.to_string()));
+        }
+    }
+
+    Payload::Json(Json::Object(map))
+}
+
+/// Describe the request line, from the pseudo-headers of a headers vector,
+/// as an object with `method`, `path`, `scheme`, `host` and `query_string`.
+/// The `path` does not include the query string. Missing values are null.
+pub fn from_pwm_request_line(vec: &[(String, String)]) -> Payload {
+    let get = |name: &str| {
+        vec.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    };
+    let string_or_null = |v: Option<&str>| v.map_or(Json::Null, |s| Json::String(s.into()));
+
+    let full_path = get(":path");
+    let (path, query_string) = match full_path.map(|p| p.split_once('?').unwrap_or((p, ""))) {
+        Some((path, query)) => (Some(path), Some(query)),
+        None => (None, None),
+    };
+
+    Payload::Json(serde_json::json!({
+        "method": string_or_null(get(":method")),
+        "path": string_or_null(path),
+        "scheme": string_or_null(get(":scheme")),
+        "host": string_or_null(get(":authority").or_else(|| get("host"))),
+        "query_string": string_or_null(query_string),
+    }))
+}
+
+/// Build the TLS information of the client connection from the proxy's
+/// `ngx.ssl_*` variables, which are unset or empty for plain connections.
+pub fn from_pwm_tls(get: impl Fn(&str) -> Option<String>) -> Payload {
+    let get = |name: &str| get(name).filter(|v| !v.is_empty());
+    let string_or_null = |v: Option<String>| v.map_or(Json::Null, Json::String);
+
+    // `ssl_client_verify` is one of `SUCCESS`, `FAILED:reason` or `NONE`
+    let verified = get("ssl_client_verify").map_or(Json::Null, |v| Json::Bool(v == "SUCCESS"));
+
+    Payload::Json(serde_json::json!({
+        "sni": string_or_null(get("ssl_server_name")),
+        "verified": verified,
+        "subject": string_or_null(get("ssl_client_s_dn")),
+        "issuer": string_or_null(get("ssl_client_i_dn")),
+    }))
+}
+
+/// Characters percent-encoded in cookie values: those that are not
+/// allowed by RFC 6265, which include `;`, `,` and whitespace.
+const COOKIE_VALUE: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b',').add(b';').add(b'\\');
+
+/// Whether a cookie name is an RFC 7230 token.
+fn is_cookie_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b))
+}
+
+/// Whether an attribute value cannot end the attribute early or
+/// start another one. Dates of `Expires` contain commas and spaces.
+fn is_cookie_attribute(attr: &str, value: &str) -> bool {
+    value.chars().all(|c| {
+        !c.is_control() && c != ';' && (attr == "Expires" || !(c == ',' || c.is_whitespace()))
+    })
+}
+
+fn cookie_to_set_cookie(entry: &Json) -> Result<String, String> {
+    let cookie = match entry {
+        // accept preformatted values as well
+        Json::String(s) if s.chars().any(char::is_control) => {
+            return Err("preformatted cookie contains control characters".into())
+        }
+        Json::String(s) => return Ok(s.clone()),
+        Json::Object(cookie) => cookie,
+        _ => return Err(format!("cookie must be an object or a string: {entry}")),
+    };
+
+    let name = match cookie.get("name") {
+        Some(Json::String(name)) if is_cookie_name(name) => name,
+        Some(name) => return Err(format!("invalid cookie name: {name}")),
+        None => return Err("cookie has no name".into()),
+    };
+    let value = match cookie.get("value") {
+        Some(Json::String(s)) => s.clone(),
+        Some(Json::Null) | None => String::new(),
+        Some(v @ (Json::Number(_) | Json::Bool(_))) => v.to_string(),
+        Some(v) => return Err(format!("invalid value for cookie {name}: {v}")),
+    };
+
+    let mut out = format!("{name}={}", utf8_percent_encode(&value, COOKIE_VALUE));
+    for (key, attr) in [
+        ("path", "Path"),
+        ("domain", "Domain"),
+        ("expires", "Expires"),
+        ("max_age", "Max-Age"),
+        ("same_site", "SameSite"),
+    ] {
+        let value = match cookie.get(key) {
+            Some(Json::String(s)) => s.clone(),
+            Some(Json::Number(n)) => n.to_string(),
+            _ => continue,
+        };
+        if !is_cookie_attribute(attr, &value) {
+            return Err(format!("invalid {attr} for cookie {name}: {value:?}"));
+        }
+        out += &format!("; {attr}={value}");
+    }
+    for (key, attr) in [("secure", "Secure"), ("http_only", "HttpOnly")] {
+        if let Some(Json::Bool(true)) = cookie.get(key) {
+            out += &format!("; {attr}");
+        }
+    }
+
+    Ok(out)
+}
+
+/// Serialize an array of cookie objects into `Set-Cookie` header values.
+/// Each object needs a `name` and may have a `value` and the attributes
+/// `path`, `domain`, `expires`, `max_age`, `same_site`, `secure`
+/// and `http_only`. String entries are used verbatim. Values are
+/// percent-encoded, and invalid entries are dropped with a warning.
+pub fn to_pwm_set_cookies(payload: Option<&Payload>) -> Vec<String> {
+    let convert = |entry: &Json| match cookie_to_set_cookie(entry) {
+        Ok(cookie) => Some(cookie),
+        Err(e) => {
+            log::warn!("dropping Set-Cookie entry: {e}");
+            None
+        }
+    };
+    match payload {
+        Some(Payload::Json(Json::Array(entries))) => entries.iter().filter_map(convert).collect(),
+        Some(Payload::Json(entry @ Json::Object(_))) => convert(entry).into_iter().collect(),
+        Some(_) => {
+            log::debug!("NYI: converting payload into Set-Cookie values");
+            vec![]
+        }
+        None => vec![],
+    }
+}
+
 /// To use this result in proxy-wasm calls as an Option<&[u8]>, use:
 /// `data::to_pwm_body(p).as_deref()`.
 pub fn to_pwm_body(payload: Option<&Payload>) -> Result<Option<Box<[u8]>>, String> {
@@ -249,6 +776,146 @@ pub fn urlencoded_bytes_to_map(input: &[u8]) -> serde_json::Map<String, serde_js
     map
 }
 
+/// Parse CSV data as described in RFC 4180, with fields optionally enclosed
+/// in double quotes, which may then contain delimiters, newlines and escaped
+/// (doubled) quotes. With `header`, the first record gives the keys and each
+/// other record becomes an object; otherwise each record is an array.
+pub fn csv_bytes_to_json(input: &[u8], delimiter: char, header: bool) -> Result<Json, String> {
+    let input = std::str::from_utf8(input).map_err(|e| format!("csv: {e}"))?;
+
+    let mut records: Vec<Vec<String>> = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            _ if quoted => field.push(c),
+            '"' if field.is_empty() => quoted = true,
+            '\r' if chars.peek() == Some(&'\n') => {}
+            // skip blank lines
+            '\n' if record.is_empty() && field.is_empty() => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c if c == delimiter => record.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("csv: unterminated quoted field".into());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    if !header {
+        return Ok(Json::Array(
+            records
+                .into_iter()
+                .map(|r| Json::Array(r.into_iter().map(Json::String).collect()))
+                .collect(),
+        ));
+    }
+
+    let mut records = records.into_iter();
+    let keys = records.next().unwrap_or_default();
+    let mut rows = vec![];
+    for (i, record) in records.enumerate() {
+        if record.len() != keys.len() {
+            return Err(format!(
+                "csv: record {} has {} fields, expected {}",
+                i + 2,
+                record.len(),
+                keys.len()
+            ));
+        }
+        let row = keys
+            .iter()
+            .cloned()
+            .zip(record.into_iter().map(Json::String));
+        rows.push(Json::Object(row.collect()));
+    }
+
+    Ok(Json::Array(rows))
+}
+
+fn csv_field(value: &Json, delimiter: char) -> String {
+    let field = match value {
+        Json::String(s) => s.clone(),
+        Json::Null => String::new(),
+        v => v.to_string(),
+    };
+
+    if field.contains([delimiter, '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// The reverse of `csv_bytes_to_json`: serialize an array of objects or of
+/// arrays as CSV records. With `header`, a first record lists the keys
+/// of the objects, in the order in which they are first seen.
+pub fn json_to_csv_bytes(value: &Json, delimiter: char, header: bool) -> Result<Vec<u8>, String> {
+    let Json::Array(rows) = value else {
+        return Err("csv: expected an array of records".into());
+    };
+
+    let mut keys: Vec<&String> = vec![];
+    for row in rows {
+        if let Json::Object(map) = row {
+            for key in map.keys() {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+    }
+
+    let separator = delimiter.to_string();
+    let mut out = String::new();
+    let mut write_record = |fields: Vec<String>| {
+        out += &fields.join(&separator);
+        out += "\r\n";
+    };
+
+    if header && !keys.is_empty() {
+        write_record(
+            keys.iter()
+                .map(|k| csv_field(&Json::from(k.as_str()), delimiter))
+                .collect(),
+        );
+    }
+
+    for row in rows {
+        match row {
+            Json::Object(map) => write_record(
+                keys.iter()
+                    .map(|k| csv_field(map.get(*k).unwrap_or(&Json::Null), delimiter))
+                    .collect(),
+            ),
+            Json::Array(values) => {
+                write_record(values.iter().map(|v| csv_field(v, delimiter)).collect())
+            }
+            _ => return Err("csv: expected records to be objects or arrays".into()),
+        }
+    }
+
+    Ok(out.into_bytes())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -268,4 +935,526 @@ mod test {
         assert_eq!(raw, payload_to_string(None));
         assert_eq!(encoded, payload_to_string(Some(JSON_CONTENT_TYPE)));
     }
+
+    #[test]
+    fn len_matches_to_bytes() {
+        let payloads = [
+            Payload::Raw(b"raw bytes".to_vec()),
+            Payload::Json(Json::String("my string".into())),
+            Payload::Json(Json::String("quote \" and ünicode".into())),
+            Payload::Json(serde_json::json!({ "a": [1, 2.5, null], "b": "c" })),
+            Payload::Json(Json::Null),
+        ];
+
+        for payload in &payloads {
+            for ct in [None, Some(JSON_CONTENT_TYPE), Some("text/plain")] {
+                let bytes = payload.to_bytes(ct).unwrap();
+                assert_eq!(Some(bytes.len()), payload.len(ct), "{payload:?} {ct:?}");
+            }
+        }
+
+        assert_eq!(
+            None,
+            Payload::Error(ErrorKind::ParseError, "error".into()).len(None)
+        );
+    }
+
+    #[test]
+    fn payload_conversions() {
+        let json = serde_json::json!({ "a": 1 });
+        assert_eq!(Payload::Json(json.clone()), Payload::from(json.clone()));
+        assert_eq!(Payload::Json(json.clone()), Payload::from_json_value(json));
+        assert_eq!(Payload::Raw(b"text".to_vec()), Payload::from("text"));
+        assert_eq!(Payload::Raw(vec![0xff, 0]), Payload::from(vec![0xff, 0]));
+    }
+
+    #[test]
+    fn error_kind_serialization() {
+        assert_eq!(
+            serde_json::json!("upstream_error"),
+            serde_json::to_value(ErrorKind::UpstreamError).unwrap()
+        );
+
+        let payload = Payload::Error(ErrorKind::Timeout, "too slow".into());
+        let bytes = serde_json::to_vec(&payload).unwrap();
+        assert_eq!(payload, serde_json::from_slice(&bytes).unwrap());
+        assert_eq!(Err("too slow".to_string()), payload.to_bytes(None));
+    }
+
+    #[test]
+    fn split_header_value_lists() {
+        assert_eq!(
+            vec!["text/html", "application/json;q=0.9", "*/*;q=0.8"],
+            split_header_value("text/html, application/json;q=0.9 ,*/*;q=0.8", ',')
+        );
+        assert_eq!(
+            vec![r#"for="[::1]:80, x""#, "for=192.0.2.1"],
+            split_header_value(r#"for="[::1]:80, x", for=192.0.2.1"#, ',')
+        );
+        assert_eq!(
+            vec![r#"a="x\", y""#, "b"],
+            split_header_value(r#"a="x\", y",, b"#, ',')
+        );
+        assert!(split_header_value("  ", ',').is_empty());
+    }
+
+    #[test]
+    fn join_header_value_round_trip() {
+        let value = "1.1 proxy-a, 1.0 proxy-b";
+        let items = split_header_value(value, ',');
+        assert_eq!(value, join_header_value(&items, ','));
+        assert_eq!("a; b", join_header_value(&["a", "b"], ';'));
+    }
+
+    #[test]
+    fn from_pwm_cookies_multiple_headers() {
+        let headers = vec![
+            ("Cookie".to_string(), "a=1; b=\"two\"".to_string()),
+            ("x-other".to_string(), "c=3".to_string()),
+            ("cookie".to_string(), "a=ignored;c=3;flag".to_string()),
+        ];
+
+        assert_eq!(
+            Payload::Json(serde_json::json!({
+                "a": "1",
+                "b": "two",
+                "c": "3",
+                "flag": "",
+            })),
+            from_pwm_cookies(&headers)
+        );
+    }
+
+    #[test]
+    fn from_pwm_request_line_pseudo_headers() {
+        let headers = |list: &[(&str, &str)]| -> Vec<(String, String)> {
+            list.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            Payload::Json(serde_json::json!({
+                "method": "POST",
+                "path": "/items/1",
+                "scheme": "https",
+                "host": "example.com:8443",
+                "query_string": "a=1&b",
+            })),
+            from_pwm_request_line(&headers(&[
+                (":method", "POST"),
+                (":path", "/items/1?a=1&b"),
+                (":scheme", "https"),
+                (":authority", "example.com:8443"),
+                ("host", "other.example.com"),
+            ]))
+        );
+
+        assert_eq!(
+            Payload::Json(serde_json::json!({
+                "method": null,
+                "path": "/",
+                "scheme": null,
+                "host": "example.com",
+                "query_string": "",
+            })),
+            from_pwm_request_line(&headers(&[(":path", "/"), ("Host", "example.com")]))
+        );
+    }
+
+    #[test]
+    fn from_pwm_tls_variables() {
+        let vars = BTreeMap::from([
+            ("ssl_server_name", "api.example.com"),
+            ("ssl_client_verify", "SUCCESS"),
+            ("ssl_client_s_dn", "CN=client"),
+            ("ssl_client_i_dn", "CN=ca"),
+        ]);
+        assert_eq!(
+            Payload::Json(serde_json::json!({
+                "sni": "api.example.com",
+                "verified": true,
+                "subject": "CN=client",
+                "issuer": "CN=ca",
+            })),
+            from_pwm_tls(|name| vars.get(name).map(|v| v.to_string()))
+        );
+
+        let vars = BTreeMap::from([
+            ("ssl_server_name", "api.example.com"),
+            ("ssl_client_verify", "NONE"),
+            ("ssl_client_s_dn", ""),
+        ]);
+        assert_eq!(
+            Payload::Json(serde_json::json!({
+                "sni": "api.example.com",
+                "verified": false,
+                "subject": null,
+                "issuer": null,
+            })),
+            from_pwm_tls(|name| vars.get(name).map(|v| v.to_string()))
+        );
+
+        assert_eq!(
+            Payload::Json(serde_json::json!({
+                "sni": null,
+                "verified": null,
+                "subject": null,
+                "issuer": null,
+            })),
+            from_pwm_tls(|_| None)
+        );
+    }
+
+    #[test]
+    fn to_pwm_set_cookies_array() {
+        let payload = Payload::Json(serde_json::json!([
+            { "name": "session", "value": "abc", "path": "/", "max_age": 3600,
+              "secure": true, "http_only": true, "same_site": "Lax" },
+            { "name": "theme", "value": "dark" },
+            "raw=value; Path=/x",
+            { "value": "no name" },
+        ]));
+
+        assert_eq!(
+            vec![
+                "session=abc; Path=/; Max-Age=3600; SameSite=Lax; Secure; HttpOnly",
+                "theme=dark",
+                "raw=value; Path=/x",
+            ],
+            to_pwm_set_cookies(Some(&payload))
+        );
+    }
+
+    #[test]
+    fn to_pwm_set_cookies_invalid() {
+        let payload = Payload::Json(serde_json::json!([
+            // values cannot inject attributes
+            { "name": "user", "value": "x; Domain=evil.example" },
+            { "name": "n", "value": 42 },
+            { "name": "when", "value": "", "expires": "Wed, 21 Oct 2026 07:28:00 GMT" },
+            { "name": "bad name", "value": "x" },
+            { "name": "obj", "value": { "a": 1 } },
+            { "name": "path", "value": "x", "path": "/; Domain=evil.example" },
+            { "name": "crlf", "value": "x", "domain": "a.example\r\nX-Evil: 1" },
+            "raw=value\r\nX-Evil: 1",
+            [1, 2],
+        ]));
+
+        assert_eq!(
+            vec![
+                "user=x%3B%20Domain=evil.example",
+                "n=42",
+                "when=; Expires=Wed, 21 Oct 2026 07:28:00 GMT",
+            ],
+            to_pwm_set_cookies(Some(&payload))
+        );
+    }
+
+    #[test]
+    fn csv_from_bytes() {
+        let csv = "id,name,notes\r\n1,\"Doe, Jane\",\"says \"\"hi\"\"\"\n2,Joe,\"two\nlines\"\n";
+
+        assert_eq!(
+            Some(Payload::Json(serde_json::json!([
+                { "id": "1", "name": "Doe, Jane", "notes": "says \"hi\"" },
+                { "id": "2", "name": "Joe", "notes": "two\nlines" },
+            ]))),
+            Payload::from_bytes(csv.into(), Some("text/csv; charset=utf-8"))
+        );
+    }
+
+    #[test]
+    fn number_precision() {
+        let body = br#"{"amount":0.10000000000000000001,"id":18446744073709551617}"#;
+        let payload = Payload::from_bytes(body.to_vec(), Some(JSON_CONTENT_TYPE)).unwrap();
+        assert_eq!(Ok(body.to_vec()), payload.to_bytes(None));
+    }
+
+    #[test]
+    fn empty_from_bytes() {
+        assert_eq!(
+            Some(Payload::json_null()),
+            Payload::from_bytes(vec![], Some("application/json"))
+        );
+        assert_eq!(
+            Some(Payload::json_null()),
+            Payload::from_bytes(vec![], Some("application/json; charset=utf-8"))
+        );
+        assert_eq!(
+            Some(Payload::Raw(vec![])),
+            Payload::from_bytes(vec![], None)
+        );
+        assert_eq!(
+            Some(Payload::Raw(vec![])),
+            Payload::from_bytes(vec![], Some("text/plain"))
+        );
+        assert!(matches!(
+            Payload::from_bytes(b" ".to_vec(), Some("application/json")),
+            Some(Payload::Error(ErrorKind::ParseError, _))
+        ));
+    }
+
+    #[test]
+    fn sniff_json() {
+        assert_eq!(
+            Payload::Json(serde_json::json!({ "a": [1] })),
+            Payload::sniff(b" {\"a\": [1]}\n".to_vec())
+        );
+        assert_eq!(
+            Payload::Json(serde_json::json!([])),
+            Payload::sniff(b"[]".to_vec())
+        );
+
+        // only objects and arrays are taken as JSON
+        for body in [&b"{\"a\": "[..], b"42", b"\"hi\"", b"true", b"hello", b""] {
+            assert_eq!(Payload::Raw(body.to_vec()), Payload::sniff(body.to_vec()));
+        }
+    }
+
+    #[test]
+    fn charset_from_bytes() {
+        let latin1 = b"{\"name\": \"Jos\xe9\"}".to_vec();
+        assert_eq!(
+            Some(Payload::Json(serde_json::json!({ "name": "José" }))),
+            Payload::from_bytes(latin1, Some("application/json; charset=ISO-8859-1"))
+        );
+
+        let html = Payload::from_bytes(
+            b"<p>\xa1Hola!</p>".to_vec(),
+            Some("text/html; Charset=\"windows-1252\""),
+        )
+        .unwrap();
+        assert_eq!(Ok(serde_json::json!("<p>¡Hola!</p>")), html.to_json());
+
+        assert_eq!(
+            Some(Payload::Raw(b"{\"a\": 1}".to_vec())),
+            Payload::from_bytes(
+                b"{\"a\": 1}".to_vec(),
+                Some("application/json; charset=nope")
+            )
+        );
+    }
+
+    #[test]
+    fn charset_to_bytes() {
+        let ct = Some("text/plain; charset=iso-8859-1");
+        let text = Payload::from_bytes(b"caf\xe9".to_vec(), ct).unwrap();
+        assert_eq!(Payload::Raw("café".into()), text);
+        assert_eq!(Ok(b"caf\xe9".to_vec()), text.to_bytes(ct));
+        assert_eq!(Some(4), text.len(ct));
+
+        // already in the target charset
+        let raw = Payload::Raw(b"caf\xe9".to_vec());
+        assert_eq!(Ok(b"caf\xe9".to_vec()), raw.to_bytes(ct));
+
+        let json = Payload::Json(serde_json::json!({ "a": "é" }));
+        assert_eq!(
+            Ok(b"{\"a\":\"\xe9\"}".to_vec()),
+            json.to_bytes(Some("application/json; charset=latin1"))
+        );
+        assert_eq!(
+            Ok("{\"a\":\"é\"}".as_bytes().to_vec()),
+            json.to_bytes(Some(JSON_CONTENT_TYPE))
+        );
+    }
+
+    #[test]
+    fn csv_without_header() {
+        assert_eq!(
+            Ok(serde_json::json!([["a", "b"], ["c", ""]])),
+            csv_bytes_to_json(b"a;b\n\nc;", ';', false)
+        );
+    }
+
+    #[test]
+    fn csv_invalid() {
+        assert!(csv_bytes_to_json(b"a,b\n1,2,3\n", ',', true).is_err());
+        assert!(csv_bytes_to_json(b"a,b\n\"1,2\n", ',', true).is_err());
+    }
+
+    #[test]
+    fn csv_to_bytes() {
+        let payload = Payload::Json(serde_json::json!([
+            { "id": 1, "name": "Doe, Jane" },
+            { "id": 2, "name": "Joe", "notes": "two\nlines" },
+        ]));
+
+        let bytes = payload.to_bytes(Some("application/csv")).unwrap();
+        assert_eq!(
+            "id,name,notes\r\n1,\"Doe, Jane\",\r\n2,Joe,\"two\nlines\"\r\n",
+            String::from_utf8(bytes.clone()).unwrap()
+        );
+
+        assert_eq!(
+            Ok(serde_json::json!([
+                { "id": "1", "name": "Doe, Jane", "notes": "" },
+                { "id": "2", "name": "Joe", "notes": "two\nlines" },
+            ])),
+            csv_bytes_to_json(&bytes, ',', true)
+        );
+    }
+
+    #[test]
+    fn urlencoded_repeated_keys() {
+        // the query ports and urlencoded bodies keep the last value
+        assert_eq!(
+            serde_json::json!({ "a": "3", "b": "x y" }),
+            Json::Object(urlencoded_bytes_to_map(b"a=1&b=x+y&a=2&a=3"))
+        );
+    }
+
+    #[test]
+    fn urlencoded_to_bytes() {
+        let payload = Payload::Json(serde_json::json!({ "a": "b c", "n": [1, 2] }));
+        let bytes = payload.to_bytes(Some(URLENCODED_CONTENT_TYPE)).unwrap();
+        assert_eq!("a=b+c&n=1&n=2", String::from_utf8(bytes).unwrap());
+    }
+
+    #[test]
+    fn negotiate_content_type() {
+        let object = Payload::Json(serde_json::json!({ "a": 1 }));
+        let array = Payload::Json(serde_json::json!([{ "a": 1 }]));
+
+        assert_eq!(
+            Some(URLENCODED_CONTENT_TYPE),
+            object.negotiate_content_type("application/x-www-form-urlencoded")
+        );
+        assert_eq!(
+            Some(JSON_CONTENT_TYPE),
+            object.negotiate_content_type("text/csv, application/*;q=0.5")
+        );
+        assert_eq!(
+            Some("text/csv"),
+            array.negotiate_content_type("application/json;q=0.8, text/*")
+        );
+        assert_eq!(Some(JSON_CONTENT_TYPE), array.negotiate_content_type("*/*"));
+        assert_eq!(
+            None,
+            array.negotiate_content_type("text/html, text/csv;q=0")
+        );
+        assert_eq!(
+            None,
+            Payload::Raw(b"text".to_vec()).negotiate_content_type("*/*")
+        );
+    }
+
+    #[test]
+    fn negotiate_content_type_csv_records_only() {
+        let numbers = Payload::Json(serde_json::json!([1, 2]));
+        assert_eq!(None, numbers.negotiate_content_type("text/csv"));
+        assert_eq!(
+            Some(JSON_CONTENT_TYPE),
+            numbers.negotiate_content_type("text/csv, application/json;q=0.5")
+        );
+
+        let rows = Payload::Json(serde_json::json!([["a", 1], ["b", 2]]));
+        assert_eq!(Some("text/csv"), rows.negotiate_content_type("text/csv"));
+    }
+
+    #[test]
+    fn negotiate_content_type_exclusions() {
+        let object = Payload::Json(serde_json::json!({ "a": 1 }));
+        let array = Payload::Json(serde_json::json!([{ "a": 1 }]));
+        let number = Payload::Json(serde_json::json!(1));
+
+        assert_eq!(
+            Some(URLENCODED_CONTENT_TYPE),
+            object.negotiate_content_type("application/json;q=0, */*")
+        );
+        assert_eq!(
+            Some("text/csv"),
+            array.negotiate_content_type("*/*, application/json;q=0")
+        );
+        assert_eq!(
+            None,
+            number.negotiate_content_type("application/json;q=0, */*")
+        );
+        assert_eq!(
+            Some("text/csv"),
+            array.negotiate_content_type("application/*;q=0, */*")
+        );
+    }
+
+    #[test]
+    fn compress_accept_encoding() {
+        assert!(Compress::Auto.applies(Some("gzip")));
+        assert!(Compress::Auto.applies(Some("br;q=1.0, GZIP;q=0.5")));
+        assert!(Compress::Auto.applies(Some("deflate, *")));
+        assert!(!Compress::Auto.applies(Some("gzip;q=0, *")));
+        assert!(!Compress::Auto.applies(Some("br, deflate")));
+        assert!(!Compress::Auto.applies(None));
+
+        assert!(Compress::Gzip.applies(None));
+        assert!(!Compress::Never.applies(Some("gzip")));
+        assert_eq!(None, Compress::parse("brotli"));
+    }
+
+    #[test]
+    fn gzip_body() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let body = br#"{"items":[1,2,3]}"#.repeat(100);
+        let compressed = gzip(&body).unwrap();
+        assert!(compressed.len() < body.len());
+
+        let mut decoded = vec![];
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(body, decoded);
+    }
+
+    #[test]
+    fn header_name_globs() {
+        assert!(header_name_matches("x-forwarded-*", "X-Forwarded-For"));
+        assert!(header_name_matches("X-FORWARDED-*", "x-forwarded-proto"));
+        assert!(header_name_matches("*-id", "x-request-id"));
+        assert!(header_name_matches("x-*-*-id", "x-kong-request-id"));
+        assert!(header_name_matches("accept?encoding", "accept-encoding"));
+        assert!(header_name_matches("*", "anything"));
+        assert!(header_name_matches("host", "Host"));
+
+        assert!(!header_name_matches("host", "hostname"));
+        assert!(!header_name_matches("x-forwarded-*", "x-forward"));
+        assert!(!header_name_matches("?", ""));
+    }
+
+    #[test]
+    fn filter_headers_include_exclude() {
+        let headers = Payload::Json(serde_json::json!({
+            "host": "example.com",
+            "x-forwarded-for": ["10.0.0.1", "10.0.0.2"],
+            "x-forwarded-proto": "https",
+            "x-forwarded-host": "example.com",
+            "authorization": "secret",
+        }));
+        let patterns =
+            |list: &[&str]| -> Vec<String> { list.iter().map(|s| s.to_string()).collect() };
+
+        assert_eq!(
+            Ok(Payload::Json(serde_json::json!({
+                "x-forwarded-for": ["10.0.0.1", "10.0.0.2"],
+                "x-forwarded-proto": "https",
+            }))),
+            filter_headers(
+                &headers,
+                &patterns(&["X-Forwarded-*"]),
+                &patterns(&["*-host"])
+            )
+        );
+
+        assert_eq!(
+            Ok(Payload::Json(serde_json::json!({
+                "host": "example.com",
+                "x-forwarded-for": ["10.0.0.1", "10.0.0.2"],
+                "x-forwarded-proto": "https",
+                "x-forwarded-host": "example.com",
+            }))),
+            filter_headers(&headers, &[], &patterns(&["authorization"]))
+        );
+
+        assert!(filter_headers(&Payload::Raw(b"host: x".to_vec()), &[], &[]).is_err());
+    }
 }